@@ -0,0 +1,119 @@
+use std::{num::NonZeroUsize, path::PathBuf, time::Instant};
+
+use anyhow::{bail, Context, Result};
+use bpaf::*;
+use log::{error, info};
+
+use crate::{
+    config, exit, logging, plan,
+    progress::{self, ProgressStyle, Stages},
+    render_plan, start_thread_pool, Options,
+};
+
+/// Renders a list of jobs from a file, one after another
+#[derive(Debug, Clone, Bpaf)]
+pub struct BatchOptions {
+    /// Threads to render every job with, and to give FFMpeg, instead of one for each core
+    #[bpaf(long, argument("N"))]
+    threads: Option<NonZeroUsize>,
+    /// Render in the background at a lower priority, leaving a core free unless --threads is given
+    nice: bool,
+    /// Only print warnings and errors, without progress bars or FFMpeg's output
+    #[bpaf(short, long)]
+    quiet: bool,
+    /// Print more about what's being done, or everything with -vv
+    #[bpaf(short('v'), long("verbose"), req_flag(()), count)]
+    verbose: usize,
+    /// How to show progress: a bar for each stage, one overall bar, json for a line of JSON for
+    /// every frame, or none. Bars are drawn when stderr is a terminal, unless --quiet is given.
+    #[bpaf(long, argument("STYLE"))]
+    progress: Option<ProgressStyle>,
+    /// Stages to show progress for, like render,encode, out of render, write, palettes, encode,
+    /// first-pass, second-pass, and checksums
+    #[bpaf(long, argument("STAGES"))]
+    progress_stages: Option<Stages>,
+    /// Keep rendering the rest of the jobs when one fails
+    keep_going: bool,
+    /// File listing the jobs, each under a [[job]] header with its options and outputs
+    #[bpaf(positional("JOBS"))]
+    jobs: PathBuf,
+}
+
+impl BatchOptions {
+    pub fn run(self) -> Result<()> {
+        logging::set_verbosity(self.quiet, self.verbose);
+        progress::set_style(self.progress, self.progress_stages.clone(), self.quiet);
+        let jobs = self.read_jobs().map_err(exit::usage)?;
+        let threads = start_thread_pool(self.threads, self.nice)?;
+        let started = Instant::now();
+        let count = jobs.len();
+        let mut failed = Vec::new();
+        for (i, options) in jobs.into_iter().enumerate() {
+            let result = plan(Options { threads, ..options })
+                .map_err(exit::usage)
+                .and_then(|plan| {
+                    let outputs: Vec<_> = plan
+                        .filenames()
+                        .iter()
+                        .map(|output| output.display().to_string())
+                        .collect();
+                    info!("Job {} of {count}: {}", i + 1, outputs.join(", "));
+                    render_plan(plan)
+                })
+                .with_context(|| format!("Job {} failed", i + 1));
+            match result {
+                Ok(()) => {}
+                Err(err) if self.keep_going => {
+                    error!("{}", exit::message(&err));
+                    failed.push((i + 1).to_string());
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        info!(
+            "Finished {count} jobs in {:.1}s",
+            started.elapsed().as_secs_f64()
+        );
+        if !failed.is_empty() {
+            bail!(
+                "{} of {count} jobs failed: {}",
+                failed.len(),
+                failed.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the options for every job. They're all checked before any are rendered, so a typo in
+    /// the last one doesn't turn up hours in.
+    fn read_jobs(&self) -> Result<Vec<Options>> {
+        let document = config::read(&self.jobs)?;
+        let mut jobs = Vec::new();
+        for (i, (name, settings)) in document.tables.iter().enumerate() {
+            let options = job_options(name, settings, &document.settings)
+                .with_context(|| format!("Invalid job {} in {}", i + 1, self.jobs.display()))?;
+            jobs.push(options);
+        }
+        if jobs.is_empty() {
+            bail!("{} has no [[job]] tables", self.jobs.display());
+        }
+        Ok(jobs)
+    }
+}
+
+/// The options for a [[job]], over the ones at the top level of the file.
+fn job_options(
+    name: &str,
+    settings: &[(String, config::Value)],
+    shared: &[(String, config::Value)],
+) -> Result<Options> {
+    if name != "job" {
+        bail!("expected a [[job]] table, not [[{name}]]");
+    }
+    let options = config::job_options(settings, shared)?;
+    options.validate()?;
+    if options.outputs.is_empty() {
+        bail!("no outputs to write");
+    }
+    Ok(options)
+}
@@ -0,0 +1,329 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use image::Primitive;
+use palette::{LinSrgba, Srgba};
+
+use crate::{frames::FrameFormat, OutputFormat, Params};
+
+/// A 3x3 matrix, in row major order.
+type Matrix = [[f64; 3]; 3];
+
+/// The D65 white point of every supported color space, as a CIE xy chromaticity.
+const D65: [f64; 2] = [0.3127, 0.3290];
+
+/// The white point of the ICC profile connection space, as XYZ.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// Bradford cone response matrix, for adapting colors between white points.
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+/// The RGB color space that frames are encoded in, and that outputs are tagged with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+}
+
+impl ColorSpace {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Srgb => "sRGB",
+            Self::DisplayP3 => "Display P3",
+            Self::Rec2020 => "Rec. 2020",
+        }
+    }
+
+    /// The red, green, and blue primaries, as CIE xy chromaticities.
+    fn primaries(self) -> [[f64; 2]; 3] {
+        match self {
+            Self::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            Self::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            Self::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+        }
+    }
+
+    /// The transfer function as the parameters g, a, b, c, and d of an ICC parametric curve of
+    /// type 3, which is `(a * x + b)^g` above `d` and `c * x` below it.
+    fn transfer(self) -> [f64; 5] {
+        match self {
+            Self::Srgb | Self::DisplayP3 => [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045],
+            Self::Rec2020 => [1.0 / 0.45, 1.0 / 1.099, 0.099 / 1.099, 1.0 / 4.5, 0.081],
+        }
+    }
+
+    /// FFMpeg's name for the matrix to convert RGB frames to YUV with. Display P3 has no matrix
+    /// of its own, and borrows BT.709's like Apple's encoders do.
+    pub fn ffmpeg_matrix(self) -> &'static str {
+        match self {
+            Self::Srgb | Self::DisplayP3 => "bt709",
+            Self::Rec2020 => "bt2020",
+        }
+    }
+
+    /// Arguments that tag a video stream with the color space.
+    pub fn ffmpeg_args(self) -> [&'static str; 8] {
+        let (primaries, transfer) = match self {
+            Self::Srgb => ("bt709", "iec61966-2-1"),
+            Self::DisplayP3 => ("smpte432", "iec61966-2-1"),
+            Self::Rec2020 => ("bt2020", "bt2020-10"),
+        };
+        [
+            "-color_primaries",
+            primaries,
+            "-color_trc",
+            transfer,
+            "-colorspace",
+            match self {
+                Self::Rec2020 => "bt2020nc",
+                _ => self.ffmpeg_matrix(),
+            },
+            "-color_range",
+            "tv",
+        ]
+    }
+
+    /// An ICC v4 display profile describing the color space, for embedding in images.
+    pub fn icc_profile(self) -> Vec<u8> {
+        let to_d50 = chromatic_adaptation(xy_to_xyz(D65), D50);
+        let colorants = mul(to_d50, rgb_to_xyz(self.primaries(), D65));
+        let column = |i: usize| colorants.map(|row| row[i]);
+        let trc = parametric_curve(self.transfer());
+
+        let tags = [
+            (*b"desc", multi_localized(self.name())),
+            (*b"cprt", multi_localized("No copyright, use freely")),
+            (*b"wtpt", xyz(D50)),
+            (*b"chad", s15_fixed16_array(to_d50.as_flattened())),
+            (*b"rXYZ", xyz(column(0))),
+            (*b"gXYZ", xyz(column(1))),
+            (*b"bXYZ", xyz(column(2))),
+            (*b"rTRC", trc.clone()),
+            (*b"gTRC", trc.clone()),
+            (*b"bTRC", trc),
+        ];
+
+        let mut profile = vec![0; 128];
+        profile[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[20..24].copy_from_slice(b"XYZ ");
+        // A fixed creation date, so the same render always gives the same bytes
+        for (i, part) in [2025u16, 1, 1, 0, 0, 0].into_iter().enumerate() {
+            profile[24 + i * 2..26 + i * 2].copy_from_slice(&part.to_be_bytes());
+        }
+        profile[36..40].copy_from_slice(b"acsp");
+        profile[68..80].copy_from_slice(&xyz(D50)[8..]);
+
+        profile.extend((tags.len() as u32).to_be_bytes());
+        let data_start = profile.len() + 12 * tags.len();
+        let mut data = Vec::new();
+        for (signature, tag) in &tags {
+            profile.extend(signature);
+            profile.extend(((data_start + data.len()) as u32).to_be_bytes());
+            profile.extend((tag.len() as u32).to_be_bytes());
+            data.extend(tag);
+            // Every tag starts on a 4 byte boundary
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        profile.extend(data);
+
+        let size = profile.len() as u32;
+        profile[0..4].copy_from_slice(&size.to_be_bytes());
+        profile
+    }
+
+    /// Precomputes what's needed to encode colors in this color space.
+    pub fn encoder(self) -> ColorEncoder {
+        let from_xyz = inverse(rgb_to_xyz(self.primaries(), D65));
+        ColorEncoder {
+            color_space: self,
+            from_srgb: mul(from_xyz, rgb_to_xyz(ColorSpace::Srgb.primaries(), D65)),
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Self::Srgb),
+            "p3" | "display-p3" => Ok(Self::DisplayP3),
+            "rec2020" | "bt2020" => Ok(Self::Rec2020),
+            _ => Err(format!(
+                "unknown gamut '{s}', expected srgb, p3, or rec2020"
+            )),
+        }
+    }
+}
+
+/// A value in a channel's own range as that channel's type. Casting to an integer channel
+/// truncates, so those are rounded first.
+pub fn to_channel<C: Primitive>(value: f64) -> C {
+    let integer = C::from(0.5).is_some_and(|half| half == C::zero());
+    C::from(if integer { value.round() } else { value }).unwrap_or_else(C::zero)
+}
+
+/// Reads a color written as RRGGBB or RRGGBBAA, with or without a leading #. Colors without alpha
+/// are opaque.
+pub fn parse_hex(s: &str) -> Result<[u8; 4], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let invalid = || format!("invalid color '{s}', expected RRGGBB or RRGGBBAA");
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut rgba = [0xff; 4];
+    for (channel, digits) in rgba.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *channel = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(rgba)
+}
+
+/// Encodes the linear sRGB colors that functions return into a color space.
+pub struct ColorEncoder {
+    color_space: ColorSpace,
+    from_srgb: Matrix,
+}
+
+impl ColorEncoder {
+    /// Encodes a color as nonlinear values, which are 0-1 within the color space's gamut.
+    pub fn encode(&self, color: LinSrgba<f64>) -> Srgba<f64> {
+        if self.color_space == ColorSpace::Srgb {
+            return color.into_encoding();
+        }
+        // Only the values are sRGB's, these are really the color space's own
+        let linear = mul_vec(self.from_srgb, [color.red, color.green, color.blue]);
+        let [g, a, b, c, d] = self.color_space.transfer();
+        let [red, green, blue] = linear.map(|value| {
+            let value = value.clamp(0.0, 1.0);
+            if value >= c * d {
+                (value.powf(1.0 / g) - b) / a
+            } else {
+                value / c
+            }
+        });
+        Srgba::new(red, green, blue, color.alpha)
+    }
+
+    /// Encodes a color as 8-bit values.
+    pub fn encode_u8(&self, color: LinSrgba<f64>) -> Srgba<u8> {
+        match self.color_space {
+            // palette's own 8-bit sRGB encoding rounds a little differently, and is kept so sRGB
+            // outputs stay the same
+            ColorSpace::Srgb => color.into_encoding(),
+            _ => self.encode(color).into_format(),
+        }
+    }
+}
+
+impl Params {
+    /// Checks that `format` can be tagged with --gamut, since an untagged wide gamut image would be
+    /// shown as sRGB with all its colors off.
+    pub fn check_color_space(&self, format: OutputFormat) -> Result<()> {
+        if self.color_space == ColorSpace::Srgb {
+            return Ok(());
+        }
+        let untagged = match format {
+            OutputFormat::Gif => Some("gif outputs"),
+            OutputFormat::Frames | OutputFormat::Zip | OutputFormat::Tar => {
+                match self.frame_format {
+                    FrameFormat::Tiff => Some("tiff frames"),
+                    FrameFormat::Bmp => Some("bmp frames"),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(untagged) = untagged {
+            bail!("{untagged} can't be tagged with a color space, so they can only be sRGB");
+        }
+        Ok(())
+    }
+}
+
+fn xy_to_xyz([x, y]: [f64; 2]) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+/// The matrix taking linear RGB to XYZ, scaled so that white has a luminance of 1.
+fn rgb_to_xyz(primaries: [[f64; 2]; 3], white: [f64; 2]) -> Matrix {
+    let primaries = primaries.map(xy_to_xyz);
+    let unscaled: Matrix = [0, 1, 2].map(|row| primaries.map(|primary| primary[row]));
+    let scale = mul_vec(inverse(unscaled), xy_to_xyz(white));
+    unscaled.map(|row| [0, 1, 2].map(|i| row[i] * scale[i]))
+}
+
+/// The Bradford matrix adapting colors seen under the white `from` to the white `to`.
+fn chromatic_adaptation(from: [f64; 3], to: [f64; 3]) -> Matrix {
+    let from = mul_vec(BRADFORD, from);
+    let to = mul_vec(BRADFORD, to);
+    let scale = [0, 1, 2].map(|i| {
+        let mut row = [0.0; 3];
+        row[i] = to[i] / from[i];
+        row
+    });
+    mul(inverse(BRADFORD), mul(scale, BRADFORD))
+}
+
+fn mul(a: Matrix, b: Matrix) -> Matrix {
+    a.map(|row| [0, 1, 2].map(|j| (0..3).map(|k| row[k] * b[k][j]).sum()))
+}
+
+fn mul_vec(a: Matrix, v: [f64; 3]) -> [f64; 3] {
+    a.map(|row| (0..3).map(|k| row[k] * v[k]).sum())
+}
+
+fn inverse(m: Matrix) -> Matrix {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / determinant))
+}
+
+fn s15_fixed16(value: f64) -> [u8; 4] {
+    ((value * 65536.0).round() as i32).to_be_bytes()
+}
+
+fn s15_fixed16_array(values: &[f64]) -> Vec<u8> {
+    let mut tag = b"sf32\0\0\0\0".to_vec();
+    tag.extend(values.iter().flat_map(|&value| s15_fixed16(value)));
+    tag
+}
+
+fn xyz(color: [f64; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    tag.extend(color.iter().flat_map(|&value| s15_fixed16(value)));
+    tag
+}
+
+fn parametric_curve(parameters: [f64; 5]) -> Vec<u8> {
+    let mut tag = b"para\0\0\0\0".to_vec();
+    // Function type 3, then 2 reserved bytes
+    tag.extend([0, 3, 0, 0]);
+    tag.extend(parameters.iter().flat_map(|&value| s15_fixed16(value)));
+    tag
+}
+
+/// A text tag with a single English string.
+fn multi_localized(text: &str) -> Vec<u8> {
+    let text: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    let mut tag = b"mluc\0\0\0\0".to_vec();
+    // One record of 12 bytes, with the text right after it
+    tag.extend(1u32.to_be_bytes());
+    tag.extend(12u32.to_be_bytes());
+    tag.extend(b"enUS");
+    tag.extend((text.len() as u32).to_be_bytes());
+    tag.extend(28u32.to_be_bytes());
+    tag.extend(text);
+    tag
+}
@@ -0,0 +1,57 @@
+use palette::{LinSrgba, Mix};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{deterministic::snap_color, Params};
+
+/// How many exact colors are worked out along curves longer than this, with the rest interpolated
+/// between them. Neighbouring samples are far closer than an 8-bit step, so only the few positions
+/// right at a jump in a function's colors (like oklab_hue's at the blue cusp) come out differently.
+const COLOR_SAMPLES: u64 = 1 << 20;
+
+/// Colors at evenly spaced points along the curve, shared by every thread.
+struct ColorSamples(Vec<LinSrgba<f64>>);
+
+impl ColorSamples {
+    fn new<F>(color: &F) -> Self
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        // Both ends are sampled, so the last positions still have a color to interpolate towards
+        Self(
+            (0..=COLOR_SAMPLES)
+                .into_par_iter()
+                .map(|k| color(k, COLOR_SAMPLES))
+                .collect(),
+        )
+    }
+
+    fn color(&self, i: u64, size: u64) -> LinSrgba<f64> {
+        let position = i as f64 * COLOR_SAMPLES as f64 / size as f64;
+        let k = position as usize;
+        self.0[k].mix(self.0[k + 1], position - k as f64)
+    }
+}
+
+impl Params {
+    /// `color` at position `i` along the curve. Every built in function only depends on how far
+    /// along the curve `i` is, so for long curves this interpolates between samples instead of
+    /// running the (mostly Okhsv) conversions for every position.
+    pub fn curve_colors<'a, F>(&self, color: &'a F) -> impl Fn(u64) -> LinSrgba<f64> + Sync + 'a
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        let size = self.num_pixels;
+        let samples = (size > COLOR_SAMPLES).then(|| ColorSamples::new(color));
+        let deterministic = self.deterministic;
+        move |i| {
+            let color = match &samples {
+                Some(samples) => samples.color(i, size),
+                None => color(i, size),
+            };
+            match deterministic {
+                true => snap_color(color),
+                false => color,
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use bpaf::{doc::Section, Args};
+
+use crate::{
+    cli,
+    platform::PLATFORMS,
+    presets::{self, Preset},
+    FUNCTIONS,
+};
+
+/// What the program is run as from the completion scripts, which call it back to complete.
+const NAME: &str = env!("CARGO_PKG_NAME");
+
+/// A shell to print a completion script for.
+#[derive(Debug, Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => Err(format!("unknown shell '{s}', expected bash, zsh, or fish")),
+        }
+    }
+}
+
+impl Shell {
+    /// Prints the completion script for the shell, which bpaf writes from the options' own
+    /// definitions, and exits.
+    pub fn print(self) -> ! {
+        let flag = match self {
+            Self::Bash => "--bpaf-complete-style-bash",
+            Self::Zsh => "--bpaf-complete-style-zsh",
+            Self::Fish => "--bpaf-complete-style-fish",
+        };
+        let args = [flag];
+        let _ = cli().run_inner(Args::from(&args[..]).set_name(NAME));
+        unreachable!("bpaf exits once it's printed the script")
+    }
+}
+
+/// Prints a man page for every command and option.
+pub fn print_manpage() {
+    print!(
+        "{}",
+        cli().render_manpage(
+            NAME,
+            Section::General,
+            None,
+            None,
+            Some("Hilbert animation")
+        )
+    );
+}
+
+/// Completes --function with the built in functions.
+// bpaf hands over what's typed as the option's own type
+#[allow(clippy::ptr_arg)]
+pub fn complete_function(typed: &String) -> Vec<(&'static str, Option<&'static str>)> {
+    FUNCTIONS
+        .iter()
+        .filter(|(name, _)| name.starts_with(typed.as_str()))
+        .map(|(name, _)| (*name, None))
+        .collect()
+}
+
+/// Completes --preset with the platforms and the presets that have been saved.
+pub fn complete_preset(typed: &Option<Preset>) -> Vec<(String, Option<String>)> {
+    let typed = match typed {
+        Some(Preset::Platform(platform)) => platform.name(),
+        Some(Preset::Saved(name)) => name,
+        None => "",
+    };
+    let platforms = PLATFORMS
+        .into_iter()
+        .map(|name| (name.to_owned(), Some("platform".to_owned())));
+    let saved = presets::saved_names()
+        .into_iter()
+        .map(|name| (name, Some("saved preset".to_owned())));
+    platforms
+        .chain(saved)
+        .filter(|(name, _)| name.starts_with(typed))
+        .collect()
+}
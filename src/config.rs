@@ -0,0 +1,806 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bpaf::{ParseFailure, Parser};
+
+use crate::{
+    presets::{self, Preset},
+    Options,
+};
+
+/// Read from the current directory when --config isn't given.
+const DEFAULT_CONFIG: &str = "hilbert_animation.toml";
+
+/// Options with short names, which a command line can set those options with.
+const SHORT_NAMES: &[(&str, &str)] = &[
+    ("function", "-f"),
+    ("framerate", "-r"),
+    ("loops", "-l"),
+    ("bitrate", "-b"),
+    ("quiet", "-q"),
+    ("verbose", "-v"),
+];
+
+/// Other long names some options go by, after the name they're kept under.
+const ALIASES: &[(&str, &str)] = &[("crf", "--quality"), ("bit-depth", "--depth")];
+
+/// A value in a config file.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Bool(bool),
+    /// Strings, and numbers as they were written.
+    Text(String),
+    Array(Vec<Value>),
+}
+
+/// A parsed config file.
+#[derive(Debug, Default)]
+pub struct Document {
+    /// The settings at the top level.
+    pub settings: Vec<(String, Value)>,
+    /// The settings under each `[[name]]` header, in order.
+    pub tables: Vec<(String, Vec<(String, Value)>)>,
+}
+
+/// Reads and parses a config file.
+pub fn read(path: &Path) -> Result<Document> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&contents).with_context(|| format!("Invalid {}", path.display()))
+}
+
+/// Reads a config file of options, without any `[[name]]` tables.
+fn read_settings(path: &Path) -> Result<Vec<(String, Value)>> {
+    let document = read(path)?;
+    if let Some((name, _)) = document.tables.first() {
+        bail!(
+            "{} can't have [[{name}]] tables, options go at the top level",
+            path.display()
+        );
+    }
+    Ok(document.settings)
+}
+
+/// Parses options the way the command line is, for config files and saved presets.
+pub fn parse_options(args: &[OsString]) -> Result<Options> {
+    crate::options()
+        .to_options()
+        .run_inner(args)
+        .map_err(|err| match err {
+            ParseFailure::Stderr(_) => anyhow!(err.unwrap_stderr()),
+            _ => anyhow!("--help isn't an option to render with"),
+        })
+}
+
+/// The command line after a subcommand that takes the render options, without the subcommand's
+/// own `options` and their values, to apply config files under.
+pub fn subcommand_render_args(options: &[&str]) -> Vec<OsString> {
+    let mut args = env::args_os().skip(2);
+    let mut render_args = Vec::new();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if options.contains(&&*text) {
+            // Its value is the next argument
+            args.next();
+            continue;
+        }
+        let own = options
+            .iter()
+            .any(|option| text.starts_with(&format!("{option}=")));
+        if !own {
+            render_args.push(arg);
+        }
+    }
+    render_args
+}
+
+/// The files options are taken from, in the order they're used: a saved --preset, then --config,
+/// or hilbert_animation.toml if there is one.
+pub fn paths(options: &Options) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if let Some(Preset::Saved(name)) = &options.preset {
+        paths.push(presets::saved_preset_path(name)?);
+    }
+    match &options.config {
+        Some(path) => paths.push(path.clone()),
+        None if Path::new(DEFAULT_CONFIG).exists() => paths.push(PathBuf::from(DEFAULT_CONFIG)),
+        None => {}
+    }
+    Ok(paths)
+}
+
+/// Fills in the options the command line didn't give from a saved --preset, and then from
+/// --config, or hilbert_animation.toml if there is one. `command_line` is the options as they
+/// were given, to parse again with the files' in front of them.
+pub fn apply(options: Options, command_line: Vec<OsString>) -> Result<Options> {
+    let (options, command_line) = match options.stdin_json {
+        true => with_stdin_json(&options, command_line).context("Invalid options on stdin")?,
+        false => (options, command_line),
+    };
+    let paths = paths(&options)?;
+    if paths.is_empty() {
+        return Ok(options);
+    }
+
+    let given_options = option_names(&command_line);
+    let mut args = Vec::new();
+    let mut set = Vec::new();
+    for path in &paths {
+        for (key, value) in read_settings(path)? {
+            let key = canonical_name(&key);
+            if matches!(
+                &*key,
+                "config" | "preset" | "outputs" | "watch" | "list-formats" | "stdin-json"
+            ) {
+                bail!(
+                    "{} can't set {key}, give it on the command line",
+                    path.display()
+                );
+            }
+            if given(&given_options, &key) || set.contains(&key) {
+                continue;
+            }
+            push_args(&mut args, &key, value);
+            set.push(key);
+        }
+    }
+    // The command line was already parsed once by itself, so any error here is the files'
+    args.extend(command_line);
+    let paths: Vec<_> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    parse_options(&args).with_context(|| format!("Invalid option in {}", paths.join(" or ")))
+}
+
+/// Reads options from stdin as a JSON object of settings, the same as a config file's, and puts
+/// them in front of the command line, which takes precedence. Returns the options parsed from
+/// both, and the command line with them, for config files to be applied under.
+fn with_stdin_json(
+    options: &Options,
+    command_line: Vec<OsString>,
+) -> Result<(Options, Vec<OsString>)> {
+    let json: serde_json::Value =
+        serde_json::from_reader(io::stdin().lock()).context("Failed to read JSON")?;
+    let serde_json::Value::Object(settings) = json else {
+        bail!(r#"expected a JSON object of options, like {{"order": 8, "outputs": "out.webp"}}"#);
+    };
+    let given_options = option_names(&command_line);
+    let mut args = Vec::new();
+    let mut outputs = Vec::new();
+    for (key, value) in &settings {
+        let key = canonical_name(key);
+        let Some(value) = json_value(&key, value)? else {
+            continue;
+        };
+        match &*key {
+            "stdin-json" | "watch" => bail!("{key} can't be set on stdin"),
+            // Outputs given on the command line replace these instead of adding to them
+            "outputs" if options.outputs.is_empty() => outputs.extend(output_paths(&value)?),
+            "outputs" => {}
+            _ if given(&given_options, &key) => {}
+            _ => push_args(&mut args, &key, value),
+        }
+    }
+    args.extend(command_line);
+    if !outputs.is_empty() {
+        args.push("--".into());
+        args.extend(outputs);
+    }
+    Ok((parse_options(&args)?, args))
+}
+
+/// A JSON setting as a config file's value, or none for null.
+fn json_value(key: &str, value: &serde_json::Value) -> Result<Option<Value>> {
+    Ok(Some(match value {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Bool(value) => Value::Bool(*value),
+        serde_json::Value::Number(number) => Value::Text(number.to_string()),
+        serde_json::Value::String(text) => Value::Text(text.clone()),
+        serde_json::Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .filter_map(|value| json_value(key, value).transpose())
+                .collect::<Result<_>>()?,
+        ),
+        serde_json::Value::Object(_) => bail!("{key} can't be an object"),
+    }))
+}
+
+/// Options for one of a batch's jobs, from its own settings over the ones shared by every job,
+/// and then a saved --preset's.
+pub fn job_options(job: &[(String, Value)], shared: &[(String, Value)]) -> Result<Options> {
+    let mut args = Vec::new();
+    let mut outputs = Vec::new();
+    let mut set = Vec::new();
+    for (key, value) in job.iter().chain(shared) {
+        let key = canonical_name(key);
+        if set.contains(&key) {
+            continue;
+        }
+        match &*key {
+            "config" | "threads" | "nice" | "quiet" | "verbose" | "progress"
+            | "progress-stages" | "watch" => {
+                bail!("{key} can't be set for each job, give it to the batch command")
+            }
+            "list-formats" => bail!("list-formats can't be set for a job, it doesn't render"),
+            "outputs" => outputs.extend(output_paths(value)?),
+            _ => push_args(&mut args, &key, value.clone()),
+        }
+        set.push(key);
+    }
+    // Outputs are positional, after everything else
+    let with_outputs = |args: &[OsString]| {
+        let mut args = args.to_vec();
+        args.push("--".into());
+        args.extend(outputs.iter().cloned());
+        args
+    };
+    let options = parse_options(&with_outputs(&args))?;
+    let Some(Preset::Saved(name)) = &options.preset else {
+        return Ok(options);
+    };
+    let path = presets::saved_preset_path(name)?;
+    for (key, value) in read_settings(&path)? {
+        let key = canonical_name(&key);
+        if !set.contains(&key) {
+            push_args(&mut args, &key, value);
+        }
+    }
+    parse_options(&with_outputs(&args))
+        .with_context(|| format!("Invalid option in {}", path.display()))
+}
+
+/// The paths an `outputs` setting lists, as a string or an array of them.
+fn output_paths(value: &Value) -> Result<Vec<OsString>> {
+    match value {
+        Value::Text(path) => Ok(vec![path.into()]),
+        Value::Array(values) => {
+            let mut paths = Vec::new();
+            for value in values {
+                paths.extend(output_paths(value)?);
+            }
+            Ok(paths)
+        }
+        Value::Bool(_) => bail!("outputs must be paths"),
+    }
+}
+
+/// The name an option is kept under, whichever of its long names it was given by.
+fn canonical_name(key: &str) -> String {
+    let key = key.replace('_', "-");
+    ALIASES
+        .iter()
+        .find(|(_, alias)| alias[2..] == key)
+        .map_or(key, |(name, _)| name.to_string())
+}
+
+/// The names of the options the command line gives, like `--order` or `-f`, without the values
+/// given with or after them, which can look like options themselves, like `--ffmpeg-arg -b:v`.
+/// Short names given together, like `-qv`, are each named.
+fn option_names(command_line: &[OsString]) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut args = command_line.iter().map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        let Some(arg) = arg.filter(|arg| arg.starts_with('-') && *arg != "-") else {
+            continue;
+        };
+        if arg == "--" {
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = long
+                .split_once('=')
+                .map_or((long, None), |(name, value)| (name, Some(value)));
+            let name = format!("--{name}");
+            if value.is_none() && takes_value(&name) {
+                args.next();
+            }
+            names.push(name);
+            continue;
+        }
+        // Each short name is a flag until one that takes a value, which is the rest of the word
+        // or the next one
+        for (i, c) in arg.char_indices().skip(1) {
+            let name = format!("-{c}");
+            let takes_value = takes_value(&name);
+            names.push(name);
+            if takes_value {
+                if i + c.len_utf8() == arg.len() {
+                    args.next();
+                }
+                break;
+            }
+        }
+    }
+    names
+}
+
+/// Whether an option takes a value, by its name alone.
+fn takes_value(name: &str) -> bool {
+    // Flags parse by themselves, but options that take a value fail without one
+    parse_options(&[name.into()]).is_err()
+}
+
+/// Whether the command line sets `key` itself, which then takes precedence over the files.
+/// `options` are its option names from `option_names`.
+fn given(options: &[String], key: &str) -> bool {
+    let long = format!("--{key}");
+    let mut spellings = vec![long.as_str()];
+    spellings.extend(
+        ALIASES
+            .iter()
+            .chain(SHORT_NAMES)
+            .filter(|(name, _)| *name == key)
+            .map(|(_, spelling)| *spelling),
+    );
+    if options
+        .iter()
+        .any(|name| spellings.contains(&name.as_str()))
+    {
+        return true;
+    }
+    // The first -f is --function, unless it was given by its long name, and the next is --frames
+    let short_f = options.iter().filter(|name| *name == "-f").count();
+    let function = options.iter().any(|name| name == "--function");
+    key == "frames" && short_f > usize::from(!function)
+}
+
+/// Adds the command line options a setting stands for.
+fn push_args(args: &mut Vec<OsString>, key: &str, value: Value) {
+    match value {
+        Value::Bool(true) => args.push(format!("--{key}").into()),
+        Value::Bool(false) => {}
+        Value::Text(text) => args.push(format!("--{key}={text}").into()),
+        Value::Array(values) => {
+            for value in values {
+                push_args(args, key, value);
+            }
+        }
+    }
+}
+
+/// Turns options given like on the command line back into settings for a file, with repeated
+/// options collected into arrays.
+pub fn settings_from_args(args: &[OsString]) -> Result<Vec<(String, Value)>> {
+    let mut settings: Vec<(String, Vec<Value>)> = Vec::new();
+    let mut function = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg
+            .to_str()
+            .with_context(|| format!("{} isn't valid UTF-8", arg.to_string_lossy()))?;
+        let (key, inline) = if let Some(long) = arg.strip_prefix("--") {
+            let (key, value) = long
+                .split_once('=')
+                .map_or((long, None), |(key, value)| (key, Some(value)));
+            (canonical_name(key), value)
+        } else if arg.starts_with('-') && arg.len() >= 2 {
+            let (short, value) = arg.split_at(2);
+            let key = match short {
+                // Like on the command line, the first -f is --function and the next is --frames
+                "-f" if function => "frames",
+                _ => SHORT_NAMES
+                    .iter()
+                    .find(|(_, spelling)| *spelling == short)
+                    .map(|(name, _)| *name)
+                    .with_context(|| format!("unknown option {short}"))?,
+            };
+            let value = value.strip_prefix('=').unwrap_or(value);
+            (key.to_owned(), (!value.is_empty()).then_some(value))
+        } else {
+            bail!("expected an option, found {arg}");
+        };
+        function |= key == "function";
+
+        let value = match inline {
+            Some(value) => Value::Text(value.to_owned()),
+            // Flags parse by themselves, while options with a value need the argument after them
+            None if parse_options(&[format!("--{key}").into()]).is_ok() => Value::Bool(true),
+            None => {
+                let value = args
+                    .next()
+                    .with_context(|| format!("--{key} needs a value"))?;
+                Value::Text(value.to_string_lossy().into_owned())
+            }
+        };
+        match settings.iter_mut().find(|(name, _)| *name == key) {
+            Some((_, values)) => values.push(value),
+            None => settings.push((key, vec![value])),
+        }
+    }
+    Ok(settings
+        .into_iter()
+        .map(|(key, mut values)| match values.len() {
+            1 => (key, values.remove(0)),
+            _ => (key, Value::Array(values)),
+        })
+        .collect())
+}
+
+/// Writes settings out as `key = value` lines.
+pub fn to_toml(settings: &[(String, Value)]) -> String {
+    fn value_to_toml(value: &Value) -> String {
+        match value {
+            Value::Bool(value) => value.to_string(),
+            Value::Text(text)
+                if text.starts_with(|c: char| c.is_ascii_digit())
+                    && text.parse::<f64>().is_ok() =>
+            {
+                text.clone()
+            }
+            Value::Text(text) => {
+                let mut quoted = String::from('"');
+                for c in text.chars() {
+                    match c {
+                        '"' => quoted.push_str("\\\""),
+                        '\\' => quoted.push_str("\\\\"),
+                        '\n' => quoted.push_str("\\n"),
+                        '\t' => quoted.push_str("\\t"),
+                        c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+            Value::Array(values) => {
+                let values: Vec<_> = values.iter().map(value_to_toml).collect();
+                format!("[{}]", values.join(", "))
+            }
+        }
+    }
+    settings
+        .iter()
+        .map(|(key, value)| format!("{key} = {}\n", value_to_toml(value)))
+        .collect()
+}
+
+/// Parses the part of TOML that options need: `key = value` lines, with strings, numbers,
+/// booleans, and arrays of them, and `[[name]]` headers for lists of them like batch jobs.
+fn parse(contents: &str) -> Result<Document> {
+    let mut reader = Reader {
+        contents,
+        position: 0,
+    };
+    let mut document = Document::default();
+    loop {
+        reader.skip_blank_lines();
+        let Some(next) = reader.peek() else {
+            break;
+        };
+        let line = reader.line();
+        if reader.rest().starts_with("[[") {
+            reader.position += 2;
+            let name = reader.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !reader.rest().starts_with("]]") {
+                bail!("line {line}: expected ]] after [[{name}");
+            }
+            reader.position += 2;
+            document.tables.push((name.to_owned(), Vec::new()));
+            continue;
+        }
+        if next == '[' {
+            bail!("line {line}: tables aren't supported, options go at the top level");
+        }
+        let key = reader.take_while(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if key.is_empty() {
+            bail!("line {line}: expected an option name");
+        }
+        reader.skip_spaces();
+        if reader.next() != Some('=') {
+            bail!("line {line}: expected = after {key}");
+        }
+        reader.skip_spaces();
+        let value = reader
+            .value()
+            .with_context(|| format!("line {line}: invalid value for {key}"))?;
+        reader.skip_spaces();
+        reader.skip_comment();
+        if !matches!(reader.next(), None | Some('\n')) {
+            bail!("line {line}: expected a new line after the value for {key}");
+        }
+        let settings = match document.tables.last_mut() {
+            Some((_, settings)) => settings,
+            None => &mut document.settings,
+        };
+        if settings.iter().any(|(name, _)| name == key) {
+            bail!("line {line}: {key} is set more than once");
+        }
+        settings.push((key.to_owned(), value));
+    }
+    Ok(document)
+}
+
+struct Reader<'a> {
+    contents: &'a str,
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.contents[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    /// The line the reader is on, counting from 1.
+    fn line(&self) -> usize {
+        self.contents[..self.position].matches('\n').count() + 1
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.position;
+        while self.peek().is_some_and(&f) {
+            self.next();
+        }
+        &self.contents[start..self.position]
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(|c| c == ' ' || c == '\t' || c == '\r');
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            self.take_while(|c| c != '\n');
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            if self.peek() != Some('\n') {
+                break;
+            }
+            self.next();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::Text),
+            Some('\'') => {
+                self.next();
+                let text = self.take_while(|c| c != '\'' && c != '\n').to_owned();
+                if self.next() != Some('\'') {
+                    bail!("unterminated string");
+                }
+                Ok(Value::Text(text))
+            }
+            Some('[') => {
+                self.next();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank_lines();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        break;
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank_lines();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => break,
+                        _ => bail!("expected , or ] in array"),
+                    }
+                }
+                Ok(Value::Array(values))
+            }
+            _ => match self.take_while(|c| c.is_ascii_alphanumeric() || "+-._".contains(c)) {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                // Underscores only separate digits in TOML numbers
+                number if number.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) => {
+                    Ok(Value::Text(number.replace('_', "")))
+                }
+                _ => bail!("expected a quoted string, number, boolean, or array"),
+            },
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        self.next();
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => bail!("unterminated string"),
+                Some('"') => return Ok(text),
+                Some('\\') => text.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('u') => {
+                        let hex = self.rest().get(..4).context("invalid \\u escape")?;
+                        let c = u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .context("invalid \\u escape")?;
+                        self.position += 4;
+                        c
+                    }
+                    _ => bail!("unknown escape in string"),
+                }),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command_line: &str) -> Vec<OsString> {
+        command_line
+            .split_whitespace()
+            .map(OsString::from)
+            .collect()
+    }
+
+    fn text(value: &Value) -> &str {
+        match value {
+            Value::Text(text) => text,
+            _ => panic!("expected text, got {value:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_settings_and_tables() {
+        let document = parse(
+            "# shared\norder = 10\nframerate = 6_0\nfunction = \"square_value\" # comment\n\
+             ffmpeg-arg = ['-b:v', \"1M\",\n  ]\nforce = true\n\n[[job]]\noutputs = \"a.webm\"\n\
+             [[job]]\norder = 11\n",
+        )
+        .unwrap();
+        let settings = &document.settings;
+        assert_eq!(settings.len(), 5);
+        assert_eq!(text(&settings[0].1), "10");
+        assert_eq!(text(&settings[1].1), "60");
+        assert_eq!(text(&settings[2].1), "square_value");
+        let Value::Array(values) = &settings[3].1 else {
+            panic!("expected an array");
+        };
+        assert_eq!(values.iter().map(text).collect::<Vec<_>>(), ["-b:v", "1M"]);
+        assert!(matches!(settings[4].1, Value::Bool(true)));
+        assert_eq!(document.tables.len(), 2);
+        assert_eq!(document.tables[0].0, "job");
+        assert_eq!(text(&document.tables[0].1[0].1), "a.webm");
+        assert_eq!(text(&document.tables[1].1[0].1), "11");
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let document = parse(r#"text = "a \"b\"\t\\ é""#).unwrap();
+        assert_eq!(text(&document.settings[0].1), "a \"b\"\t\\ \u{e9}");
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let settings = vec![
+            ("order".to_owned(), Value::Text("8".to_owned())),
+            ("text".to_owned(), Value::Text("say \"hi\"\n".to_owned())),
+            ("nice".to_owned(), Value::Bool(true)),
+        ];
+        let document = parse(&to_toml(&settings)).unwrap();
+        assert_eq!(text(&document.settings[0].1), "8");
+        assert_eq!(text(&document.settings[1].1), "say \"hi\"\n");
+        assert!(matches!(document.settings[2].1, Value::Bool(true)));
+    }
+
+    #[test]
+    fn rejects_mistakes_with_their_line() {
+        for (contents, error) in [
+            (
+                "order = 1\norder = 2",
+                "line 2: order is set more than once",
+            ),
+            ("[render]\norder = 1", "line 1: tables aren't supported"),
+            (
+                "\n\nfunction = \"square",
+                "line 3: invalid value for function",
+            ),
+            ("order 1", "line 1: expected = after order"),
+            (
+                "order = 1 2",
+                "line 1: expected a new line after the value for order",
+            ),
+            ("[[job]\norder = 1", "line 1: expected ]] after [[job"),
+            ("function = square", "line 1: invalid value for function"),
+        ] {
+            let err = parse(contents).unwrap_err().to_string();
+            assert!(err.starts_with(error), "{contents:?} gave {err:?}");
+        }
+    }
+
+    #[test]
+    fn names_options_without_their_values() {
+        assert_eq!(
+            option_names(&args("--order 8 --ffmpeg-arg -b:v --force out.webm")),
+            ["--order", "--ffmpeg-arg", "--force"]
+        );
+        assert_eq!(
+            option_names(&args("--order=8 -r30 -l 2 -- -q")),
+            ["--order", "-r", "-l"]
+        );
+    }
+
+    #[test]
+    fn names_each_short_option_given_together() {
+        assert_eq!(option_names(&args("-qv")), ["-q", "-v"]);
+        assert_eq!(option_names(&args("-qr 30")), ["-q", "-r"]);
+        // The value of -f isn't more short options
+        assert_eq!(option_names(&args("-fquadratic")), ["-f"]);
+    }
+
+    #[test]
+    fn short_options_given_together_are_each_given() {
+        let options = option_names(&args("-qv"));
+        assert!(given(&options, "quiet"));
+        assert!(given(&options, "verbose"));
+        assert!(!given(&options, "framerate"));
+    }
+
+    #[test]
+    fn counts_frames_after_function() {
+        let given_frames = |command_line| given(&option_names(&args(command_line)), "frames");
+        assert!(!given_frames("-f square_value"));
+        assert!(!given_frames("-fframes"));
+        assert!(given_frames("-f square_value -f 64"));
+        assert!(given_frames("--function square_value -f 64"));
+        assert!(given_frames("--frames 64"));
+        assert!(!given_frames("--framerate 60"));
+    }
+
+    #[test]
+    fn aliases_are_given() {
+        assert!(given(&option_names(&args("--quality 20")), "crf"));
+        assert!(given(&option_names(&args("--depth=16")), "bit-depth"));
+    }
+
+    #[test]
+    fn job_settings_override_shared_ones() {
+        let job = parse("order = 5\noutputs = [\"a.webm\", \"b.gif\"]\n").unwrap();
+        let shared = parse("order = 10\nframerate = 60\n").unwrap();
+        let options = job_options(&job.settings, &shared.settings).unwrap();
+        assert_eq!(options.order, 5);
+        assert_eq!(options.framerate, 60);
+        assert_eq!(
+            options.outputs,
+            [PathBuf::from("a.webm"), PathBuf::from("b.gif")]
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let path = env::temp_dir().join(format!(
+            "hilbert_animation_config_test_{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "order = 5\nframerate = 60\nframes = 16\n").unwrap();
+        let command_line = args(&format!("--config {} -qr 24 out.webm", path.display()));
+        let applied = parse_options(&command_line).and_then(|options| apply(options, command_line));
+        fs::remove_file(&path).unwrap();
+        let options = applied.unwrap();
+        assert_eq!(options.order, 5);
+        assert_eq!(options.framerate, 24);
+        assert_eq!(options.frames, 16);
+        assert!(options.quiet);
+    }
+}
@@ -0,0 +1,170 @@
+use std::{num::NonZeroUsize, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bpaf::*;
+use image::{
+    imageops::{self, FilterType},
+    Rgba, RgbaImage,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    color_function, config, exit,
+    frames::FrameFormat,
+    glyphs, logging, options,
+    progress::{self, Progress},
+    resume::write_frame,
+    start_thread_pool, ColorFunction, Options, Params,
+};
+
+/// Space around and between the cells, in pixels.
+const GAP: u32 = 8;
+/// How many pixels wide and tall each pixel of a label's glyphs is drawn.
+const LABEL_SCALE: u32 = 2;
+const BACKGROUND: Rgba<u8> = Rgba([24, 24, 24, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([220, 220, 220, 255]);
+
+/// Renders frames from across the animation into a labeled grid, to see how it plays out at a
+/// glance
+#[derive(Debug, Clone, Bpaf)]
+pub struct ContactSheetOptions {
+    /// How many frames to sample, evenly spaced from the first
+    #[bpaf(long, argument("K"), fallback(NonZeroUsize::new(12).unwrap()))]
+    count: NonZeroUsize,
+    /// Frames in each row of the grid, instead of making it as square as it can be
+    #[bpaf(long, argument("N"))]
+    columns: Option<NonZeroUsize>,
+    /// Size of each frame in the grid, in pixels. Frames that aren't square fit within it.
+    #[bpaf(long, argument("PIXELS"), fallback(256))]
+    cell_size: u32,
+    #[bpaf(external(options))]
+    options: Options,
+}
+
+impl ContactSheetOptions {
+    pub fn run(self) -> Result<()> {
+        let own = ["--count", "--columns", "--cell-size"];
+        let mut options = config::apply(self.options.clone(), config::subcommand_render_args(&own))
+            .map_err(exit::usage)?;
+        logging::set_verbosity(options.quiet, options.verbose);
+        progress::set_style(
+            options.progress,
+            options.progress_stages.clone(),
+            options.quiet,
+        );
+        match options.outputs.len() {
+            0 => options.outputs.push(PathBuf::from("contact-sheet.png")),
+            1 => {}
+            _ => return Err(exit::usage(anyhow!("contact-sheet writes a single image"))),
+        }
+        let threads = start_thread_pool(options.threads, options.nice)?;
+        let options = Options { threads, ..options };
+        let (function, params, format) = plan(options, self.cell_size).map_err(exit::usage)?;
+
+        let count = self.count.get().min(params.frames);
+        let sampled: Vec<usize> = (0..count).map(|k| k * params.frames / count).collect();
+        let columns = self
+            .columns
+            .map_or_else(|| (count as f64).sqrt().ceil() as usize, NonZeroUsize::get)
+            .min(count);
+        let sheet = self.draw(&params, function, &sampled, columns);
+        let image = params.encode_image(&sheet, format)?;
+        write_frame(params.filename.clone(), &image)
+            .with_context(|| format!("Failed to write {}", params.filename.display()))
+    }
+
+    /// Renders each of the `sampled` frames into a cell of the grid, with its frame number and
+    /// time underneath.
+    fn draw(
+        &self,
+        params: &Params,
+        function: ColorFunction,
+        sampled: &[usize],
+        columns: usize,
+    ) -> RgbaImage {
+        let colors = params.color_table::<u8, _>(function);
+        let progress = Progress::new("render", sampled.len());
+        let thumbnails: Vec<RgbaImage> = sampled
+            .par_iter()
+            .map(|&i| {
+                let frame = params.gen_frame(&colors, i);
+                let thumbnail = thumbnail(&frame, self.cell_size);
+                params.recycle_frame(frame);
+                progress.update(1);
+                thumbnail
+            })
+            .collect();
+
+        // Every frame is the same size, so each cell is too
+        let (width, height) = thumbnails[0].dimensions();
+        let rows = sampled.len().div_ceil(columns) as u32;
+        let label_height = glyphs::HEIGHT * LABEL_SCALE + GAP;
+        let cell_width = width + GAP;
+        let cell_height = height + label_height + GAP;
+        let mut sheet = RgbaImage::from_pixel(
+            GAP + columns as u32 * cell_width,
+            GAP + rows * cell_height,
+            BACKGROUND,
+        );
+        for (n, (thumbnail, &i)) in thumbnails.iter().zip(sampled).enumerate() {
+            let x = GAP + (n % columns) as u32 * cell_width;
+            let y = GAP + (n / columns) as u32 * cell_height;
+            imageops::overlay(&mut sheet, thumbnail, x as i64, y as i64);
+            let seconds = i as f64 / params.framerate as f64;
+            glyphs::draw_text(
+                &mut sheet,
+                &format!("{i} {seconds:.2}s"),
+                x,
+                y + height + GAP / 2,
+                LABEL_SCALE,
+                LABEL_COLOR,
+            );
+        }
+        sheet
+    }
+}
+
+/// Works out what to render and how to write it, checking the options before anything is
+/// rendered.
+fn plan(options: Options, cell_size: u32) -> Result<(ColorFunction, Params, FrameFormat)> {
+    let function = color_function(&options.function)?;
+    let params = Params::new(options)?;
+    if params.frames == 0 {
+        bail!("There are no frames to put on a contact sheet");
+    }
+    if cell_size == 0 {
+        bail!("--cell-size has to be at least a pixel");
+    }
+    let format: FrameFormat = params
+        .filename
+        .extension()
+        .and_then(|ext| ext.to_str()?.parse().ok())
+        .with_context(|| {
+            format!(
+                "Can't tell the image format from '{}', expected a png, jpeg, tiff, bmp, webp, or \
+                 exr file",
+                params.filename.display()
+            )
+        })?;
+    if params.filename.exists() && !params.force {
+        bail!(
+            "{} already exists, pass --force to overwrite it",
+            params.filename.display()
+        );
+    }
+    Ok((function, params, format))
+}
+
+/// The frame scaled to fit a `size` pixel square. Pixels stay crisp when it's scaled up, and are
+/// averaged when it's scaled down.
+fn thumbnail(frame: &RgbaImage, size: u32) -> RgbaImage {
+    let (width, height) = frame.dimensions();
+    let scale = size as f64 / width.max(height) as f64;
+    let width = ((width as f64 * scale).round() as u32).clamp(1, size);
+    let height = ((height as f64 * scale).round() as u32).clamp(1, size);
+    let filter = match scale >= 1.0 {
+        true => FilterType::Nearest,
+        false => FilterType::Triangle,
+    };
+    imageops::resize(frame, width, height, filter)
+}
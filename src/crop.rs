@@ -0,0 +1,75 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Result};
+
+use crate::Params;
+
+/// A window of the curve for --crop, in cells from the top left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    /// The whole curve, for renders that aren't cropped.
+    pub fn full(image_size: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: image_size,
+            height: image_size,
+        }
+    }
+
+    /// Checks that the window fits within a curve of the given size.
+    pub fn check(self, image_size: u32) -> Result<()> {
+        if self.x as u64 + self.width as u64 > image_size as u64
+            || self.y as u64 + self.height as u64 > image_size as u64
+        {
+            bail!("--crop {self} goes past the edge of the {image_size}x{image_size} curve");
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid crop '{s}', expected x,y,width,height like 0,0,256,256");
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x, y, width, height] = parts[..] else {
+            return Err(invalid());
+        };
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(format!(
+                "invalid crop '{s}', width and height must be between 1 and 65535"
+            ));
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl Params {
+    /// The part of the curve that's rendered, which is all of it unless --crop was given.
+    pub fn window(&self) -> Crop {
+        self.crop.unwrap_or(Crop::full(self.image_size))
+    }
+}
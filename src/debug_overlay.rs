@@ -0,0 +1,51 @@
+use image::{ImageBuffer, Pixel, Primitive, Rgba, RgbaImage};
+
+use crate::{glyphs, overlay, Params};
+
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 176]);
+const TEXT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+impl Params {
+    /// The lines --debug-overlay draws on frame `i`: which frame of the animation it is, where
+    /// along the curve its colors start, and the parameters that decide how it loops.
+    fn debug_lines(&self, i: usize) -> Vec<String> {
+        let (frame, frames) = self.animation_frame(i);
+        let seconds = frame as f64 / self.framerate as f64;
+        vec![
+            format!("FRAME {frame}/{frames} {seconds:.2}s"),
+            format!("OFFSET {}/{}", self.frame_offset(i), self.num_pixels),
+            format!("ORDER {} {} FPS", self.order, self.framerate),
+            self.function.to_uppercase(),
+        ]
+    }
+
+    /// Draws the frame number, offset, and parameters in the top left of frame `i`, on a dark
+    /// box so they can be read over any colors. Glyphs grow with the frame, a pixel of theirs for
+    /// every 128 of its.
+    pub fn draw_debug_overlay<C>(&self, frame: &mut ImageBuffer<Rgba<C>, Vec<C>>, i: usize)
+    where
+        C: Primitive + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let scale = (frame.width().min(frame.height()) / 128).max(1);
+        let padding = 2 * scale;
+        let line_height = (glyphs::HEIGHT + 2) * scale;
+        let lines = self.debug_lines(i);
+        let width = lines
+            .iter()
+            .map(|line| glyphs::text_width(line, scale))
+            .max()
+            .unwrap_or(0);
+
+        let mut label = RgbaImage::from_pixel(
+            width + 2 * padding,
+            lines.len() as u32 * line_height - 2 * scale + 2 * padding,
+            BACKGROUND,
+        );
+        for (n, line) in lines.iter().enumerate() {
+            let y = padding + n as u32 * line_height;
+            glyphs::draw_text(&mut label, line, padding, y, scale, TEXT_COLOR);
+        }
+        overlay::blend(frame, &label, padding as i64, padding as i64);
+    }
+}
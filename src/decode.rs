@@ -0,0 +1,274 @@
+use std::{
+    ffi::c_int,
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    mem,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use anyhow::{bail, Context, Result};
+use image::{codecs::gif::GifDecoder, AnimationDecoder, ImageFormat, RgbaImage};
+use libwebp_sys as webp;
+use log::debug;
+
+use crate::{exit::FfmpegError, ffmpeg_log::FfmpegLog, OutputFormat};
+
+/// The frames of a render, decoded one at a time so whole videos are never held in memory.
+pub type Frames = Box<dyn Iterator<Item = Result<RgbaImage>>>;
+
+/// Decodes the frames of a gif, webp, webm, mp4, zip or tar of frames, or directory of frames, as
+/// 8-bit RGBA. The format is told from the extension, the way outputs' are.
+pub fn frames(path: &Path, ffmpeg_path: &Path) -> Result<Frames> {
+    if path.is_dir() {
+        return frame_dir(path);
+    }
+    let format: OutputFormat = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| ext.parse().ok())
+        .with_context(|| format!("Can't tell the format of '{}'", path.display()))?;
+    match format {
+        OutputFormat::Gif => gif(path),
+        OutputFormat::Webp => Ok(Box::new(AnimDecoder::open(path)?)),
+        OutputFormat::Webm | OutputFormat::Mp4 => {
+            Ok(Box::new(VideoDecoder::spawn(path, ffmpeg_path)?))
+        }
+        OutputFormat::Zip | OutputFormat::Tar => archive(path, format),
+        _ => bail!(
+            "{} can't be decoded, extract its frames into a directory first",
+            path.display()
+        ),
+    }
+}
+
+/// Every image in a directory, in the order of their names.
+fn frame_dir(dir: &Path) -> Result<Frames> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| ImageFormat::from_path(path).is_ok())
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        bail!("{} has no frames in it", dir.display());
+    }
+    Ok(Box::new(paths.into_iter().map(|path| {
+        let frame =
+            image::open(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(frame.into_rgba8())
+    })))
+}
+
+/// Every image in a zip or tar of frames, in the order of their names. They're read out of the
+/// archive up front, but only decoded one at a time.
+fn archive(path: &Path, format: OutputFormat) -> Result<Frames> {
+    let mut files = archive_files(path, format)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    files.retain(|(name, _)| ImageFormat::from_path(name).is_ok());
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if files.is_empty() {
+        bail!("{} has no frames in it", path.display());
+    }
+    Ok(Box::new(files.into_iter().map(|(name, data)| {
+        let frame = ImageFormat::from_path(&name)
+            .and_then(|format| image::load_from_memory_with_format(&data, format))
+            .with_context(|| format!("Failed to read {name}"))?;
+        Ok(frame.into_rgba8())
+    })))
+}
+
+/// The name and contents of each file in a zip or tar.
+fn archive_files(path: &Path, format: OutputFormat) -> Result<Vec<(String, Vec<u8>)>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut files = Vec::new();
+    match format {
+        OutputFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                files.push((entry.name()?.into_owned(), data));
+            }
+        }
+        _ => {
+            for entry in tar::Archive::new(file).entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                files.push((name, data));
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn gif(path: &Path) -> Result<Frames> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let decoder = GifDecoder::new(BufReader::new(file))
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Box::new(decoder.into_frames().map(|frame| {
+        Ok(frame.context("Failed to decode gif frame")?.into_buffer())
+    })))
+}
+
+/// An owned libwebp animation decoder, along with the file it's decoding.
+struct AnimDecoder {
+    decoder: NonNull<webp::WebPAnimDecoder>,
+    size: (u32, u32),
+    // Read by the decoder until it's deleted
+    _data: Vec<u8>,
+}
+
+impl AnimDecoder {
+    fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let webp_data = webp::WebPData {
+            bytes: data.as_ptr(),
+            size: data.len(),
+        };
+        let mut options = unsafe { mem::zeroed::<webp::WebPAnimDecoderOptions>() };
+        if unsafe { webp::WebPAnimDecoderOptionsInit(&mut options) } == 0 {
+            bail!("Failed to initialize webp decoder");
+        }
+        options.color_mode = webp::MODE_RGBA;
+        let decoder = unsafe { webp::WebPAnimDecoderNew(&webp_data, &options) };
+        let decoder = NonNull::new(decoder)
+            .with_context(|| format!("Failed to decode {}", path.display()))?;
+        let mut info = unsafe { mem::zeroed::<webp::WebPAnimInfo>() };
+        unsafe { webp::WebPAnimDecoderGetInfo(decoder.as_ptr(), &mut info) };
+        Ok(Self {
+            decoder,
+            size: (info.canvas_width, info.canvas_height),
+            _data: data,
+        })
+    }
+}
+
+impl Iterator for AnimDecoder {
+    type Item = Result<RgbaImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if unsafe { webp::WebPAnimDecoderHasMoreFrames(self.decoder.as_ptr()) } == 0 {
+            return None;
+        }
+        let mut buffer = ptr::null_mut();
+        let mut timestamp: c_int = 0;
+        if unsafe {
+            webp::WebPAnimDecoderGetNext(self.decoder.as_ptr(), &mut buffer, &mut timestamp)
+        } == 0
+        {
+            return Some(Err(anyhow::anyhow!("Failed to decode webp frame")));
+        }
+        // The canvas is the decoder's, and is drawn over for the next frame
+        let (width, height) = self.size;
+        let len = width as usize * height as usize * 4;
+        let canvas = unsafe { slice::from_raw_parts(buffer, len) }.to_vec();
+        RgbaImage::from_raw(width, height, canvas).map(Ok)
+    }
+}
+
+impl Drop for AnimDecoder {
+    fn drop(&mut self) {
+        unsafe { webp::WebPAnimDecoderDelete(self.decoder.as_ptr()) };
+    }
+}
+
+/// Frames decoded by FFMpeg, streamed back as PAM images so each says how big it is.
+struct VideoDecoder {
+    ffmpeg: Option<(Child, FfmpegLog)>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl VideoDecoder {
+    fn spawn(path: &Path, ffmpeg_path: &Path) -> Result<Self> {
+        if !path.exists() {
+            bail!("{} doesn't exist", path.display());
+        }
+        let mut command = Command::new(ffmpeg_path);
+        // Only errors, since the frames are what's being looked at rather than FFMpeg
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(path)
+            .args(["-f", "image2pipe", "-c:v", "pam", "-pix_fmt", "rgba", "-"]);
+        debug!("Running {command:?}");
+        let mut ffmpeg = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(FfmpegError("Failed to run FFMpeg"))?;
+        let stdout = BufReader::new(
+            ffmpeg
+                .stdout
+                .take()
+                .context("Failed to open FFMpeg stdout")?,
+        );
+        let log = FfmpegLog::capture(&mut ffmpeg);
+        Ok(Self {
+            ffmpeg: Some((ffmpeg, log)),
+            stdout,
+        })
+    }
+
+    /// Reads the next frame, or none once FFMpeg has decoded them all.
+    fn read_frame(&mut self) -> Result<Option<RgbaImage>> {
+        if self.stdout.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let (mut width, mut height) = (0, 0);
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                bail!("FFMpeg's output ended partway through a frame");
+            }
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["ENDHDR"] => break,
+                ["WIDTH", value] => width = value.parse()?,
+                ["HEIGHT", value] => height = value.parse()?,
+                ["DEPTH", depth] if depth != "4" => bail!("FFMpeg didn't decode to RGBA"),
+                _ => {}
+            }
+        }
+        let mut pixels = vec![0; width as usize * height as usize * 4];
+        self.stdout.read_exact(&mut pixels)?;
+        Ok(RgbaImage::from_raw(width, height, pixels))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let Some((mut ffmpeg, log)) = self.ffmpeg.take() else {
+            return Ok(());
+        };
+        let status = ffmpeg.wait().context(FfmpegError("FFMpeg failed"))?;
+        log.check(status, "FFMpeg failed to decode")
+    }
+}
+
+impl Drop for VideoDecoder {
+    /// Stops FFMpeg if fewer frames were wanted than it had to decode.
+    fn drop(&mut self) {
+        if let Some((mut ffmpeg, _)) = self.ffmpeg.take() {
+            let _ = ffmpeg.kill();
+            let _ = ffmpeg.wait();
+        }
+    }
+}
+
+impl Iterator for VideoDecoder {
+    type Item = Result<RgbaImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self
+            .read_frame()
+            .context(FfmpegError("Failed to decode video"))
+        {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => self.finish().err().map(Err),
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
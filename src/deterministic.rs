@@ -0,0 +1,73 @@
+use std::{
+    env, fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use image::ImageFormat;
+use palette::LinSrgba;
+
+use crate::{OutputFormat, Params};
+
+/// Rounds a color to single precision for --deterministic, so that math libraries which differ in
+/// the last bits of sin, powf, and the like still give the same frames.
+pub fn snap_color(color: LinSrgba<f64>) -> LinSrgba<f64> {
+    color.into_format::<f32, f32>().into_format()
+}
+
+impl Params {
+    /// Checks that every output can be written the same way on any machine for --deterministic.
+    pub fn check_deterministic(&self, format: OutputFormat) -> Result<()> {
+        if !self.deterministic {
+            return Ok(());
+        }
+        if self.hwaccel.is_some() && matches!(format, OutputFormat::Webm | OutputFormat::Mp4) {
+            bail!("--deterministic can't be used with --hwaccel, since hardware encoders differ");
+        }
+        Ok(())
+    }
+
+    /// FFMpeg output arguments that leave its own version out of the file for --deterministic,
+    /// so outputs don't change with the FFMpeg they were encoded by.
+    pub fn bitexact_args(&self) -> &'static [&'static str] {
+        match self.deterministic {
+            true => &["-fflags", "+bitexact", "-flags", "+bitexact"],
+            false => &[],
+        }
+    }
+
+    /// Modification time for files added to archives: now, or for --deterministic the
+    /// conventional SOURCE_DATE_EPOCH if it's set and the epoch otherwise.
+    pub fn archive_mtime(&self) -> u64 {
+        if self.deterministic {
+            return env::var("SOURCE_DATE_EPOCH")
+                .ok()
+                .and_then(|epoch| epoch.parse().ok())
+                .unwrap_or(0);
+        }
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs())
+    }
+
+    /// CRC-32 of the output as written, or of every frame file in a directory of frames in the
+    /// order of their names, for the manifest of a --deterministic render.
+    pub fn content_checksum(&self, format: OutputFormat) -> Result<String> {
+        let mut files = match format {
+            OutputFormat::Frames => fs::read_dir(&self.filename)
+                .with_context(|| format!("Failed to read {}", self.filename.display()))?
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| ImageFormat::from_path(path).is_ok())
+                .collect(),
+            _ => vec![self.filename.clone()],
+        };
+        files.sort();
+        let mut hasher = crc32fast::Hasher::new();
+        for file in files {
+            let data =
+                fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+            hasher.update(&data);
+        }
+        Ok(format!("{:08x}", hasher.finalize()))
+    }
+}
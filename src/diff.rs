@@ -0,0 +1,240 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Result};
+use bpaf::*;
+use image::RgbaImage;
+use log::warn;
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+
+use crate::{decode, exit, logging, video::FfmpegPipe};
+
+/// Side of the windows SSIM is worked out over.
+const SSIM_WINDOW: u32 = 8;
+
+/// Compares two renders frame by frame
+#[derive(Debug, Clone, Bpaf)]
+pub struct DiffOptions {
+    /// FFMpeg binary to use for decoding videos and writing the heatmap
+    #[bpaf(long, argument("PATH"), fallback("ffmpeg".into()))]
+    ffmpeg_path: PathBuf,
+    /// Also write a video of where the frames differ, from black where they're the same through
+    /// red and yellow to white where they differ the most, encoded by FFMpeg
+    #[bpaf(long, argument("PATH"))]
+    heatmap: Option<PathBuf>,
+    /// Framerate of the heatmap
+    #[bpaf(long, argument("FPS"), fallback(30))]
+    framerate: u32,
+    /// Overwrite the heatmap if it already exists
+    force: bool,
+    /// First render to compare: a gif, webp, webm, mp4, zip or tar of frames, or directory
+    /// of frames
+    #[bpaf(positional("A"))]
+    a: PathBuf,
+    /// Second render to compare it with
+    #[bpaf(positional("B"))]
+    b: PathBuf,
+}
+
+impl DiffOptions {
+    pub fn run(self) -> Result<()> {
+        if let Some(heatmap) = &self.heatmap {
+            if heatmap.exists() && !self.force {
+                return Err(exit::usage(anyhow::anyhow!(
+                    "{} already exists, pass --force to overwrite it",
+                    heatmap.display()
+                )));
+            }
+        }
+        let mut a = decode::frames(&self.a, &self.ffmpeg_path)?;
+        let mut b = decode::frames(&self.b, &self.ffmpeg_path)?;
+        let mut heatmap = None;
+        let mut squared_error = 0.0;
+        let mut ssim_total = 0.0;
+        let mut worst: Option<(usize, Comparison)> = None;
+        let mut compared = 0;
+
+        println!("frame      psnr    ssim");
+        for (i, (a, b)) in a.by_ref().zip(b.by_ref()).enumerate() {
+            let (a, b) = (a?, b?);
+            if a.dimensions() != b.dimensions() {
+                bail!(
+                    "Frame {i} is {}x{} in {} but {}x{} in {}",
+                    a.width(),
+                    a.height(),
+                    self.a.display(),
+                    b.width(),
+                    b.height(),
+                    self.b.display()
+                );
+            }
+            let comparison = Comparison::new(&a, &b);
+            println!(
+                "{i:>5}  {:>8}  {:.4}",
+                format_psnr(comparison.psnr()),
+                comparison.ssim
+            );
+            if let Some(path) = &self.heatmap {
+                let heatmap = match &mut heatmap {
+                    Some(heatmap) => heatmap,
+                    None => heatmap.insert(self.start_heatmap(path, a.dimensions())?),
+                };
+                heatmap.write(difference_heatmap(&a, &b).as_raw())?;
+            }
+
+            squared_error += comparison.mean_squared_error;
+            ssim_total += comparison.ssim;
+            if worst.is_none_or(|(_, worst)| comparison.ssim < worst.ssim) {
+                worst = Some((i, comparison));
+            }
+            compared += 1;
+        }
+        if let Some(heatmap) = heatmap {
+            heatmap.finish()?;
+        }
+        let Some((worst_frame, worst)) = worst else {
+            bail!("There are no frames to compare");
+        };
+
+        let longer = match (a.next().is_some(), b.next().is_some()) {
+            (true, _) => Some(&self.a),
+            (_, true) => Some(&self.b),
+            _ => None,
+        };
+        if let Some(longer) = longer {
+            warn!(
+                "{} has more frames, only the first {compared} were compared",
+                longer.display()
+            );
+        }
+        let compared = compared as f64;
+        println!(
+            "Overall PSNR {} dB, mean SSIM {:.4}, worst frame {worst_frame} with PSNR {} dB and \
+             SSIM {:.4}",
+            format_psnr(psnr(squared_error / compared)),
+            ssim_total / compared,
+            format_psnr(worst.psnr()),
+            worst.ssim
+        );
+        Ok(())
+    }
+
+    /// Starts FFMpeg encoding the heatmap, once the size of the frames is known.
+    fn start_heatmap(&self, path: &Path, (width, height): (u32, u32)) -> Result<FfmpegPipe> {
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .arg("-y")
+            .args(logging::ffmpeg_args())
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-s"])
+            .arg(format!("{width}x{height}"))
+            .arg("-r")
+            .arg(self.framerate.to_string())
+            .args(["-i", "-"])
+            .arg(path);
+        FfmpegPipe::spawn(command)
+    }
+}
+
+/// How closely one frame matches another.
+#[derive(Debug, Clone, Copy)]
+struct Comparison {
+    /// Over the red, green, and blue channels, out of 255.
+    mean_squared_error: f64,
+    /// Of the frames' luma, averaged over windows of them.
+    ssim: f64,
+}
+
+impl Comparison {
+    fn new(a: &RgbaImage, b: &RgbaImage) -> Self {
+        let squared_error: u64 = a
+            .as_raw()
+            .par_chunks_exact(4)
+            .zip(b.as_raw().par_chunks_exact(4))
+            .map(|(a, b)| {
+                (0..3)
+                    .map(|c| (a[c] as i64 - b[c] as i64).pow(2) as u64)
+                    .sum::<u64>()
+            })
+            .sum();
+        let samples = a.width() as f64 * a.height() as f64 * 3.0;
+        Self {
+            mean_squared_error: squared_error as f64 / samples,
+            ssim: ssim(a, b),
+        }
+    }
+
+    fn psnr(&self) -> f64 {
+        psnr(self.mean_squared_error)
+    }
+}
+
+/// Peak signal to noise ratio in decibels, which is infinite for identical frames.
+fn psnr(mean_squared_error: f64) -> f64 {
+    10.0 * (255.0f64.powi(2) / mean_squared_error).log10()
+}
+
+fn format_psnr(psnr: f64) -> String {
+    match psnr.is_finite() {
+        true => format!("{psnr:.2}"),
+        false => "inf".to_owned(),
+    }
+}
+
+/// Structural similarity of the frames' luma, from 1 for identical frames down, averaged over
+/// windows that don't overlap.
+fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |image: &RgbaImage, x, y| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+    };
+    let (width, height) = a.dimensions();
+    let columns = width.div_ceil(SSIM_WINDOW);
+    let windows = columns * height.div_ceil(SSIM_WINDOW);
+    let total: f64 = (0..windows)
+        .into_par_iter()
+        .map(|window| {
+            let x0 = window % columns * SSIM_WINDOW;
+            let y0 = window / columns * SSIM_WINDOW;
+            let pixels: Vec<(f64, f64)> = (y0..(y0 + SSIM_WINDOW).min(height))
+                .flat_map(|y| (x0..(x0 + SSIM_WINDOW).min(width)).map(move |x| (x, y)))
+                .map(|(x, y)| (luma(a, x, y), luma(b, x, y)))
+                .collect();
+            let n = pixels.len() as f64;
+            let mean_a = pixels.iter().map(|(a, _)| a).sum::<f64>() / n;
+            let mean_b = pixels.iter().map(|(_, b)| b).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (a, b) in &pixels {
+                var_a += (a - mean_a).powi(2) / n;
+                var_b += (b - mean_b).powi(2) / n;
+                covariance += (a - mean_a) * (b - mean_b) / n;
+            }
+            ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2))
+        })
+        .sum();
+    total / windows as f64
+}
+
+/// Each pixel colored by how far apart the frames are there, brightened so small differences
+/// still show up.
+fn difference_heatmap(a: &RgbaImage, b: &RgbaImage) -> RgbaImage {
+    let mut heatmap = RgbaImage::new(a.width(), a.height());
+    heatmap
+        .par_chunks_exact_mut(4)
+        .zip(a.as_raw().par_chunks_exact(4))
+        .zip(b.as_raw().par_chunks_exact(4))
+        .for_each(|((pixel, a), b)| {
+            let difference = (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0);
+            let t = (difference as f64 / 255.0).sqrt() * 3.0;
+            let channel = |start: f64| ((t - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+            pixel.copy_from_slice(&[channel(0.0), channel(1.0), channel(2.0), 255]);
+        });
+    heatmap
+}
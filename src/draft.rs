@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use crate::{gif::Dither, scale::Dimensions, video::EncodePreset, Options, OutputFormat, Platform};
+
+/// How many orders lower --draft renders at, for a sixteenth of the pixels.
+const DRAFT_ORDERS: u8 = 2;
+
+/// Turns the options into a quick preview of the same animation: a lower order scaled back up to
+/// the size asked for, half the frames at half the framerate so it plays for as long, and the
+/// fastest encoder settings.
+pub fn apply(options: &mut Options) {
+    let shift = DRAFT_ORDERS.min(options.order.saturating_sub(1));
+    let image_size = 2u32.pow(options.order as u32);
+    let size = match options.crop {
+        Some(crop) => Dimensions {
+            width: crop.width,
+            height: crop.height,
+        },
+        None => Dimensions {
+            width: image_size,
+            height: image_size,
+        },
+    };
+    if !draws_from_palette(options) {
+        options.output_size = Some(options.output_size.unwrap_or(size));
+    }
+    options.order -= shift;
+    if let Some(crop) = &mut options.crop {
+        crop.x >>= shift;
+        crop.y >>= shift;
+        crop.width = crop.width.div_ceil(1 << shift);
+        crop.height = crop.height.div_ceil(1 << shift);
+    }
+    options.supersample = 1;
+    options.strip_rows = None;
+
+    if options.frames > 1 && options.framerate > 1 {
+        options.frames /= 2;
+        options.framerate /= 2;
+    }
+
+    options.encode_preset = Some(EncodePreset::Fast);
+    options.two_pass = false;
+    options.webp_method = Some(0);
+    #[cfg(feature = "gifski")]
+    {
+        options.gif_fast = true;
+    }
+    // Encoding again and again to fit would take longer than the draft itself
+    options.max_size = None;
+}
+
+/// Whether any output is a gif drawn straight from the curve's palette, which can't be resized,
+/// so the draft is left at its lower order's size.
+fn draws_from_palette(options: &Options) -> bool {
+    #[cfg(feature = "gifski")]
+    if options.gifski {
+        return false;
+    }
+    if !options.palette_cycle && options.dither != Dither::None {
+        return false;
+    }
+    let is_gif = |output: &Path| match options.format {
+        Some(format) => format == OutputFormat::Gif,
+        None => output.extension().is_some_and(|ext| ext == "gif"),
+    };
+    if options.outputs.is_empty() {
+        let format = options.format.or(options.platform().map(Platform::format));
+        return format == Some(OutputFormat::Gif);
+    }
+    options.outputs.iter().any(|output| is_gif(output))
+}
@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::{
+    gif::Dither,
+    units::{Bitrate, ByteSize},
+    ColorFunction, OutputFormat, Params,
+};
+
+/// Frames rendered to time a render by, spread through the animation.
+const SAMPLE_FRAMES: usize = 3;
+
+impl Params {
+    /// Prints what the render would do and roughly what it would take for --dry-run, timing a
+    /// few frames to estimate the rest by.
+    pub fn print_dry_run(
+        &self,
+        outputs: &[(Params, OutputFormat)],
+        function: ColorFunction,
+    ) -> Result<()> {
+        let (width, height) = self.frame_dimensions();
+        let frames = self.shard_range(self.frames).len();
+        println!("{}", self.description());
+        println!(
+            "{frames} frame{} of {width}x{height} at {} fps, {:.2}s long",
+            if frames == 1 { "" } else { "s" },
+            self.framerate,
+            self.frames as f64 / self.framerate as f64
+        );
+        let threads = rayon::current_num_threads();
+        println!(
+            "Peak memory: about {} with {threads} thread{}",
+            ByteSize(self.peak_memory()),
+            if threads == 1 { "" } else { "s" }
+        );
+
+        if self.strip_rows.is_some() {
+            println!("Render time and output sizes aren't estimated for --strip-rows");
+            return Ok(());
+        }
+        let started = Instant::now();
+        let colors = self.color_table::<u8, _>(function);
+        let table_time = started.elapsed();
+        let samples: Vec<_> = (0..SAMPLE_FRAMES.min(self.frames))
+            .map(|k| {
+                let started = Instant::now();
+                let frame = self.gen_frame(&colors, k * self.frames / SAMPLE_FRAMES);
+                (frame, started.elapsed())
+            })
+            .collect();
+        let Some((frame, _)) = samples.first() else {
+            return Ok(());
+        };
+        let mut frame_time =
+            samples.iter().map(|(_, time)| *time).sum::<Duration>() / samples.len() as u32;
+
+        let mut uses_ffmpeg = false;
+        for (params, format) in outputs {
+            let estimate = params.sample_encoding(frame, *format)?;
+            let size = match estimate {
+                Estimate::Encoded { bytes, time } => {
+                    frame_time += time;
+                    Some(bytes * frames as u64)
+                }
+                Estimate::Size(bytes) => Some(bytes),
+                Estimate::Unknown => None,
+            };
+            uses_ffmpeg |= matches!(format, OutputFormat::Webm | OutputFormat::Mp4);
+            let size = match (size, params.max_size) {
+                (Some(size), Some(max_size)) => format!("about {}", ByteSize(size.min(max_size.0))),
+                (Some(size), None) => format!("about {}", ByteSize(size)),
+                (None, Some(max_size)) => format!("at most {max_size}"),
+                (None, None) => "not estimated without --bitrate".to_owned(),
+            };
+            println!("{} ({}): {size}", params.filename.display(), format.name());
+        }
+
+        // Frames are rendered and encoded on every thread at once
+        let render_time =
+            table_time + frame_time * frames as u32 / rayon::current_num_threads() as u32;
+        println!(
+            "Render time: about {}{}",
+            format_duration(render_time),
+            if uses_ffmpeg {
+                ", plus FFMpeg's encoding"
+            } else {
+                ""
+            }
+        );
+        Ok(())
+    }
+
+    /// Encodes a sample frame the way the output would, where that can be done alone, for how
+    /// long each frame takes and how big it comes out.
+    fn sample_encoding(&self, frame: &image::RgbaImage, format: OutputFormat) -> Result<Estimate> {
+        let (width, height) = self.frame_dimensions();
+        let pixels = width as u64 * height as u64;
+        let started = Instant::now();
+        let encoded = match format {
+            OutputFormat::Frames | OutputFormat::Zip | OutputFormat::Tar => {
+                self.encode_image(frame, self.frame_format)?
+            }
+            OutputFormat::Webp => self.encode_webp_image(frame)?,
+            // Uncompressed 32-bit floats, which is as big as they get
+            OutputFormat::Exr => {
+                return Ok(Estimate::Size(pixels * 16 * self.frames as u64));
+            }
+            // A single image with a palette for each frame
+            OutputFormat::Gif if self.palette_cycle => {
+                return Ok(Estimate::Size(pixels + 768 * self.frames as u64));
+            }
+            // Measured at around half a byte a pixel dithered, and a fifth of one without, where
+            // only the bands that moved are redrawn
+            OutputFormat::Gif if self.dither == Dither::None => {
+                return Ok(Estimate::Size(pixels * self.frames as u64 / 5));
+            }
+            OutputFormat::Gif => return Ok(Estimate::Size(pixels * self.frames as u64 / 2)),
+            OutputFormat::Webm | OutputFormat::Mp4 => {
+                return Ok(match self.bitrate {
+                    Some(Bitrate(bits)) => {
+                        Estimate::Size((bits as f64 / 8.0 * self.video_duration()) as u64)
+                    }
+                    None => Estimate::Unknown,
+                });
+            }
+        };
+        Ok(Estimate::Encoded {
+            bytes: encoded.len() as u64,
+            time: started.elapsed(),
+        })
+    }
+}
+
+/// How big an output would be.
+enum Estimate {
+    /// From encoding a sample frame, which took `time`.
+    Encoded {
+        bytes: u64,
+        time: Duration,
+    },
+    /// Worked out from its settings.
+    Size(u64),
+    Unknown,
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 60.0 {
+        return format!("{seconds:.1}s");
+    }
+    let seconds = seconds.round() as u64;
+    match seconds < 3600 {
+        true => format!("{}m {}s", seconds / 60, seconds % 60),
+        false => format!("{}h {}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
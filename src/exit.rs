@@ -0,0 +1,88 @@
+use std::{error::Error, fmt, io, process::ExitCode};
+
+use bpaf::ParseFailure;
+
+/// Bad options, or options that can't be used together.
+const USAGE: u8 = 2;
+/// A file that couldn't be read or written.
+const IO: u8 = 3;
+/// An encoder or FFMpeg failing partway through a render.
+const ENCODER: u8 = 4;
+/// Interrupted with Ctrl-C or SIGTERM, like a shell reports for SIGINT.
+pub const INTERRUPTED: u8 = 130;
+
+/// Wraps an error caused by the options given rather than by anything going wrong while
+/// rendering. It reads exactly as the error it wraps.
+#[derive(Debug)]
+struct UsageError(anyhow::Error);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for UsageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Context for a failure running or talking to FFMpeg, so it counts as the encoder's even when
+/// it's an I/O error on the pipe to it.
+#[derive(Debug)]
+pub struct FfmpegError(pub &'static str);
+
+impl fmt::Display for FfmpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Marks an error as a mistake in the options, for `.map_err(exit::usage)`.
+pub fn usage(err: anyhow::Error) -> anyhow::Error {
+    UsageError(err).into()
+}
+
+/// The exit code for an error: a mistake in the options, then FFMpeg failing, then failing to
+/// read or write a file, and anything else is the encoder's.
+pub fn code(err: &anyhow::Error) -> ExitCode {
+    let code = if err.chain().any(|cause| cause.is::<UsageError>()) {
+        USAGE
+    } else if err.downcast_ref::<FfmpegError>().is_some() {
+        ENCODER
+    } else if err.chain().any(|cause| cause.is::<io::Error>()) {
+        IO
+    } else {
+        ENCODER
+    };
+    ExitCode::from(code)
+}
+
+/// An error and what caused it, laid out like anyhow's debug format but without the backtrace
+/// that RUST_BACKTRACE adds to it, which is only noise for mistakes in the options.
+pub fn message(err: &anyhow::Error) -> String {
+    let mut message = err.to_string();
+    let causes: Vec<String> = err.chain().skip(1).map(ToString::to_string).collect();
+    match &causes[..] {
+        [] => {}
+        [cause] => message.push_str(&format!("\n\nCaused by:\n    {cause}")),
+        _ => {
+            message.push_str("\n\nCaused by:");
+            for (n, cause) in causes.iter().enumerate() {
+                message.push_str(&format!("\n    {n}: {cause}"));
+            }
+        }
+    }
+    message
+}
+
+/// Prints why the command line couldn't be parsed, or the help asked for, and the exit code to
+/// go with it.
+pub fn parse_failure(failure: ParseFailure) -> ExitCode {
+    failure.print_message(100);
+    match failure {
+        ParseFailure::Stderr(_) => ExitCode::from(USAGE),
+        ParseFailure::Stdout(..) | ParseFailure::Completion(_) => ExitCode::SUCCESS,
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+use crate::{OutputFormat, Params};
+
+impl Params {
+    /// Whether FFMpeg can be run, by asking it for its version.
+    fn has_ffmpeg(&self) -> bool {
+        Command::new(&self.ffmpeg_path)
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+
+    /// Checks that FFMpeg can be run for a video output before anything is rendered. If it can't,
+    /// the output is written as webp with the built in encoder instead, with --ffmpeg-fallback or
+    /// when asked on a terminal. Returns the output to write.
+    pub fn check_ffmpeg(self, format: OutputFormat) -> Result<(Self, OutputFormat)> {
+        if !matches!(format, OutputFormat::Webm | OutputFormat::Mp4) || self.has_ffmpeg() {
+            return Ok((self, format));
+        }
+        let filename = match self.is_stdout() {
+            true => self.filename.clone(),
+            false => self.filename.with_extension("webp"),
+        };
+        let missing = format!(
+            "FFMpeg can't be run from '{}', and it's needed for {} outputs",
+            self.ffmpeg_path.display(),
+            format.name()
+        );
+        let fallback = self.ffmpeg_fallback
+            || ask(&format!(
+                "{missing}. Write {} with the built in WebP encoder instead?",
+                filename.display()
+            ))?;
+        if !fallback {
+            bail!(
+                "{missing}. Install it from https://ffmpeg.org/download.html or with a package \
+                 manager, like `apt install ffmpeg`, `brew install ffmpeg`, or `winget install \
+                 ffmpeg`, and make sure it's on your PATH or pass --ffmpeg-path. To write {} with \
+                 the built in WebP encoder instead, pass --ffmpeg-fallback.",
+                filename.display()
+            );
+        }
+        if self.ffmpeg_fallback {
+            warn!(
+                "{missing}, writing {} with the built in WebP encoder instead",
+                filename.display()
+            );
+        }
+        let params = Self {
+            filename,
+            format: Some(OutputFormat::Webp),
+            ..self
+        };
+        Ok((params, OutputFormat::Webp))
+    }
+}
+
+/// Asks a yes or no question, when there's someone at a terminal to answer it.
+pub fn ask(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
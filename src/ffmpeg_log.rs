@@ -0,0 +1,83 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+
+use crate::exit::FfmpegError;
+
+/// Most lines FFMpeg printed that are kept for the error when it fails. Its error is always at
+/// the end, after the warnings leading up to it.
+const KEPT_LINES: usize = 10;
+
+/// What FFMpeg prints, read as it runs instead of being drawn over the progress bars. Each line is
+/// logged with --verbose, and the last few are kept to say why it failed if it does.
+#[derive(Default)]
+pub struct FfmpegLog(Vec<JoinHandle<VecDeque<String>>>);
+
+impl FfmpegLog {
+    /// Starts reading whichever of FFMpeg's stdout and stderr were piped and haven't been taken,
+    /// like stdout when frames are read back from it.
+    pub fn capture(ffmpeg: &mut Child) -> Self {
+        let stdout = ffmpeg.stdout.take().map(read_lines);
+        let stderr = ffmpeg.stderr.take().map(read_lines);
+        Self(stdout.into_iter().chain(stderr).collect())
+    }
+
+    /// Checks how FFMpeg exited once it's finished, failing with `failed` and the last lines it
+    /// printed if it didn't succeed.
+    pub fn check(self, status: ExitStatus, failed: &str) -> Result<()> {
+        let lines: Vec<String> = self
+            .0
+            .into_iter()
+            .flat_map(|reader| reader.join().unwrap_or_default())
+            .collect();
+        match (status.success(), &lines[..]) {
+            (true, _) => Ok(()),
+            (false, []) => bail!("{failed} with {status}"),
+            (false, _) => bail!("{failed} with {status}:\n  {}", lines.join("\n  ")),
+        }
+    }
+}
+
+/// Reads `reader` a line at a time, logging each one and returning the last few once it ends.
+fn read_lines(reader: impl Read + Send + 'static) -> JoinHandle<VecDeque<String>> {
+    thread::spawn(move || {
+        let mut last = VecDeque::with_capacity(KEPT_LINES);
+        // File names and metadata FFMpeg echoes back aren't always UTF-8
+        for line in BufReader::new(reader).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            let line = String::from_utf8_lossy(&line).trim_end().to_owned();
+            if line.is_empty() {
+                continue;
+            }
+            debug!("FFMpeg: {line}");
+            if last.len() == KEPT_LINES {
+                last.pop_front();
+            }
+            last.push_back(line);
+        }
+        last
+    })
+}
+
+/// Runs FFMpeg without feeding it anything, like to join videos, failing with `failed` and what
+/// it printed if it doesn't succeed.
+pub fn run(mut command: Command, failed: &str) -> Result<()> {
+    debug!("Running {command:?}");
+    let mut ffmpeg = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(FfmpegError("Failed to run FFMpeg"))?;
+    let log = FfmpegLog::capture(&mut ffmpeg);
+    let status = ffmpeg.wait().context(FfmpegError("FFMpeg failed"))?;
+    log.check(status, failed)
+}
@@ -0,0 +1,592 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+/// Deepest composite glyphs are followed, in case a broken font refers back to itself.
+const MAX_COMPONENT_DEPTH: usize = 8;
+
+/// A TrueType font, read just far enough to draw a line of text: the character map, glyph
+/// outlines, and advance widths. There's no kerning or shaping, which labels can do without.
+pub struct Font {
+    data: Vec<u8>,
+    units_per_em: f32,
+    ascent: f32,
+    descent: f32,
+    glyph_count: u16,
+    long_offsets: bool,
+    h_metrics: u16,
+    glyf: usize,
+    loca: usize,
+    hmtx: usize,
+    cmap: CharMap,
+}
+
+/// The subtable of the character map that glyphs are looked up in.
+enum CharMap {
+    /// Segments of the Basic Multilingual Plane, at this offset.
+    Segments(usize),
+    /// Groups of any Unicode characters, at this offset.
+    Groups(usize),
+}
+
+/// A point of an outline, in font units with y up.
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    x: f32,
+    y: f32,
+    on_curve: bool,
+}
+
+impl Font {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(data).with_context(|| format!("Can't read {} as a font", path.display()))
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Self> {
+        let mut font = Self {
+            data,
+            units_per_em: 0.0,
+            ascent: 0.0,
+            descent: 0.0,
+            glyph_count: 0,
+            long_offsets: false,
+            h_metrics: 0,
+            glyf: 0,
+            loca: 0,
+            hmtx: 0,
+            cmap: CharMap::Segments(0),
+        };
+        // Collections hold several fonts, of which the first is used
+        let start = match &font.data.get(..4) {
+            Some(b"ttcf") => font.u32(12)? as usize,
+            _ => 0,
+        };
+        match font.u32(start)? {
+            0x0001_0000 | 0x7472_7565 => {}
+            0x4f54_544f => bail!("it has PostScript outlines, only TrueType fonts are supported"),
+            _ => bail!("it isn't a TrueType font"),
+        }
+
+        let head = font.table(start, b"head")?;
+        let hhea = font.table(start, b"hhea")?;
+        let maxp = font.table(start, b"maxp")?;
+        font.units_per_em = font.u16(head + 18)? as f32;
+        font.long_offsets = font.u16(head + 50)? == 1;
+        font.ascent = font.u16(hhea + 4)? as i16 as f32;
+        font.descent = font.u16(hhea + 6)? as i16 as f32;
+        font.h_metrics = font.u16(hhea + 34)?;
+        font.glyph_count = font.u16(maxp + 4)?;
+        font.glyf = font.table(start, b"glyf")?;
+        font.loca = font.table(start, b"loca")?;
+        font.hmtx = font.table(start, b"hmtx")?;
+        font.cmap = font.char_map(font.table(start, b"cmap")?)?;
+        if font.units_per_em == 0.0 || font.h_metrics == 0 {
+            bail!("its metrics are broken");
+        }
+        Ok(font)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        match self.data.get(offset..offset + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => bail!("it's cut short"),
+        }
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok((self.u16(offset)? as u32) << 16 | self.u16(offset + 2)? as u32)
+    }
+
+    /// Where the table tagged `tag` starts.
+    fn table(&self, start: usize, tag: &[u8; 4]) -> Result<usize> {
+        let tables = self.u16(start + 4)? as usize;
+        for record in (0..tables).map(|i| start + 12 + i * 16) {
+            if self.data.get(record..record + 4) == Some(tag) {
+                return Ok(self.u32(record + 8)? as usize);
+            }
+        }
+        bail!("it has no {} table", String::from_utf8_lossy(tag))
+    }
+
+    /// Picks a Unicode subtable of the character map, preferring one that covers every plane.
+    fn char_map(&self, cmap: usize) -> Result<CharMap> {
+        let mut found = None;
+        for record in (0..self.u16(cmap + 2)? as usize).map(|i| cmap + 4 + i * 8) {
+            let platform = self.u16(record)?;
+            let encoding = self.u16(record + 2)?;
+            let subtable = cmap + self.u32(record + 4)? as usize;
+            // Unicode, and Windows' Unicode BMP and full repertoire encodings
+            if !(platform == 0 || (platform == 3 && matches!(encoding, 1 | 10))) {
+                continue;
+            }
+            match self.u16(subtable)? {
+                12 => return Ok(CharMap::Groups(subtable)),
+                4 => found = Some(CharMap::Segments(subtable)),
+                _ => {}
+            }
+        }
+        found.context("it has no Unicode character map")
+    }
+
+    /// The glyph for a character, or the missing glyph if the font doesn't have it.
+    fn glyph(&self, c: char) -> Result<u16> {
+        let c = c as u32;
+        match self.cmap {
+            CharMap::Segments(table) => {
+                if c > 0xffff {
+                    return Ok(0);
+                }
+                let segments = self.u16(table + 6)? as usize / 2;
+                let ends = table + 14;
+                let starts = ends + segments * 2 + 2;
+                let deltas = starts + segments * 2;
+                let range_offsets = deltas + segments * 2;
+                for i in 0..segments {
+                    if (self.u16(ends + i * 2)? as u32) < c {
+                        continue;
+                    }
+                    let start = self.u16(starts + i * 2)? as u32;
+                    if start > c {
+                        return Ok(0);
+                    }
+                    let delta = self.u16(deltas + i * 2)?;
+                    let range_offset = self.u16(range_offsets + i * 2)? as usize;
+                    if range_offset == 0 {
+                        return Ok((c as u16).wrapping_add(delta));
+                    }
+                    // Offset from where the range offset itself is kept
+                    let index = range_offsets + i * 2 + range_offset + (c - start) as usize * 2;
+                    return Ok(match self.u16(index)? {
+                        0 => 0,
+                        glyph => glyph.wrapping_add(delta),
+                    });
+                }
+                Ok(0)
+            }
+            CharMap::Groups(table) => {
+                for group in (0..self.u32(table + 12)? as usize).map(|i| table + 16 + i * 12) {
+                    let start = self.u32(group)?;
+                    if (start..=self.u32(group + 4)?).contains(&c) {
+                        return Ok((self.u32(group + 8)? + c - start) as u16);
+                    }
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// How far along the line a glyph moves the next one, in font units.
+    fn advance(&self, glyph: u16) -> Result<f32> {
+        let metric = glyph.min(self.h_metrics - 1) as usize;
+        Ok(self.u16(self.hmtx + metric * 4)? as f32)
+    }
+
+    /// A glyph's contours, each a closed loop of points.
+    fn outline(&self, glyph: u16, depth: usize) -> Result<Vec<Vec<Point>>> {
+        if glyph >= self.glyph_count || depth > MAX_COMPONENT_DEPTH {
+            return Ok(Vec::new());
+        }
+        let (start, end) = match self.long_offsets {
+            true => (
+                self.u32(self.loca + glyph as usize * 4)? as usize,
+                self.u32(self.loca + glyph as usize * 4 + 4)? as usize,
+            ),
+            false => (
+                self.u16(self.loca + glyph as usize * 2)? as usize * 2,
+                self.u16(self.loca + glyph as usize * 2 + 2)? as usize * 2,
+            ),
+        };
+        // Glyphs like spaces have no outline at all
+        if end <= start {
+            return Ok(Vec::new());
+        }
+        let offset = self.glyf + start;
+        match self.u16(offset)? as i16 {
+            contours if contours >= 0 => self.simple_outline(offset, contours as usize),
+            _ => self.composite_outline(offset, depth),
+        }
+    }
+
+    fn simple_outline(&self, offset: usize, contours: usize) -> Result<Vec<Vec<Point>>> {
+        let ends: Vec<usize> = (0..contours)
+            .map(|i| Ok(self.u16(offset + 10 + i * 2)? as usize))
+            .collect::<Result<_>>()?;
+        let points = ends.last().map_or(0, |end| end + 1);
+        let instructions = offset + 10 + contours * 2;
+        let mut at = instructions + 2 + self.u16(instructions)? as usize;
+
+        let mut flags = Vec::with_capacity(points);
+        while flags.len() < points {
+            let flag = *self.data.get(at).context("it's cut short")?;
+            at += 1;
+            let repeats = match flag & 0x08 {
+                0 => 0,
+                _ => {
+                    at += 1;
+                    *self.data.get(at - 1).context("it's cut short")? as usize
+                }
+            };
+            flags.extend(std::iter::repeat_n(flag, repeats + 1));
+        }
+        flags.truncate(points);
+
+        // Each coordinate is a change from the last, as a byte with its sign in the flag, as the
+        // same as the last, or as a signed 16-bit number
+        let mut coordinates = |short: u8, same_or_positive: u8| -> Result<Vec<f32>> {
+            let mut value = 0i32;
+            let mut values = Vec::with_capacity(points);
+            for flag in &flags {
+                if flag & short != 0 {
+                    let delta = *self.data.get(at).context("it's cut short")? as i32;
+                    at += 1;
+                    value += if flag & same_or_positive != 0 {
+                        delta
+                    } else {
+                        -delta
+                    };
+                } else if flag & same_or_positive == 0 {
+                    value += self.u16(at)? as i16 as i32;
+                    at += 2;
+                }
+                values.push(value as f32);
+            }
+            Ok(values)
+        };
+        let xs = coordinates(0x02, 0x10)?;
+        let ys = coordinates(0x04, 0x20)?;
+
+        let mut outline = Vec::with_capacity(contours);
+        let mut first = 0;
+        for end in ends {
+            if end < first || end >= points {
+                bail!("a glyph's contours are broken");
+            }
+            outline.push(
+                (first..=end)
+                    .map(|i| Point {
+                        x: xs[i],
+                        y: ys[i],
+                        on_curve: flags[i] & 0x01 != 0,
+                    })
+                    .collect(),
+            );
+            first = end + 1;
+        }
+        Ok(outline)
+    }
+
+    /// The outlines of a glyph made of other glyphs, each moved and scaled into place.
+    fn composite_outline(&self, offset: usize, depth: usize) -> Result<Vec<Vec<Point>>> {
+        let mut outline = Vec::new();
+        let mut at = offset + 10;
+        loop {
+            let flags = self.u16(at)?;
+            let glyph = self.u16(at + 2)?;
+            at += 4;
+            let (dx, dy) = match flags & 0x0001 {
+                0 => {
+                    let bytes = self.data.get(at..at + 2).context("it's cut short")?;
+                    at += 2;
+                    (bytes[0] as i8 as f32, bytes[1] as i8 as f32)
+                }
+                _ => {
+                    at += 4;
+                    (
+                        self.u16(at - 4)? as i16 as f32,
+                        self.u16(at - 2)? as i16 as f32,
+                    )
+                }
+            };
+            let f2dot14 =
+                |offset: usize| Ok::<_, anyhow::Error>(self.u16(offset)? as i16 as f32 / 16384.0);
+            let [a, b, c, d] = if flags & 0x0008 != 0 {
+                at += 2;
+                let scale = f2dot14(at - 2)?;
+                [scale, 0.0, 0.0, scale]
+            } else if flags & 0x0040 != 0 {
+                at += 4;
+                [f2dot14(at - 4)?, 0.0, 0.0, f2dot14(at - 2)?]
+            } else if flags & 0x0080 != 0 {
+                at += 8;
+                [
+                    f2dot14(at - 8)?,
+                    f2dot14(at - 6)?,
+                    f2dot14(at - 4)?,
+                    f2dot14(at - 2)?,
+                ]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            };
+            // Components placed by matching up points are rare enough to place at the origin
+            let (dx, dy) = match flags & 0x0002 {
+                0 => (0.0, 0.0),
+                _ => (dx, dy),
+            };
+            for contour in self.outline(glyph, depth + 1)? {
+                outline.push(
+                    contour
+                        .into_iter()
+                        .map(|p| Point {
+                            x: a * p.x + c * p.y + dx,
+                            y: b * p.x + d * p.y + dy,
+                            on_curve: p.on_curve,
+                        })
+                        .collect(),
+                );
+            }
+            if flags & 0x0020 == 0 {
+                return Ok(outline);
+            }
+        }
+    }
+
+    /// Draws a line of text `size` pixels tall, from the top of the tallest letters to the
+    /// bottom of the lowest. Returns its width, height, and how much of each pixel it covers.
+    pub fn render(&self, text: &str, size: f32) -> Result<(u32, u32, Vec<f32>)> {
+        let scale = size / (self.ascent - self.descent).max(1.0);
+        let mut glyphs = Vec::new();
+        let mut pen = 1.0;
+        for c in text.chars() {
+            let glyph = self.glyph(c)?;
+            glyphs.push((pen, glyph));
+            pen += self.advance(glyph)? * scale;
+        }
+        // A pixel of room around it, so antialiased edges aren't cut off
+        let width = pen.ceil() as u32 + 1;
+        let height = size.ceil() as u32 + 2;
+        let baseline = 1.0 + self.ascent * scale;
+
+        let mut raster = Raster::new(width as usize, height as usize);
+        for (pen, glyph) in glyphs {
+            for contour in self.outline(glyph, 0)? {
+                let points: Vec<Point> = contour
+                    .into_iter()
+                    .map(|p| Point {
+                        x: pen + p.x * scale,
+                        y: baseline - p.y * scale,
+                        on_curve: p.on_curve,
+                    })
+                    .collect();
+                raster.contour(&points);
+            }
+        }
+        Ok((width, height, raster.coverage()))
+    }
+}
+
+/// Fills outlines by adding up how much of each pixel every edge covers, then summing along
+/// each row, so the inside of a shape is as covered as its edges say.
+struct Raster {
+    width: usize,
+    height: usize,
+    area: Vec<f32>,
+}
+
+impl Raster {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            area: vec![0.0; width * height],
+        }
+    }
+
+    /// Adds a closed contour of straight lines and quadratic curves. Two points off the curve in
+    /// a row have an implied point on it halfway between them.
+    fn contour(&mut self, points: &[Point]) {
+        let Some(start) = points.iter().position(|p| p.on_curve) else {
+            return;
+        };
+        let mut last = (points[start].x, points[start].y);
+        let mut control: Option<(f32, f32)> = None;
+        for i in 1..=points.len() {
+            let p = points[(start + i) % points.len()];
+            let point = (p.x, p.y);
+            match (p.on_curve, control) {
+                (true, None) => {
+                    self.line(last, point);
+                    last = point;
+                }
+                (true, Some(c)) => {
+                    self.curve(last, c, point);
+                    last = point;
+                    control = None;
+                }
+                (false, None) => control = Some(point),
+                (false, Some(c)) => {
+                    let mid = ((c.0 + point.0) / 2.0, (c.1 + point.1) / 2.0);
+                    self.curve(last, c, mid);
+                    last = mid;
+                    control = Some(point);
+                }
+            }
+        }
+        if let Some(c) = control {
+            self.curve(last, c, (points[start].x, points[start].y));
+        }
+    }
+
+    /// Adds a quadratic curve as enough straight lines that it looks smooth.
+    fn curve(&mut self, from: (f32, f32), control: (f32, f32), to: (f32, f32)) {
+        let deviation = ((from.0 - 2.0 * control.0 + to.0).powi(2)
+            + (from.1 - 2.0 * control.1 + to.1).powi(2))
+        .sqrt();
+        let steps = ((deviation * 4.0).sqrt().ceil() as usize).clamp(1, 64);
+        let mut last = from;
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            let u = 1.0 - t;
+            let point = (
+                u * u * from.0 + 2.0 * u * t * control.0 + t * t * to.0,
+                u * u * from.1 + 2.0 * u * t * control.1 + t * t * to.1,
+            );
+            self.line(last, point);
+            last = point;
+        }
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32)) {
+        if from.1 == to.1 {
+            return;
+        }
+        let (direction, from, to) = match from.1 < to.1 {
+            true => (1.0, from, to),
+            false => (-1.0, to, from),
+        };
+        let dxdy = (to.0 - from.0) / (to.1 - from.1);
+        let mut x = from.0;
+        if from.1 < 0.0 {
+            x -= from.1 * dxdy;
+        }
+        let rows = from.1.max(0.0) as usize..(to.1.ceil().max(0.0) as usize).min(self.height);
+        for y in rows {
+            let dy = (y as f32 + 1.0).min(to.1) - (y as f32).max(from.1);
+            let next_x = x + dxdy * dy;
+            let d = dy * direction;
+            let (x0, x1) = match x < next_x {
+                true => (x, next_x),
+                false => (next_x, x),
+            };
+            let x0_floor = x0.floor();
+            let x0_index = x0_floor as isize;
+            let x1_ceil = x1.ceil();
+            let x1_index = x1_ceil as isize;
+            if x1_index <= x0_index + 1 {
+                // Within a single pixel, split between it and the next by where it crosses
+                let middle = 0.5 * (x + next_x) - x0_floor;
+                self.add(y, x0_index, d - d * middle);
+                self.add(y, x0_index + 1, d * middle);
+            } else {
+                let slope = (x1 - x0).recip();
+                let x0_fraction = x0 - x0_floor;
+                let first = 0.5 * slope * (1.0 - x0_fraction).powi(2);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let last = 0.5 * slope * x1_fraction.powi(2);
+                self.add(y, x0_index, d * first);
+                if x1_index == x0_index + 2 {
+                    self.add(y, x0_index + 1, d * (1.0 - first - last));
+                } else {
+                    let second = slope * (1.5 - x0_fraction);
+                    self.add(y, x0_index + 1, d * (second - first));
+                    for xi in x0_index + 2..x1_index - 1 {
+                        self.add(y, xi, d * slope);
+                    }
+                    let before_last = second + (x1_index - x0_index - 3) as f32 * slope;
+                    self.add(y, x1_index - 1, d * (1.0 - before_last - last));
+                }
+                self.add(y, x1_index, d * last);
+            }
+            x = next_x;
+        }
+    }
+
+    /// Adds `area` to pixel `x` of row `y`. What an edge covers left of the raster still counts
+    /// for every pixel after it in the row, so it goes in the first one, and what it covers right
+    /// of the raster is of nothing that's drawn.
+    fn add(&mut self, y: usize, x: isize, area: f32) {
+        if y >= self.height || x >= self.width as isize {
+            return;
+        }
+        self.area[y * self.width + x.max(0) as usize] += area;
+    }
+
+    /// How much of each pixel is covered, from 0 to 1.
+    fn coverage(self) -> Vec<f32> {
+        self.area
+            .chunks(self.width)
+            .flat_map(|row| {
+                let mut sum = 0.0;
+                row.iter().map(move |area| {
+                    sum += area;
+                    sum.abs().min(1.0)
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills the rectangle from `left`, `top` to `right`, `bottom` in a raster `width` by
+    /// `height`.
+    fn fill(
+        width: usize,
+        height: usize,
+        (left, top, right, bottom): (f32, f32, f32, f32),
+    ) -> Vec<f32> {
+        let corner = |x, y| Point {
+            x,
+            y,
+            on_curve: true,
+        };
+        let mut raster = Raster::new(width, height);
+        raster.contour(&[
+            corner(left, top),
+            corner(right, top),
+            corner(right, bottom),
+            corner(left, bottom),
+        ]);
+        raster.coverage()
+    }
+
+    #[test]
+    fn fills_a_square() {
+        let coverage = fill(4, 4, (1.0, 1.0, 3.0, 3.0));
+        #[rustfmt::skip]
+        let expected = [
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 1.0, 0.0,
+            0.0, 1.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ];
+        assert_eq!(coverage, expected);
+    }
+
+    #[test]
+    fn covers_part_of_a_pixel() {
+        let coverage = fill(2, 1, (0.5, 0.0, 2.0, 1.0));
+        assert!((coverage[0] - 0.5).abs() < 1e-6);
+        assert!((coverage[1] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn keeps_coverage_left_of_the_raster() {
+        let coverage = fill(3, 2, (-1.5, 0.0, 2.0, 1.0));
+        assert_eq!(coverage, [1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn clips_coverage_right_of_the_raster() {
+        let coverage = fill(3, 2, (1.0, 0.0, 5.5, 1.0));
+        assert_eq!(coverage, [0.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn clips_coverage_above_and_below_the_raster() {
+        let coverage = fill(2, 2, (0.0, -3.0, 1.0, 4.0));
+        assert_eq!(coverage, [1.0, 0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::Result;
+
+use crate::video::{self, EncoderSupport};
+
+/// Prints which output formats and encoders can be used: those built into this build, and those
+/// the FFMpeg at `ffmpeg_path` can encode with on this machine.
+pub fn list(ffmpeg_path: &Path) -> Result<()> {
+    let version = ffmpeg_version(ffmpeg_path);
+    let encoders = match version {
+        Some(_) => video::probe_encoders(ffmpeg_path)?,
+        None => Vec::new(),
+    };
+    let video = |container: &str| {
+        let available: Vec<&str> = encoders
+            .iter()
+            .filter(|(_, picked_by, support)| {
+                picked_by.split(' ').next() == Some(container)
+                    && *support == EncoderSupport::Available
+            })
+            .map(|(name, _, _)| *name)
+            .collect();
+        match (&version, &available[..]) {
+            (None, _) => "no, FFMpeg can't be run".to_owned(),
+            (Some(_), []) => "no, FFMpeg has no encoder for it".to_owned(),
+            (Some(_), _) => format!("yes, with FFMpeg: {}", available.join(", ")),
+        }
+    };
+    let gifski = match cfg!(feature = "gifski") {
+        true => "yes, built in, and with gifski for --gifski",
+        false => "yes, built in (build with --features gifski for --gifski)",
+    };
+
+    println!("Output formats:");
+    for (format, support) in [
+        ("gif", gifski.to_owned()),
+        ("webp", "yes, built in".to_owned()),
+        ("webm", video("webm")),
+        ("mp4", video("mp4")),
+        (
+            "frames",
+            "yes, built in: png, jpeg, tiff, bmp, webp, or exr".to_owned(),
+        ),
+        ("exr", "yes, built in".to_owned()),
+        ("zip", "yes, built in".to_owned()),
+        ("tar", "yes, built in".to_owned()),
+    ] {
+        println!("  {format:<8}{support}");
+    }
+
+    println!();
+    let Some(version) = version else {
+        println!(
+            "FFMpeg can't be run from '{}', install it or pass --ffmpeg-path for webm and mp4 \
+             outputs",
+            ffmpeg_path.display()
+        );
+        return Ok(());
+    };
+    println!("FFMpeg: {version}");
+    println!("Video encoders:");
+    for (name, picked_by, support) in encoders {
+        let support = match support {
+            EncoderSupport::Available => "yes",
+            EncoderSupport::NoHardware => "no, it failed to encode a test frame",
+            EncoderSupport::NotBuilt => "no, not in this FFMpeg",
+        };
+        println!("  {name:<19}{picked_by:<28}{support}");
+    }
+    Ok(())
+}
+
+/// The first line FFMpeg prints for -version, or none if it can't be run.
+pub fn ffmpeg_version(ffmpeg_path: &Path) -> Option<String> {
+    let output = Command::new(ffmpeg_path)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    Some(version.lines().next().unwrap_or_default().to_owned())
+}
@@ -0,0 +1,472 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Cursor, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    DynamicImage, ImageEncoder, ImageFormat, RgbaImage,
+};
+use palette::LinSrgba;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    outputs::FrameSink,
+    progress::Progress,
+    resume::{self, write_frame},
+    video::BitDepth,
+    Output, OutputFormat, Params,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameFormat {
+    Png,
+    Jpeg,
+    Tiff,
+    Bmp,
+    Webp,
+    Exr,
+}
+
+impl FrameFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Tiff => "tiff",
+            Self::Bmp => "bmp",
+            Self::Webp => "webp",
+            Self::Exr => "exr",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Tiff => ImageFormat::Tiff,
+            Self::Bmp => ImageFormat::Bmp,
+            Self::Webp => ImageFormat::WebP,
+            Self::Exr => ImageFormat::OpenExr,
+        }
+    }
+
+    /// Whether the format compresses by itself, so there's no point compressing it again.
+    fn is_compressed(self) -> bool {
+        !matches!(self, Self::Tiff | Self::Bmp)
+    }
+
+    fn supports_bit_depth(self, bit_depth: BitDepth) -> bool {
+        match (self, bit_depth) {
+            // Always 32-bit float
+            (Self::Exr, _) => true,
+            (_, BitDepth::Eight) => true,
+            (Self::Png | Self::Tiff, BitDepth::Sixteen) => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for FrameFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(Self::Png),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "tiff" | "tif" => Ok(Self::Tiff),
+            "bmp" => Ok(Self::Bmp),
+            "webp" => Ok(Self::Webp),
+            "exr" => Ok(Self::Exr),
+            _ => Err(format!(
+                "unknown frame format '{s}', expected png, jpeg, tiff, bmp, webp, or exr"
+            )),
+        }
+    }
+}
+
+/// A printf style frame filename like `frame_%06d.png`, as used by ffmpeg image sequences.
+#[derive(Debug, Clone)]
+pub struct FramePattern {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl FramePattern {
+    fn format(&self, number: usize) -> String {
+        format!(
+            "{}{number:0width$}{}",
+            self.prefix,
+            self.suffix,
+            width = self.width
+        )
+    }
+}
+
+impl FramePattern {
+    /// Whether `name` is this pattern's name for some frame number.
+    fn matches(&self, name: &str) -> bool {
+        name.strip_prefix(&self.prefix)
+            .and_then(|rest| rest.strip_suffix(&self.suffix))
+            .is_some_and(|number| is_frame_number(number, self.width))
+    }
+}
+
+/// Whether `number` is a frame number written at least `width` digits wide.
+fn is_frame_number(number: &str, width: usize) -> bool {
+    !number.is_empty() && number.len() >= width && number.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl FromStr for FramePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid frame pattern '{s}', expected one %d or %0Nd");
+        let (prefix, rest) = s.split_once('%').ok_or_else(invalid)?;
+        let (width, suffix) = rest.split_once('d').ok_or_else(invalid)?;
+        let width = match width {
+            "" => 0,
+            width if width.starts_with('0') => width.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        if suffix.contains('%') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            width,
+            suffix: suffix.to_string(),
+        })
+    }
+}
+
+/// Colors along the curve at the precision a frame format is written in.
+enum FrameColors {
+    Linear(Vec<[f32; 4]>),
+    Sixteen(Vec<[u16; 4]>),
+    Eight(Vec<[u8; 4]>),
+}
+
+/// An archive that frames are streamed into, instead of a directory.
+#[derive(Debug, Clone, Copy)]
+pub enum Archive {
+    Zip,
+    Tar,
+}
+
+enum ArchiveWriter {
+    Zip(Box<ZipWriter<BufWriter<File>>>),
+    Tar(tar::Builder<Output>),
+}
+
+impl ArchiveWriter {
+    /// Starts the archive in the output. Only tar can go to stdout, since zip needs to seek back.
+    fn create(archive: Archive, params: &Params) -> Result<Self> {
+        Ok(match archive {
+            Archive::Zip => {
+                let file = File::create(&params.filename).context("Failed to open file")?;
+                Self::Zip(Box::new(ZipWriter::new(BufWriter::new(file))))
+            }
+            Archive::Tar => Self::Tar(tar::Builder::new(params.create_output()?)),
+        })
+    }
+
+    fn add(&mut self, name: &str, data: &[u8], compress: bool, mtime: u64) -> Result<()> {
+        match self {
+            Self::Zip(zip) => {
+                let method = if compress {
+                    CompressionMethod::Deflated
+                } else {
+                    CompressionMethod::Stored
+                };
+                zip.start_file(
+                    name,
+                    SimpleFileOptions::default().compression_method(method),
+                )?;
+                zip.write_all(data)?;
+            }
+            Self::Tar(tar) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(mtime);
+                tar.append_data(&mut header, name, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Zip(zip) => zip.finish()?.flush()?,
+            Self::Tar(tar) => tar.into_inner()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+pub struct FrameDirSink<'a> {
+    params: &'a Params,
+    format: FrameFormat,
+    out_dir: PathBuf,
+    i: usize,
+}
+
+impl FrameSink for FrameDirSink<'_> {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()> {
+        let data = self.params.encode_image(frame, self.format)?;
+        let name = self.params.frame_name(self.i, self.format);
+        write_frame(self.out_dir.join(name), &data)
+            .with_context(|| format!("Failed to save frame {}", self.i))?;
+        self.i += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.params.finish_resumable_dir(&self.out_dir)
+    }
+}
+
+pub struct ArchiveSink<'a> {
+    params: &'a Params,
+    format: FrameFormat,
+    writer: ArchiveWriter,
+    i: usize,
+}
+
+impl ArchiveSink<'_> {
+    /// Adds the next frame, already encoded in the archive's frame format.
+    fn add_encoded(&mut self, data: &[u8]) -> Result<()> {
+        let name = self.params.frame_name(self.i, self.format);
+        self.writer
+            .add(
+                &name,
+                data,
+                !self.format.is_compressed(),
+                self.params.archive_mtime(),
+            )
+            .with_context(|| format!("Failed to add frame {} to archive", self.i))?;
+        self.i += 1;
+        Ok(())
+    }
+}
+
+impl FrameSink for ArchiveSink<'_> {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()> {
+        let data = self.params.encode_image(frame, self.format)?;
+        self.add_encoded(&data)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.writer.finish().context("Failed to finish archive")
+    }
+}
+
+impl Params {
+    /// Saves each frame to a directory named after the output without its extension.
+    pub fn write_frames<F>(&self, color: F, format: FrameFormat) -> Result<()>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        self.check_frame_bit_depth(format)?;
+
+        let colors = self.frame_colors(color, format);
+        let out_dir = self.create_frame_dir(format)?;
+        let frames = self.shard_range(self.frames);
+        let written = frames
+            .clone()
+            .filter(|&i| self.already_written(&out_dir.join(self.frame_name(i, format))))
+            .count();
+        let progress = Progress::resumed("write", frames.len(), written);
+        frames.into_par_iter().try_for_each(|i| -> Result<()> {
+            let path = out_dir.join(self.frame_name(i, format));
+            if self.already_written(&path) {
+                return Ok(());
+            }
+            let frame = self.encode_frame(&colors, i, format)?;
+            write_frame(path, &frame).with_context(|| format!("Failed to save frame {i}"))?;
+            progress.update_written(1, frame.len());
+            Ok(())
+        })?;
+        self.finish_resumable_dir(&out_dir)
+    }
+
+    /// Encodes a single frame as an image, without writing it anywhere.
+    pub fn encode_single_frame<F>(&self, color: F, i: usize, format: FrameFormat) -> Result<Vec<u8>>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        self.check_frame_bit_depth(format)?;
+        let colors = self.frame_colors(color, format);
+        self.encode_frame(&colors, i, format)
+    }
+
+    /// Streams each frame into an archive in order, as soon as it's encoded.
+    pub fn write_frame_archive<F>(
+        &self,
+        color: F,
+        format: FrameFormat,
+        archive: Archive,
+    ) -> Result<()>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        self.check_frame_bit_depth(format)?;
+
+        let colors = self.frame_colors(color, format);
+        let mut sink = self.archive_sink(format, archive)?;
+        self.stream_frames(
+            |i| self.encode_frame(&colors, i, format),
+            |frame| sink.add_encoded(&frame?),
+        )?;
+        Box::new(sink).finish()
+    }
+
+    /// Saves frames rendered elsewhere into a directory, like when they're shared with other
+    /// outputs.
+    pub fn frame_dir_sink(&self, format: FrameFormat) -> Result<FrameDirSink<'_>> {
+        Ok(FrameDirSink {
+            params: self,
+            format,
+            out_dir: self.create_frame_dir(format)?,
+            i: 0,
+        })
+    }
+
+    pub fn archive_sink(&self, format: FrameFormat, archive: Archive) -> Result<ArchiveSink<'_>> {
+        Ok(ArchiveSink {
+            params: self,
+            format,
+            writer: ArchiveWriter::create(archive, self)?,
+            i: 0,
+        })
+    }
+
+    /// Makes the directory named after the output without its extension, in --frames-dir if it's
+    /// given. With --resume, an existing one is written into. With --force, the frames already in
+    /// it are removed first, so none are left over from a longer render, and anything else is
+    /// left alone.
+    fn create_frame_dir(&self, format: FrameFormat) -> Result<PathBuf> {
+        let out_dir = self.frame_dir();
+        if !self.resume && out_dir.is_dir() {
+            resume::remove_own_files(&out_dir, |name| self.is_frame_name(name, format))?;
+        }
+        fs::create_dir_all(&out_dir).context("Failed to create output dir")?;
+        let format = match format {
+            FrameFormat::Exr => OutputFormat::Exr,
+            _ => OutputFormat::Frames,
+        };
+        self.start_resumable_dir(&out_dir, format)?;
+        Ok(out_dir)
+    }
+
+    pub fn frame_dir(&self) -> PathBuf {
+        self.in_frames_dir(self.filename.with_extension(""))
+    }
+
+    /// Moves a directory that's put next to the output into --frames-dir, if it's given.
+    pub fn in_frames_dir(&self, dir: PathBuf) -> PathBuf {
+        match (&self.frames_dir, dir.file_name()) {
+            (Some(frames_dir), Some(name)) => frames_dir.join(name),
+            _ => dir,
+        }
+    }
+
+    /// The name of a frame's file, following --frame-pattern and --frame-start.
+    pub fn frame_name(&self, i: usize, format: FrameFormat) -> String {
+        let number = self.frame_start + i;
+        match &self.frame_pattern {
+            Some(pattern) => pattern.format(number),
+            None => format!("frame_{number:05}.{}", format.extension()),
+        }
+    }
+
+    /// Whether `name` is a frame's file, or what's left of one a render was writing, for any frame
+    /// number.
+    fn is_frame_name(&self, name: &str, format: FrameFormat) -> bool {
+        let name = name.strip_suffix(".partial").unwrap_or(name);
+        match &self.frame_pattern {
+            Some(pattern) => pattern.matches(name),
+            None => name
+                .strip_prefix("frame_")
+                .and_then(|rest| rest.strip_suffix(&format!(".{}", format.extension())))
+                .is_some_and(|number| is_frame_number(number, 5)),
+        }
+    }
+
+    fn check_frame_bit_depth(&self, format: FrameFormat) -> Result<()> {
+        if !format.supports_bit_depth(self.bit_depth) {
+            bail!(
+                "{} frames can't be {}-bit",
+                format.extension(),
+                self.bit_depth.bits()
+            );
+        }
+        Ok(())
+    }
+
+    /// The table of colors that frames in `format` are rendered from.
+    fn frame_colors<F>(&self, color: F, format: FrameFormat) -> FrameColors
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        match (format, self.bit_depth) {
+            (FrameFormat::Exr, _) => FrameColors::Linear(self.linear_color_table(color)),
+            (_, BitDepth::Sixteen) => FrameColors::Sixteen(self.color_table(color)),
+            _ => FrameColors::Eight(self.color_table(color)),
+        }
+    }
+
+    fn encode_frame(&self, colors: &FrameColors, i: usize, format: FrameFormat) -> Result<Vec<u8>> {
+        let mut bytes = Cursor::new(Vec::new());
+        match (colors, format) {
+            (FrameColors::Linear(colors), _) => self
+                .gen_image(colors, i)
+                .write_to(&mut bytes, ImageFormat::OpenExr)?,
+            (FrameColors::Sixteen(colors), FrameFormat::Png) => self
+                .gen_image(colors, i)
+                .write_with_encoder(self.png_encoder(&mut bytes)?)?,
+            (FrameColors::Sixteen(colors), _) => self
+                .gen_image(colors, i)
+                .write_to(&mut bytes, format.image_format())?,
+            (FrameColors::Eight(colors), _) => {
+                let frame = self.gen_frame(colors, i);
+                let encoded = self.encode_image(&frame, format);
+                self.recycle_frame(frame);
+                return encoded;
+            }
+        }
+        Ok(bytes.into_inner())
+    }
+
+    pub fn encode_image(&self, frame: &RgbaImage, format: FrameFormat) -> Result<Vec<u8>> {
+        let mut bytes = Cursor::new(Vec::new());
+        match format {
+            // JPEG has no alpha channel
+            FrameFormat::Jpeg => {
+                let frame = DynamicImage::from(frame.clone()).into_rgb8();
+                let mut encoder = JpegEncoder::new_with_quality(&mut bytes, self.jpeg_quality);
+                encoder.set_icc_profile(self.color_space.icc_profile())?;
+                encoder.encode_image(&frame)?;
+            }
+            FrameFormat::Png => frame.write_with_encoder(self.png_encoder(&mut bytes)?)?,
+            // Goes through libwebp so the --webp-* options apply, where image can only do lossless
+            FrameFormat::Webp => return self.encode_webp_image(frame),
+            _ => frame.write_to(&mut bytes, format.image_format())?,
+        }
+        Ok(bytes.into_inner())
+    }
+
+    /// A png encoder that tags images with the color space.
+    fn png_encoder<W: Write>(&self, writer: W) -> Result<PngEncoder<W>> {
+        let mut encoder = PngEncoder::new(writer);
+        encoder.set_icc_profile(self.color_space.icc_profile())?;
+        Ok(encoder)
+    }
+}
@@ -0,0 +1,622 @@
+use std::{borrow::Cow, collections::HashMap, io::Write, num::NonZeroU32, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+
+use crate::{outputs::FrameSink, progress::Progress, Output, Params};
+
+/// NeuQuant sampling factor, 1 is the slowest and best.
+const QUANTIZER_SPEED: i32 = 1;
+
+/// The byte that ends a gif.
+#[cfg(feature = "gifski")]
+const GIF_TRAILER: u8 = 0x3b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    Ordered,
+    BlueNoise,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "floyd-steinberg" => Ok(Self::FloydSteinberg),
+            "ordered" => Ok(Self::Ordered),
+            "blue-noise" => Ok(Self::BlueNoise),
+            _ => Err(format!(
+                "unknown dither '{s}', expected floyd-steinberg, ordered, blue-noise, or none"
+            )),
+        }
+    }
+}
+
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// A threshold in [0, 1) for position (x, y), for threshold based dithering.
+fn threshold(dither: Dither, x: u32, y: u32) -> f32 {
+    match dither {
+        Dither::Ordered => (BAYER_8X8[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0,
+        // Martin Roberts' R2 sequence, which is cheap and has blue noise like characteristics
+        Dither::BlueNoise => {
+            const A1: f64 = 0.754_877_666_246_692_8;
+            const A2: f64 = 0.569_840_290_998_053_3;
+            (x as f64 * A1 + y as f64 * A2).fract() as f32
+        }
+        Dither::None | Dither::FloydSteinberg => 0.5,
+    }
+}
+
+/// Builds a palette of at most `size` colors for `colors`, given as RGBA, returning it as RGB
+/// along with the palette index for each color.
+fn build_palette(colors: &[u8], size: usize) -> (Vec<u8>, Vec<u8>) {
+    // Use the colors as they are if they fit
+    let mut exact = HashMap::new();
+    for color in colors.chunks_exact(4) {
+        let next = exact.len();
+        exact.entry([color[0], color[1], color[2]]).or_insert(next);
+        if exact.len() > size {
+            break;
+        }
+    }
+    if exact.len() <= size {
+        let mut palette = vec![0; exact.len() * 3];
+        for (color, &index) in &exact {
+            palette[index * 3..index * 3 + 3].copy_from_slice(color);
+        }
+        let lookup = colors
+            .chunks_exact(4)
+            .map(|color| exact[&[color[0], color[1], color[2]]] as u8)
+            .collect();
+        return (palette, lookup);
+    }
+
+    let quantizer = NeuQuant::new(QUANTIZER_SPEED, size, colors);
+    let lookup = colors
+        .par_chunks_exact(4)
+        .map(|color| quantizer.index_of(color) as u8)
+        .collect();
+    (quantizer.color_map_rgb(), lookup)
+}
+
+/// Quantizes a frame to a palette of `size` colors, dithering with the given algorithm.
+fn quantize(frame: &RgbaImage, dither: Dither, size: usize) -> (Vec<u8>, Vec<u8>) {
+    let quantizer = NeuQuant::new(QUANTIZER_SPEED, size, frame.as_raw());
+    let palette = quantizer.color_map_rgba();
+    let palette_color = |index: usize| &palette[index * 4..index * 4 + 4];
+
+    let indices = match dither {
+        Dither::None => frame
+            .pixels()
+            .map(|pixel| quantizer.index_of(&pixel.0) as u8)
+            .collect(),
+        Dither::Ordered | Dither::BlueNoise => {
+            // Roughly the distance between neighboring palette colors
+            let spread = 255.0 / (palette.len() as f32 / 4.0).cbrt();
+            frame
+                .enumerate_pixels()
+                .map(|(x, y, pixel)| {
+                    let offset = (threshold(dither, x, y) - 0.5) * spread;
+                    let mut color = pixel.0;
+                    for channel in &mut color[..3] {
+                        *channel = (*channel as f32 + offset).round().clamp(0.0, 255.0) as u8;
+                    }
+                    quantizer.index_of(&color) as u8
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => {
+            let width = frame.width() as usize;
+            let mut indices = Vec::with_capacity(frame.as_raw().len() / 4);
+            // Error carried into the current and next rows, with a pixel of padding on each side
+            let mut errors = vec![[0.0f32; 3]; width + 2];
+            let mut next_errors = vec![[0.0f32; 3]; width + 2];
+            for row in frame.rows() {
+                for (x, pixel) in row.enumerate() {
+                    let mut color = pixel.0;
+                    let mut wanted = [0.0; 3];
+                    for c in 0..3 {
+                        wanted[c] = (color[c] as f32 + errors[x + 1][c]).clamp(0.0, 255.0);
+                        color[c] = wanted[c].round() as u8;
+                    }
+                    let index = quantizer.index_of(&color);
+                    indices.push(index as u8);
+
+                    let actual = palette_color(index);
+                    for c in 0..3 {
+                        let error = wanted[c] - actual[c] as f32;
+                        errors[x + 2][c] += error * 7.0 / 16.0;
+                        next_errors[x][c] += error * 3.0 / 16.0;
+                        next_errors[x + 1][c] += error * 5.0 / 16.0;
+                        next_errors[x + 2][c] += error * 1.0 / 16.0;
+                    }
+                }
+                std::mem::swap(&mut errors, &mut next_errors);
+                next_errors.fill([0.0; 3]);
+            }
+            indices
+        }
+    };
+
+    (quantizer.color_map_rgb(), indices)
+}
+
+/// The part of a frame that gets drawn, in pixels.
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+/// The smallest rect containing every pixel that differs between two frames of the given width.
+/// Identical frames still need something to draw to hold their delay, so they get a single pixel.
+fn changed_rect<T: PartialEq>(previous: &[T], current: &[T], width: usize) -> Rect {
+    let differs = |(a, b): (&T, &T)| a != b;
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (y, (previous_row, row)) in previous
+        .chunks(width)
+        .zip(current.chunks(width))
+        .enumerate()
+    {
+        let Some(left) = previous_row.iter().zip(row).position(differs) else {
+            continue;
+        };
+        let right = previous_row.iter().zip(row).rposition(differs).unwrap();
+        bounds = Some(match bounds {
+            None => (left, y, right, y),
+            Some((min_left, top, max_right, _)) => {
+                (min_left.min(left), top, max_right.max(right), y)
+            }
+        });
+    }
+
+    match bounds {
+        Some((left, top, right, bottom)) => Rect {
+            left,
+            top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        },
+        None => Rect {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+        },
+    }
+}
+
+/// A frame of palette indices cropped to where it differs from the previous frame, and drawn over
+/// it. Pixels are compared by `current` and `previous`, which hold whatever decides what they look
+/// like, since the same index can mean different colors with local palettes.
+fn delta_frame<T: PartialEq>(
+    (width, height): (usize, usize),
+    previous: Option<&[T]>,
+    current: &[T],
+    indices: &[u8],
+) -> gif::Frame<'static> {
+    let rect = match previous {
+        Some(previous) => changed_rect(previous, current, width),
+        None => Rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        },
+    };
+    let buffer = indices
+        .chunks(width)
+        .skip(rect.top)
+        .take(rect.height)
+        .flat_map(|row| &row[rect.left..rect.left + rect.width])
+        .copied()
+        .collect();
+
+    gif::Frame {
+        left: rect.left as u16,
+        top: rect.top as u16,
+        width: rect.width as u16,
+        height: rect.height as u16,
+        dispose: gif::DisposalMethod::Keep,
+        buffer: Cow::Owned(buffer),
+        ..Default::default()
+    }
+}
+
+/// The RGB color of each pixel in a frame of palette indices.
+fn palette_colors(palette: &[u8], indices: &[u8]) -> Vec<[u8; 3]> {
+    indices
+        .iter()
+        .map(|&index| {
+            let index = index as usize * 3;
+            [palette[index], palette[index + 1], palette[index + 2]]
+        })
+        .collect()
+}
+
+/// A palette shared by every frame, and the index of each color in it.
+struct GlobalPalette {
+    palette: Vec<u8>,
+    indices: HashMap<[u8; 4], u8>,
+}
+
+pub struct GifSink<'a> {
+    params: &'a Params,
+    /// Started on the first frame, since every frame has all of the animation's colors to build
+    /// the global palette from.
+    encoder: Option<gif::Encoder<Output>>,
+    /// Left out when frames are dithered separately.
+    global_palette: Option<GlobalPalette>,
+    previous_colors: Option<Vec<[u8; 3]>>,
+}
+
+impl FrameSink for GifSink<'_> {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()> {
+        let params = self.params;
+        if self.encoder.is_none() {
+            let global_palette = (params.dither == Dither::None).then(|| {
+                // The first frame isn't shifted, so putting its pixels in curve order gives the
+                // same palette as rendering the colors directly
+                let mut colors = vec![0; frame.as_raw().len()];
+                for (pixel, h) in frame.pixels().zip(params.curve.iter()) {
+                    let h = *h as usize * 4;
+                    colors[h..h + 4].copy_from_slice(&pixel.0);
+                }
+                let (palette, lookup) = build_palette(&colors, params.colors);
+                let indices = colors
+                    .chunks_exact(4)
+                    .zip(lookup)
+                    .map(|(color, index)| (color.try_into().unwrap(), index))
+                    .collect();
+                GlobalPalette { palette, indices }
+            });
+            let palette = global_palette
+                .as_ref()
+                .map_or(&[][..], |global| &global.palette);
+            self.encoder = Some(params.gif_encoder(palette)?);
+            self.global_palette = global_palette;
+        }
+
+        let (local_palette, indices, colors) = match &self.global_palette {
+            Some(global) => {
+                let indices: Vec<u8> = frame
+                    .pixels()
+                    .map(|pixel| global.indices[&pixel.0])
+                    .collect();
+                let colors = palette_colors(&global.palette, &indices);
+                (None, indices, colors)
+            }
+            None => {
+                let (palette, indices) = quantize(frame, params.dither, params.colors);
+                let colors = palette_colors(&palette, &indices);
+                (Some(palette), indices, colors)
+            }
+        };
+
+        let (width, height) = params.frame_dimensions();
+        let mut gif_frame = delta_frame(
+            (width as usize, height as usize),
+            self.previous_colors.as_deref(),
+            &colors,
+            &indices,
+        );
+        gif_frame.delay = (100.0 / params.framerate as f64).round() as u16;
+        gif_frame.palette = local_palette;
+        self.previous_colors = Some(colors);
+        self.encoder
+            .as_mut()
+            .unwrap()
+            .write_frame(&gif_frame)
+            .context("failed to write frames")
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        if let Some(encoder) = self.encoder {
+            encoder
+                .into_inner()
+                .and_then(|mut file| file.flush())
+                .context("Failed to finish gif")?;
+        }
+        Ok(())
+    }
+}
+
+impl Params {
+    /// Writes a gif, from `colors` along the curve where its mode can work from them, or from
+    /// rendered frames.
+    pub fn write_gif<R>(&self, colors: &[[u8; 4]], render_frame: R) -> Result<()>
+    where
+        R: Fn(usize) -> RgbaImage + Sync,
+    {
+        #[cfg(feature = "gifski")]
+        if self.gifski {
+            return self.write_gifski(render_frame);
+        }
+        if self.palette_cycle {
+            return self.write_palette_cycle_gif(colors);
+        }
+        if self.dither != Dither::None {
+            return self.write_dithered_gif(render_frame);
+        }
+
+        self.write_global_palette_gif(colors)
+    }
+
+    /// Encodes frames rendered elsewhere, like when they're shared with other outputs. Palette
+    /// cycling and gifski need to be in charge of the frames themselves, so they can't.
+    pub fn gif_sink(&self) -> Result<GifSink<'_>> {
+        #[cfg(feature = "gifski")]
+        if self.gifski {
+            bail!("--gifski can't be combined with other outputs");
+        }
+        if self.palette_cycle {
+            bail!("--palette-cycle can't be combined with other outputs");
+        }
+        if self.crop.is_some() && self.dither == Dither::None {
+            // The shared palette comes from the first frame, which only has the window's colors
+            bail!("--crop needs --dither for gifs combined with other outputs");
+        }
+        Ok(GifSink {
+            params: self,
+            encoder: None,
+            global_palette: None,
+            previous_colors: None,
+        })
+    }
+
+    /// Opens the output file and starts a gif in it, set to loop according to --loops.
+    fn gif_encoder(&self, global_palette: &[u8]) -> Result<gif::Encoder<Output>> {
+        let file = self.create_output()?;
+        let (width, height) = self.frame_dimensions();
+        let mut encoder = gif::Encoder::new(file, width as u16, height as u16, global_palette)
+            .context("Failed to initialize gif encoder")?;
+        if let Some(repeat) = self.gif_repeat() {
+            encoder
+                .set_repeat(repeat)
+                .context("Failed to write gif loop count")?;
+        }
+        encoder
+            .write_raw_extension(
+                gif::Extension::Comment.into(),
+                &[self.description().as_bytes()],
+            )
+            .context("Failed to write gif comment")?;
+        Ok(encoder)
+    }
+
+    /// The NETSCAPE2.0 loop extension for --loops. It counts repeats after the first play, and
+    /// playing once is done by leaving it out, since a count of 0 loops forever.
+    fn gif_repeat(&self) -> Option<gif::Repeat> {
+        match self.loops.map(NonZeroU32::get) {
+            None => Some(gif::Repeat::Infinite),
+            Some(1) => None,
+            Some(loops) => Some(gif::Repeat::Finite(
+                (loops - 1).try_into().unwrap_or(u16::MAX),
+            )),
+        }
+    }
+
+    /// Splits the curve into one band per color and draws it once, then animates it by only
+    /// swapping the local color table of each frame. The image data is identical for every frame,
+    /// so it's only compressed once.
+    fn write_palette_cycle_gif(&self, colors: &[[u8; 4]]) -> Result<()> {
+        let bands = self.colors as u64;
+        let indices = self
+            .curve
+            .iter()
+            .map(|h| (h * bands / self.num_pixels) as u8)
+            .collect();
+
+        let (width, height) = self.frame_dimensions();
+        let delay = (100.0 / self.framerate as f64).round() as u16;
+        let mut image = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            delay,
+            buffer: Cow::Owned(indices),
+            ..Default::default()
+        };
+        image.make_lzw_pre_encoded();
+
+        let mut palettes = Vec::with_capacity(self.frames);
+        let sampled = (0..self.frames).into_par_iter().map(|i| {
+            let offset = self.frame_offset(i);
+            (0..bands)
+                .flat_map(|band| {
+                    // Sample the middle of each band
+                    let h = (band * 2 + 1) * self.num_pixels / (bands * 2);
+                    let [r, g, b, _] = colors[((h + offset) % self.num_pixels) as usize];
+                    [r, g, b]
+                })
+                .collect::<Vec<u8>>()
+        });
+        let progress = Progress::new("palettes", self.frames);
+        sampled
+            .inspect(|_| progress.update(1))
+            .collect_into_vec(&mut palettes);
+
+        let mut encoder = self.gif_encoder(&[])?;
+        let progress = Progress::new("encode", self.frames);
+        for palette in palettes {
+            let frame = gif::Frame {
+                width: width as u16,
+                height: height as u16,
+                delay,
+                buffer: Cow::Borrowed(&image.buffer),
+                palette: Some(palette),
+                ..Default::default()
+            };
+            encoder
+                .write_lzw_pre_encoded_frame(&frame)
+                .context("failed to write frames")?;
+            progress.update(1);
+        }
+
+        Ok(())
+    }
+
+    /// Every frame is the same curve with its colors shifted along it, so they all share one set
+    /// of colors. That set is quantized once into a global palette, and frames are rendered
+    /// straight to palette indices.
+    fn write_global_palette_gif(&self, colors: &[[u8; 4]]) -> Result<()> {
+        let (palette, lookup) = build_palette(colors.as_flattened(), self.colors);
+
+        let frame_indices = |i: usize| -> Vec<u8> {
+            let offset = self.frame_offset(i);
+            self.curve
+                .iter()
+                .map(|h| lookup[((h + offset) % self.num_pixels) as usize])
+                .collect()
+        };
+        let (width, height) = self.frame_dimensions();
+        let delay = (100.0 / self.framerate as f64).round() as u16;
+        let mut encoder = self.gif_encoder(&palette)?;
+        self.stream_frames(
+            |i| {
+                let indices = frame_indices(i);
+                // Redoing the previous frame's lookups is cheap, and keeps workers independent
+                let previous = i.checked_sub(1).map(frame_indices);
+                let mut frame = delta_frame(
+                    (width as usize, height as usize),
+                    previous.as_deref(),
+                    &indices,
+                    &indices,
+                );
+                frame.delay = delay;
+                frame.make_lzw_pre_encoded();
+                frame
+            },
+            |frame| {
+                encoder
+                    .write_lzw_pre_encoded_frame(&frame)
+                    .context("failed to write frames")
+            },
+        )
+    }
+
+    /// Dithering depends on each pixel's position, so every frame has to be quantized separately.
+    fn write_dithered_gif<R>(&self, render_frame: R) -> Result<()>
+    where
+        R: Fn(usize) -> RgbaImage + Sync,
+    {
+        let (width, height) = self.frame_dimensions();
+        let delay = (100.0 / self.framerate as f64).round() as u16;
+        let mut encoder = self.gif_encoder(&[])?;
+        let mut previous_colors: Option<Vec<[u8; 3]>> = None;
+        self.stream_frames(
+            |i| {
+                let frame = render_frame(i);
+                let (palette, indices) = quantize(&frame, self.dither, self.colors);
+                self.recycle_frame(frame);
+                let colors = palette_colors(&palette, &indices);
+                (palette, indices, colors)
+            },
+            // Each frame has its own palette, so the changed area can only be found once the
+            // previous frame is known
+            |(palette, indices, colors)| {
+                let mut frame = delta_frame(
+                    (width as usize, height as usize),
+                    previous_colors.as_deref(),
+                    &colors,
+                    &indices,
+                );
+                frame.delay = delay;
+                frame.palette = Some(palette);
+                previous_colors = Some(colors);
+                encoder
+                    .write_frame(&frame)
+                    .context("failed to write frames")
+            },
+        )
+    }
+
+    #[cfg(feature = "gifski")]
+    fn write_gifski<R>(&self, render_frame: R) -> Result<()>
+    where
+        R: Fn(usize) -> RgbaImage + Sync,
+    {
+        use imgref::ImgVec;
+        use rgb::FromSlice;
+
+        let (collector, writer) = gifski::new(gifski::Settings {
+            quality: self.gif_quality,
+            fast: self.gif_fast,
+            // gifski leaves out the loop extension for 0 repeats
+            repeat: self.gif_repeat().unwrap_or(gif::Repeat::Finite(0)),
+            ..Default::default()
+        })
+        .context("Failed to initialize gifski")?;
+
+        // gifski only makes progress while both ends are running, so frames are fed from another
+        // thread.
+        std::thread::scope(|scope| {
+            let render_frame = &render_frame;
+            let feeder = scope.spawn(move || {
+                let mut i = 0;
+                self.stream_frames(
+                    |i| {
+                        let frame = render_frame(i);
+                        let (width, height) = frame.dimensions();
+                        let pixels = frame.as_raw().as_rgba().to_vec();
+                        self.recycle_frame(frame);
+                        ImgVec::new(pixels, width as usize, height as usize)
+                    },
+                    |frame| {
+                        let timestamp = i as f64 / self.framerate as f64;
+                        collector
+                            .add_frame_rgba(i, frame, timestamp)
+                            .context("Failed to add frame to gifski")?;
+                        i += 1;
+                        Ok(())
+                    },
+                )
+            });
+
+            // Kept in memory so the comment can go in before the trailer, since gifski can't
+            // write one itself
+            let mut gif = Vec::new();
+            let written = writer
+                .write(&mut gif, &mut gifski::progress::NoProgress {})
+                .context("Failed to write gif");
+            feeder.join().unwrap()?;
+            written?;
+
+            if gif.pop() != Some(GIF_TRAILER) {
+                bail!("gifski wrote an incomplete gif");
+            }
+            let comment = self.description();
+            // 0x21 starts an extension
+            gif.extend([0x21, gif::Extension::Comment as u8]);
+            for block in comment.as_bytes().chunks(0xff) {
+                gif.push(block.len() as u8);
+                gif.extend(block);
+            }
+            gif.extend([0, GIF_TRAILER]);
+
+            let mut file = self.create_output()?;
+            file.write_all(&gif).context("Failed to write gif")?;
+            file.flush().context("Failed to write gif")
+        })
+    }
+}
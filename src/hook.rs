@@ -0,0 +1,41 @@
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+
+use crate::{OutputFormat, Params};
+
+impl Params {
+    /// Runs --post-cmd for a finished output, with its placeholders filled in.
+    pub fn run_post_cmd(&self, format: OutputFormat) -> Result<()> {
+        let Some(post_cmd) = &self.post_cmd else {
+            return Ok(());
+        };
+        // Split before filling in, so an output path with spaces stays one argument
+        let mut args = post_cmd
+            .split_whitespace()
+            .map(|arg| self.fill_placeholders(arg, format));
+        let program = args.next().context("--post-cmd is empty")?;
+        let mut command = Command::new(&program);
+        command.args(args);
+        debug!("Running {command:?}");
+        let status = command
+            .status()
+            .with_context(|| format!("Failed to run {program}"))?;
+        if !status.success() {
+            bail!("--post-cmd failed with {status}");
+        }
+        Ok(())
+    }
+
+    fn fill_placeholders(&self, arg: &str, format: OutputFormat) -> String {
+        let output = self.output_path(format).to_string_lossy().into_owned();
+        let placeholders = [("output", output), ("format", format.name().to_string())];
+        placeholders
+            .into_iter()
+            .chain(self.placeholders())
+            .fold(arg.to_string(), |arg, (name, value)| {
+                arg.replace(&format!("{{{name}}}"), &value)
+            })
+    }
+}
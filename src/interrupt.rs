@@ -0,0 +1,191 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+};
+
+#[cfg(unix)]
+use std::{fs, io, sync::atomic::AtomicI32, thread};
+
+#[cfg(unix)]
+use log::{info, warn};
+
+#[cfg(unix)]
+use crate::exit;
+
+/// What's cleaned up if the render is interrupted, each with the id of the guard that registered
+/// it.
+static CLEANUP: Mutex<Vec<(u64, Target)>> = Mutex::new(Vec::new());
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Where the signal handler writes to wake up the thread that cleans up, since hardly anything
+/// else is safe to do from inside a handler.
+#[cfg(unix)]
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+/// Whether the render is paused with SIGUSR1, checked without locking for every step of it.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Locked by whatever waits for the render to be resumed with SIGUSR2.
+static PAUSE: Mutex<()> = Mutex::new(());
+static RESUMED: Condvar = Condvar::new();
+
+enum Target {
+    /// A half-written file or a temporary directory, removed.
+    Path(PathBuf),
+    /// An FFMpeg process being fed frames, stopped.
+    Child(u32),
+}
+
+/// Keeps something registered for cleanup until it's dropped, once it's finished with.
+#[must_use]
+pub struct Cleanup(u64);
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        CLEANUP.lock().unwrap().retain(|(id, _)| *id != self.0);
+    }
+}
+
+fn register(target: Target) -> Cleanup {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    CLEANUP.lock().unwrap().push((id, target));
+    Cleanup(id)
+}
+
+/// Removes a file or directory if the render is interrupted before the guard is dropped.
+pub fn remove_on_interrupt(path: &Path) -> Cleanup {
+    register(Target::Path(path.to_owned()))
+}
+
+/// Stops an FFMpeg process if the render is interrupted before the guard is dropped.
+pub fn kill_on_interrupt(child: &Child) -> Cleanup {
+    let cleanup = register(Target::Child(child.id()));
+    // Started just as the render was paused, so it's paused along with the rest
+    #[cfg(unix)]
+    if PAUSED.load(Ordering::Relaxed) {
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGSTOP) };
+    }
+    cleanup
+}
+
+/// Waits for the render to be resumed if it's paused. Called for every step of it, which is where
+/// rendering and encoding pause.
+pub fn wait_while_paused() {
+    if !PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut guard = PAUSE.lock().unwrap();
+    while PAUSED.load(Ordering::Relaxed) {
+        guard = RESUMED.wait(guard).unwrap();
+    }
+}
+
+/// Cleans up and exits on Ctrl-C or SIGTERM, stopping any FFMpeg processes and removing partial
+/// outputs and temporary directories, rather than leaving them behind. SIGUSR1 pauses the render
+/// and SIGUSR2 resumes it.
+#[cfg(unix)]
+pub fn install() {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        warn!(
+            "Failed to handle Ctrl-C, so partial outputs will be left behind if interrupted: {}",
+            io::Error::last_os_error()
+        );
+        return;
+    }
+    let [read, write] = fds;
+    WAKE.store(write, Ordering::Relaxed);
+    thread::spawn(move || {
+        let mut signal = 0u8;
+        loop {
+            match unsafe { libc::read(read, (&raw mut signal).cast(), 1) } {
+                1 => match libc::c_int::from(signal) {
+                    libc::SIGUSR1 => set_paused(true),
+                    libc::SIGUSR2 => set_paused(false),
+                    _ => clean_up_and_exit(),
+                },
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                _ => return,
+            }
+        }
+    });
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGUSR1, libc::SIGUSR2] {
+        unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+    }
+}
+
+/// Ctrl-C can't be caught portably elsewhere, so it stops the render where it is, and there are no
+/// signals to pause it with.
+#[cfg(not(unix))]
+pub fn install() {}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    // Every signal handled fits in a byte
+    let byte = signal as u8;
+    unsafe { libc::write(WAKE.load(Ordering::Relaxed), (&raw const byte).cast(), 1) };
+}
+
+/// Pauses or resumes rendering, along with any FFMpeg processes being fed frames.
+#[cfg(unix)]
+fn set_paused(paused: bool) {
+    let cleanup = CLEANUP.lock().unwrap();
+    if PAUSED.swap(paused, Ordering::Relaxed) == paused {
+        return;
+    }
+    let signal = match paused {
+        true => libc::SIGSTOP,
+        false => libc::SIGCONT,
+    };
+    for (_, target) in cleanup.iter() {
+        if let Target::Child(pid) = *target {
+            unsafe { libc::kill(pid as libc::pid_t, signal) };
+        }
+    }
+    if !paused {
+        // Taken so nothing's between checking it's paused and waiting when it's woken
+        let _guard = PAUSE.lock().unwrap();
+        RESUMED.notify_all();
+    }
+    // On a new line, past any progress bar
+    eprintln!();
+    match paused {
+        true => info!("Paused, send SIGUSR2 to resume"),
+        false => info!("Resumed"),
+    }
+}
+
+#[cfg(unix)]
+fn clean_up_and_exit() -> ! {
+    // Held until exiting, so nothing new is started in the meantime
+    let cleanup = CLEANUP.lock().unwrap();
+    // On a new line, past any progress bar
+    eprintln!();
+    info!("Interrupted, cleaning up");
+    // FFMpeg is stopped first, so nothing's still writing to what's removed
+    for (_, target) in cleanup.iter() {
+        if let Target::Child(pid) = *target {
+            let pid = pid as libc::pid_t;
+            unsafe {
+                libc::kill(pid, libc::SIGTERM);
+                // It can't handle being stopped while it's paused
+                libc::kill(pid, libc::SIGCONT);
+                libc::waitpid(pid, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+    for (_, target) in cleanup.iter() {
+        if let Target::Path(path) = target {
+            let _ = match path.is_dir() {
+                true => fs::remove_dir_all(path),
+                false => fs::remove_file(path),
+            };
+        }
+    }
+    std::process::exit(exit::INTERRUPTED.into())
+}
@@ -0,0 +1,53 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints messages to stderr, the everyday ones as they are and the rest marked with their level.
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", record.args()),
+            Level::Warn => eprintln!("Warning: {}", record.args()),
+            Level::Info => eprintln!("{}", record.args()),
+            Level::Debug => eprintln!("debug: {}", record.args()),
+            Level::Trace => eprintln!("trace: {}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Starts printing messages, at the everyday level until the options say otherwise.
+pub fn init() {
+    // Only fails if it's already set, which it can't be
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Sets what's printed from --quiet and how many times --verbose was given.
+pub fn set_verbosity(quiet: bool, verbose: usize) {
+    log::set_max_level(match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    });
+}
+
+/// Arguments that keep FFMpeg to warnings and errors, or everything with -vv, and leave out the
+/// stats line it redraws as it goes. What it prints is captured and logged with --verbose.
+pub fn ffmpeg_args() -> &'static [&'static str] {
+    match log::max_level() {
+        LevelFilter::Trace => &["-hide_banner", "-nostats"],
+        _ => &["-hide_banner", "-nostats", "-loglevel", "warning"],
+    }
+}
@@ -1,290 +1,1268 @@
+mod batch;
+mod color;
+mod color_samples;
+mod completions;
+mod config;
+mod contact_sheet;
+mod crop;
+mod debug_overlay;
+mod decode;
+mod deterministic;
+mod diff;
+mod draft;
+mod dry_run;
+mod exit;
+mod fallback;
+mod ffmpeg_log;
+mod font;
+mod formats;
+mod frames;
+mod gif;
+mod glyphs;
+mod hook;
+mod interrupt;
+mod logging;
+mod manifest;
+mod max_size;
+mod memory;
+mod metadata;
+mod nice;
+mod notify;
+mod outputs;
+mod overlay;
+mod partial;
+mod platform;
+mod pool;
+mod presets;
+mod preview;
+mod progress;
+mod proof;
+mod resume;
+mod scale;
+mod self_test;
+mod shard;
+mod suggest;
+mod supersample;
+mod template;
+mod units;
+mod validate;
+mod verify;
+mod video;
+mod watch;
+mod webp;
+mod wizard;
+
 use std::{
-    ffi::{OsStr, OsString},
-    fs::{self, File},
-    io::{BufWriter, Write},
-    iter::once,
-    num::NonZeroU32,
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    num::{NonZeroU32, NonZeroUsize},
     ops::Rem,
-    path::{Path, PathBuf},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+    sync::{mpsc, Arc},
+    time::{Instant, SystemTime},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use batch::{batch_options, BatchOptions};
 use bpaf::*;
-use image::{codecs::gif::GifEncoder, RgbaImage};
-use kdam::{par_tqdm, tqdm};
-use palette::{IntoColor, LinSrgba, Okhsva, OklabHue, Srgba};
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
-use webp_animation::{Encoder, EncoderOptions};
+use color::{ColorEncoder, ColorSpace};
+use completions::{complete_function, complete_preset, Shell};
+use contact_sheet::{contact_sheet_options, ContactSheetOptions};
+use crop::Crop;
+use diff::{diff_options, DiffOptions};
+use frames::{Archive, FrameFormat, FramePattern};
+use gif::Dither;
+use image::{ImageBuffer, Pixel, Primitive, Rgba};
+use log::debug;
+use overlay::{Overlay, Position, TextColor};
+use palette::{IntoColor, LinSrgba, Okhsva, OklabHue};
+use platform::Platform;
+use pool::FramePool;
+use presets::{preset_command, Preset, PresetCommand};
+use preview::{preview_options, PreviewOptions};
+use progress::{Progress, ProgressStyle, Stages};
+use proof::Proof;
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+use scale::{Dimensions, ScaleFilter};
+use self_test::{self_test_options, SelfTestOptions};
+use shard::{merge_options, MergeOptions, Shard};
+use units::{Bitrate, ByteSize, Duration};
+use verify::{verify_options, VerifyOptions};
+use video::{BitDepth, Container, EncodePreset, HwAccel, PixelFormat};
+use webp::{BackgroundColor, WebpPreset};
 
 #[derive(Debug, Clone, Bpaf)]
 #[bpaf(options)]
+// Only ever parsed once, so there's no point boxing the options
+#[allow(clippy::large_enum_variant)]
+enum Cli {
+    /// Join the shards of a render made with --shard
+    #[bpaf(command)]
+    Merge(#[bpaf(external(merge_options))] MergeOptions),
+    /// Save options to render with again by name
+    #[bpaf(command)]
+    Preset(#[bpaf(external(preset_command))] PresetCommand),
+    /// Render a single frame, to check the colors before rendering them all
+    #[bpaf(command)]
+    Preview(#[bpaf(external(preview_options))] PreviewOptions),
+    /// Render frames from across the animation into a labeled grid, to review it without
+    /// playing it
+    #[bpaf(command("contact-sheet"))]
+    ContactSheet(#[bpaf(external(contact_sheet_options))] ContactSheetOptions),
+    /// Render a list of jobs from a file, one after another
+    #[bpaf(command)]
+    Batch(#[bpaf(external(batch_options))] BatchOptions),
+    /// Compare two renders frame by frame, to weigh up encoder settings
+    #[bpaf(command)]
+    Diff(#[bpaf(external(diff_options))] DiffOptions),
+    /// Check that a render's manifest can be reproduced, frame by frame
+    #[bpaf(command)]
+    Verify(#[bpaf(external(verify_options))] VerifyOptions),
+    /// Render a tiny animation with every function and through every encoder that can be used,
+    /// checking that each output decodes, to make sure the build and FFMpeg work before a long
+    /// render
+    #[bpaf(command("self-test"))]
+    SelfTest(#[bpaf(external(self_test_options))] SelfTestOptions),
+    /// Print a script that completes the commands and options for a shell: bash, zsh, or fish
+    #[bpaf(command)]
+    Completions(#[bpaf(positional("SHELL"))] Shell),
+    /// Print a man page for the commands and options
+    #[bpaf(command)]
+    Manpage,
+    /// Choose the order, function, length, and output a question at a time, then print the
+    /// command that renders it and optionally run it
+    #[bpaf(command)]
+    Wizard,
+    Render(#[bpaf(external(options))] Options),
+}
+
+#[derive(Debug, Clone, Bpaf)]
 struct Options {
     #[bpaf(long, fallback(9))]
     order: u8,
-    #[bpaf(short, long, fallback("oklab_hue".to_owned()))]
+    #[bpaf(short, long, fallback("oklab_hue".to_owned()), complete(complete_function))]
     function: String,
     #[bpaf(short, long, fallback(256))]
     frames: usize,
     #[bpaf(short('r'), long, fallback(30))]
     framerate: u32,
-    #[bpaf(short, long, fallback(NonZeroU32::new(1).unwrap()))]
-    loops: NonZeroU32,
+    /// How long the animation plays, like 1m30s, 90s, or 1.5m, instead of giving --frames
+    #[bpaf(long, argument("DURATION"))]
+    duration: Option<Duration>,
+    /// Number of times to play the animation. Videos repeat the frames, webp and gif outputs loop
+    /// forever if not given.
     #[bpaf(short, long)]
-    bitrate: Option<String>,
-    #[bpaf(positional, fallback("out.webp".into()))]
-    filename: PathBuf,
+    loops: Option<NonZeroU32>,
+    /// Bitrate for video outputs, like 2.5M, 800k, or 2.5Mbps
+    #[bpaf(short, long, argument("BITRATE"))]
+    bitrate: Option<Bitrate>,
+    /// Constant quality for video outputs, on the encoder's own scale (0-63 for VP9, 0-51 for
+    /// H.264, lower is better). Combined with --bitrate, the bitrate becomes an upper bound.
+    #[bpaf(long("crf"), long("quality"), argument("CRF"))]
+    crf: Option<u8>,
+    /// Encoder speed preset for video outputs: fast, balanced, or best
+    #[bpaf(long, argument("PRESET"))]
+    encode_preset: Option<EncodePreset>,
+    /// Hardware encoder to use for video outputs: nvenc, vaapi, qsv, or videotoolbox
+    #[bpaf(long, argument("API"))]
+    hwaccel: Option<HwAccel>,
+    /// Threads to render with, and to give FFMpeg, instead of one for each core
+    #[bpaf(long, argument("N"))]
+    threads: Option<NonZeroUsize>,
+    /// Render in the background at a lower priority, leaving a core free unless --threads is given
+    nice: bool,
+    /// Only print warnings and errors, without progress bars
+    #[bpaf(short, long)]
+    quiet: bool,
+    /// Print more about what's being done, or everything with -vv
+    #[bpaf(short('v'), long("verbose"), req_flag(()), count)]
+    verbose: usize,
+    /// How to show progress: a bar for each stage, one overall bar, json for a line of JSON for
+    /// every frame, or none. Bars are drawn when stderr is a terminal, unless --quiet is given.
+    #[bpaf(long, argument("STYLE"))]
+    progress: Option<ProgressStyle>,
+    /// Stages to show progress for, like render,encode, out of render, write, palettes, encode,
+    /// first-pass, second-pass, and checksums
+    #[bpaf(long, argument("STAGES"))]
+    progress_stages: Option<Stages>,
+    /// FFMpeg binary to use for video outputs
+    #[bpaf(long, argument("PATH"), fallback("ffmpeg".into()))]
+    ffmpeg_path: PathBuf,
+    /// Extra argument to pass to FFMpeg, can be repeated
+    #[bpaf(long("ffmpeg-arg"), argument("ARG"), many)]
+    ffmpeg_args: Vec<String>,
+    /// Write webp instead of video outputs if FFMpeg can't be run, rather than asking
+    ffmpeg_fallback: bool,
+    /// Split video outputs into this many segments, encoded in parallel and then joined
+    #[bpaf(long, argument("N"), fallback(NonZeroUsize::new(1).unwrap()))]
+    segments: NonZeroUsize,
+    /// Keep the directory of encoded segments after joining them, instead of removing it
+    keep_segments: bool,
+    /// Directory to put directories of frames and video segments in, instead of next to the
+    /// output. Each is still named after its output.
+    #[bpaf(long, argument("DIR"))]
+    frames_dir: Option<PathBuf>,
+    /// Render this many times the size and average it back down, smoothing the edges between
+    /// colors. Either 2 or 4.
+    #[bpaf(
+        long,
+        argument("FACTOR"),
+        guard(valid_supersample, "supersampling must be 2 or 4"),
+        fallback(1)
+    )]
+    supersample: u32,
+    /// Only render this window of the curve, as x,y,width,height in cells from the top left, for
+    /// close-ups of high orders
+    #[bpaf(long, argument("X,Y,W,H"))]
+    crop: Option<Crop>,
+    /// Resize frames to this size once they're rendered, like 1080x1080
+    #[bpaf(long, argument("WIDTHxHEIGHT"))]
+    output_size: Option<Dimensions>,
+    /// Filter for --output-size: nearest to keep pixels crisp, or lanczos to smooth them
+    #[bpaf(long, argument("FILTER"), fallback(ScaleFilter::Nearest))]
+    scale_filter: ScaleFilter,
+    /// Text to draw onto every frame, like an attribution
+    #[bpaf(long, argument("TEXT"))]
+    text: Option<String>,
+    /// TrueType font to draw --text in, like DejaVuSans.ttf
+    #[bpaf(long, argument("PATH"))]
+    font: Option<PathBuf>,
+    /// Height of --text in pixels, a sixteenth of the frame's if not given
+    #[bpaf(long, argument("PIXELS"))]
+    text_size: Option<NonZeroU32>,
+    /// Where to draw --text: top-left, top, top-right, left, center, right, bottom-left, bottom,
+    /// or bottom-right
+    #[bpaf(long, argument("POSITION"), fallback(Position::BottomRight))]
+    text_pos: Position,
+    /// Color of --text, as RRGGBB or RRGGBBAA
+    #[bpaf(long, argument("COLOR"), fallback(TextColor::WHITE))]
+    text_color: TextColor,
+    /// Image to draw onto every frame at its own size, like a png logo
+    #[bpaf(long, argument("PATH"))]
+    watermark: Option<PathBuf>,
+    /// Where to draw --watermark, the same positions as --text-pos
+    #[bpaf(long, argument("POSITION"), fallback(Position::BottomLeft))]
+    watermark_pos: Position,
+    /// Opacity of --watermark, from 0 to 1
+    #[bpaf(
+        long,
+        argument("OPACITY"),
+        guard(valid_opacity, "opacity must be between 0 and 1"),
+        fallback(1.0)
+    )]
+    watermark_opacity: f32,
+    /// Draw the frame number, where along the curve its colors start, and the order, framerate,
+    /// and function onto every frame, to see where an animation jumps or doesn't loop
+    debug_overlay: bool,
+    /// Render video frames this many rows at a time, without ever holding a whole frame, for
+    /// orders too big for memory. Much slower.
+    #[bpaf(long, argument("ROWS"))]
+    strip_rows: Option<NonZeroUsize>,
+    /// Audio file to add to video outputs, looped or cut to the length of the animation
+    #[bpaf(long, argument("PATH"))]
+    audio: Option<PathBuf>,
+    /// Use two-pass encoding for video outputs
+    two_pass: bool,
+    /// Keep the alpha channel in webm outputs
+    alpha: bool,
+    /// Pixel format for video outputs: yuv420p, or yuv444p to avoid chroma subsampling
+    #[bpaf(long, argument("FORMAT"), fallback(PixelFormat::Yuv420p))]
+    pix_fmt: PixelFormat,
+    /// Bit depth: 8, 10 for videos, or 16 for png frames, to avoid banding in slow gradients
+    #[bpaf(
+        long("bit-depth"),
+        long("depth"),
+        argument("BITS"),
+        fallback(BitDepth::Eight)
+    )]
+    bit_depth: BitDepth,
+    /// Keep webp outputs lossless even when --webp-quality is given
+    lossless: bool,
+    /// Quality for lossy webp outputs, 0-100. With --lossless, this is how hard to try to compress.
+    #[bpaf(
+        long,
+        argument("QUALITY"),
+        guard(valid_webp_quality, "quality must be between 0 and 100"),
+        optional
+    )]
+    webp_quality: Option<f32>,
+    /// Compression method for webp outputs, 0 (fast) to 6 (slower but smaller)
+    #[bpaf(
+        long,
+        argument("METHOD"),
+        guard(valid_webp_method, "method must be between 0 and 6"),
+        optional
+    )]
+    webp_method: Option<usize>,
+    /// Encoder tuning preset for webp outputs: default, picture, photo, drawing, icon, or text
+    #[bpaf(long, argument("PRESET"), fallback(WebpPreset::Default))]
+    webp_preset: WebpPreset,
+    /// Allow webp outputs to mix lossy and lossless frames, picking whichever is smaller
+    webp_mixed: bool,
+    /// Use the slower but more accurate RGB to YUV conversion for lossy webp outputs
+    webp_sharp_yuv: bool,
+    /// Dithering for gif outputs: floyd-steinberg, ordered, blue-noise, or none
+    #[bpaf(long, argument("DITHER"), fallback(Dither::None))]
+    dither: Dither,
+    /// Number of colors in gif outputs, 2-256
+    #[bpaf(
+        long,
+        argument("N"),
+        guard(valid_gif_colors, "colors must be between 2 and 256"),
+        fallback(256)
+    )]
+    colors: usize,
+    /// Animate gif outputs by cycling the palette of one image with --colors bands, for much
+    /// smaller files
+    palette_cycle: bool,
+    /// Encode gif outputs with gifski, for better quality
+    #[cfg(feature = "gifski")]
+    gifski: bool,
+    /// Quality for gif outputs encoded with gifski, 1-100
+    #[cfg(feature = "gifski")]
+    #[bpaf(
+        long,
+        argument("QUALITY"),
+        guard(valid_gif_quality, "quality must be between 1 and 100"),
+        fallback(90)
+    )]
+    gif_quality: u8,
+    /// Encode gif outputs with gifski faster, at a lower quality
+    #[cfg(feature = "gifski")]
+    gif_fast: bool,
+    /// Image format for frame outputs: png, jpeg, tiff, bmp, webp, or exr. Webp frames follow the
+    /// other webp options.
+    #[bpaf(long, argument("FORMAT"), fallback(FrameFormat::Png))]
+    frame_format: FrameFormat,
+    /// Filename for frames with a printf style number, like frame_%06d.png. It should end in the
+    /// extension for --frame-format.
+    #[bpaf(long, argument("PATTERN"))]
+    frame_pattern: Option<FramePattern>,
+    /// Number of the first frame in frame filenames
+    #[bpaf(long, argument("N"), fallback(0))]
+    frame_start: usize,
+    /// Quality for jpeg frames, 1-100
+    #[bpaf(
+        long,
+        argument("QUALITY"),
+        guard(valid_jpeg_quality, "quality must be between 1 and 100"),
+        fallback(90)
+    )]
+    jpeg_quality: u8,
+    /// Background color hint for webp outputs, as RRGGBB or RRGGBBAA
+    #[bpaf(long, argument("COLOR"))]
+    background: Option<BackgroundColor>,
+    /// Color space to encode and tag outputs in: srgb, p3, or rec2020. The wider ones keep
+    /// saturated colors that sRGB would clip, for displays that can show them.
+    #[bpaf(long, argument("GAMUT"), fallback(ColorSpace::Srgb))]
+    gamut: ColorSpace,
+    /// Platform to fit the output to: discord, discord-emoji, telegram-sticker, or twitter. Picks
+    /// the format for outputs without an extension, and caps the size, framerate, and file size.
+    /// Can also be the name of options saved with the preset save command.
+    #[bpaf(long, argument("PRESET"), complete(complete_preset))]
+    preset: Option<Preset>,
+    /// Only render every Nth frame, at full quality, for a short clip to check a long render by
+    #[bpaf(long, argument("N"))]
+    proof: Option<NonZeroUsize>,
+    /// Render a quick preview instead: a lower order scaled up to the same size, half the frames at
+    /// half the framerate, and the fastest encoder settings
+    draft: bool,
+    /// Print the settings, and roughly the memory, time, and output sizes the render would take
+    /// from timing a few frames, without writing anything
+    dry_run: bool,
+    /// Print which output formats and video encoders can be used, from what's built in and what
+    /// FFMpeg can encode with on this machine, without rendering anything
+    list_formats: bool,
+    /// Also write <output>.json next to each output, with the parameters, encoder settings, timing,
+    /// and a checksum of each frame
+    manifest: bool,
+    /// Render exactly the same bytes for the same version and options on any machine. Colors are
+    /// rounded so math libraries' last bits can't change them, FFMpeg leaves its version out,
+    /// archives get fixed times, and --manifest also records a checksum of the whole output.
+    deterministic: bool,
+    /// Config file to take options from, where the command line doesn't give them. Read from
+    /// hilbert_animation.toml if there is one and this isn't given.
+    #[bpaf(long, argument("PATH"))]
+    config: Option<PathBuf>,
+    /// Read options from stdin as a JSON object, like {"order": 8, "outputs": "out.webp"}, under
+    /// the command line's and over config files'
+    stdin_json: bool,
+    /// Render a draft, then render it again whenever the config file or saved preset changes,
+    /// until stopped with Ctrl-C
+    watch: bool,
+    /// Show a desktop notification with the outputs and how long they took when the render
+    /// finishes or fails
+    notify: bool,
+    /// Overwrite outputs that already exist, instead of refusing to. Old frames in a directory of
+    /// frames are removed, and other files in it are left alone
+    force: bool,
+    /// Pick up an interrupted frame directory render where it stopped, keeping the frames it
+    /// already wrote
+    resume: bool,
+    /// Only render this part of the frames, like 2/8 for the second of eight, to spread a render
+    /// over several machines. Join the parts with the merge command.
+    #[bpaf(long, argument("INDEX/COUNT"))]
+    shard: Option<Shard>,
+    /// Command to run after each output is written, like "notify-send {output}". {output},
+    /// {format}, {order}, {size}, {function}, {frames}, and {framerate} are filled in. It's split
+    /// on spaces and run without a shell.
+    #[bpaf(long, argument("COMMAND"))]
+    post_cmd: Option<String>,
+    /// Largest file to write, like 8MB or 500KiB. Gif, webp, and video outputs are encoded again
+    /// at lower qualities until they fit.
+    #[bpaf(long, argument("SIZE"))]
+    max_size: Option<ByteSize>,
+    /// Memory to stay under, like 2GB. Fewer frames are rendered ahead of the encoders, and
+    /// --max-size renders frames again for each attempt instead of keeping them all.
+    #[bpaf(long, argument("SIZE"))]
+    max_memory: Option<ByteSize>,
+    /// Output format: gif, webp, webm, mp4, frames, exr, zip, or tar. Taken from the output's
+    /// extension if not given, where no extension means frames, or --preset's format. Needed when
+    /// writing gif, webp, or tar outputs to stdout with -.
+    #[bpaf(long, argument("FORMAT"))]
+    format: Option<OutputFormat>,
+    /// Files to write, out.webp if none are given, or out with the extension for --preset. Several
+    /// outputs are all encoded from a single render of the frames. {order}, {size}, {function},
+    /// {frames}, {framerate}, and the {date} and {time} in UTC are filled in.
+    #[bpaf(positional("OUTPUT"), many)]
+    outputs: Vec<PathBuf>,
+}
+
+fn valid_webp_quality(quality: &f32) -> bool {
+    (0.0..=100.0).contains(quality)
+}
+
+fn valid_opacity(opacity: &f32) -> bool {
+    (0.0..=1.0).contains(opacity)
+}
+
+fn valid_gif_colors(colors: &usize) -> bool {
+    (2..=256).contains(colors)
+}
+
+#[cfg(feature = "gifski")]
+fn valid_gif_quality(quality: &u8) -> bool {
+    (1..=100).contains(quality)
+}
+
+fn valid_jpeg_quality(quality: &u8) -> bool {
+    (1..=100).contains(quality)
+}
+
+fn valid_webp_method(method: &usize) -> bool {
+    *method <= 6
+}
+
+fn valid_supersample(factor: &u32) -> bool {
+    matches!(factor, 1 | 2 | 4)
+}
+
+/// A channel type frames can be rendered with.
+trait Channel: Primitive + Send + Sync {
+    /// FFMpeg's name for raw RGBA frames with this channel type, in little endian.
+    const FFMPEG_PIX_FMT: &'static str;
+
+    fn encode(color: LinSrgba<f64>, encoder: &ColorEncoder) -> [Self; 4];
+
+    fn to_le_bytes(samples: &[Self]) -> Cow<'_, [u8]>;
+
+    /// Hands a frame's samples back once it's encoded, for types that pool their frames.
+    fn recycle(_params: &Params, _samples: Vec<Self>) {}
+}
+
+impl Channel for u8 {
+    const FFMPEG_PIX_FMT: &'static str = "rgba";
+
+    fn encode(color: LinSrgba<f64>, encoder: &ColorEncoder) -> [Self; 4] {
+        encoder.encode_u8(color).into()
+    }
+
+    fn to_le_bytes(samples: &[Self]) -> Cow<'_, [u8]> {
+        Cow::Borrowed(samples)
+    }
+
+    fn recycle(params: &Params, samples: Vec<Self>) {
+        params.recycle_samples(samples);
+    }
 }
 
+impl Channel for u16 {
+    const FFMPEG_PIX_FMT: &'static str = "rgba64le";
+
+    fn encode(color: LinSrgba<f64>, encoder: &ColorEncoder) -> [Self; 4] {
+        encoder.encode(color).into_format::<u16, u16>().into()
+    }
+
+    fn to_le_bytes(samples: &[Self]) -> Cow<'_, [u8]> {
+        Cow::Owned(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Gif,
+    Webp,
+    Webm,
+    Mp4,
+    Frames,
+    Exr,
+    Zip,
+    Tar,
+}
+
+impl OutputFormat {
+    /// Whether the format can be written in one go, without seeking or an output directory.
+    fn supports_stdout(self) -> bool {
+        matches!(self, Self::Gif | Self::Webp | Self::Tar)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+            Self::Webm => "webm",
+            Self::Mp4 => "mp4",
+            Self::Frames => "frames",
+            Self::Exr => "exr",
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(Self::Gif),
+            "webp" => Ok(Self::Webp),
+            "webm" => Ok(Self::Webm),
+            "mp4" => Ok(Self::Mp4),
+            "frames" => Ok(Self::Frames),
+            "exr" => Ok(Self::Exr),
+            "zip" => Ok(Self::Zip),
+            "tar" => Ok(Self::Tar),
+            _ => Err(format!(
+                "unknown format '{s}', expected gif, webp, webm, mp4, frames, exr, zip, or tar"
+            )),
+        }
+    }
+}
+
+/// One of the built in functions coloring the curve, from a position along it and its length.
+type ColorFunction = fn(u64, u64) -> LinSrgba<f64>;
+
+/// Where encoded outputs are written, either a file or stdout.
+type Output = BufWriter<Box<dyn Write + Send>>;
+
 #[derive(Debug, Clone)]
 struct Params {
     order: u8,
+    function: String,
     image_size: u32,
     num_pixels: u64,
+    /// Each pixel's position along the curve, in row major order. Only the offset changes
+    /// between frames, so it's worked out once and shared.
+    curve: Arc<Vec<u64>>,
+    frame_pool: Arc<FramePool>,
     frames: usize,
     framerate: u32,
-    loops: NonZeroU32,
-    bitrate: Option<String>,
+    loops: Option<NonZeroU32>,
+    bitrate: Option<Bitrate>,
+    crf: Option<u8>,
+    encode_preset: Option<EncodePreset>,
+    hwaccel: Option<HwAccel>,
+    threads: Option<NonZeroUsize>,
+    ffmpeg_path: PathBuf,
+    ffmpeg_args: Vec<String>,
+    ffmpeg_fallback: bool,
+    segments: NonZeroUsize,
+    keep_segments: bool,
+    frames_dir: Option<PathBuf>,
+    crop: Option<Crop>,
+    supersample: u32,
+    output_size: Option<Dimensions>,
+    scale_filter: ScaleFilter,
+    /// Text and a watermark to draw onto frames, drawn once and shared.
+    overlay: Option<Arc<Overlay>>,
+    debug_overlay: bool,
+    strip_rows: Option<NonZeroUsize>,
+    audio: Option<PathBuf>,
+    two_pass: bool,
+    alpha: bool,
+    pix_fmt: PixelFormat,
+    bit_depth: BitDepth,
+    lossless: bool,
+    webp_quality: Option<f32>,
+    webp_method: Option<usize>,
+    webp_preset: WebpPreset,
+    webp_mixed: bool,
+    webp_sharp_yuv: bool,
+    dither: Dither,
+    colors: usize,
+    palette_cycle: bool,
+    #[cfg(feature = "gifski")]
+    gifski: bool,
+    #[cfg(feature = "gifski")]
+    gif_quality: u8,
+    #[cfg(feature = "gifski")]
+    gif_fast: bool,
+    frame_format: FrameFormat,
+    frame_pattern: Option<FramePattern>,
+    frame_start: usize,
+    jpeg_quality: u8,
+    background: Option<BackgroundColor>,
+    color_space: ColorSpace,
+    preset: Option<Platform>,
+    manifest: bool,
+    deterministic: bool,
+    force: bool,
+    resume: bool,
+    shard: Option<Shard>,
+    proof: Option<Proof>,
+    dry_run: bool,
+    post_cmd: Option<String>,
+    max_size: Option<ByteSize>,
+    max_memory: Option<ByteSize>,
+    format: Option<OutputFormat>,
     filename: PathBuf,
 }
 
 impl Params {
-    fn new(options: Options) -> Self {
+    fn new(mut options: Options) -> Result<Self> {
+        if let Some(duration) = options.duration {
+            // At the framerate --preset plays it at, so it still lasts as long
+            let framerate = options.platform().map_or(options.framerate, |platform| {
+                platform.framerate(options.framerate)
+            });
+            options.frames = duration.frames(framerate);
+        }
+        options.validate()?;
+        let platform = options.platform();
+        if let Some(platform) = platform {
+            platform.apply(&mut options);
+        }
+        if options.draft {
+            draft::apply(&mut options);
+        }
         let order = options.order;
         let image_size = 2u32.pow(order as u32);
-        let num_pixels = (image_size as u64).pow(2);
+        let window = options.crop.unwrap_or(Crop::full(image_size));
+        window.check(image_size)?;
+        // Positions along the curve, of which there are more than pixels when supersampling
+        let num_pixels = (image_size as u64 * options.supersample as u64).pow(2);
+        let proof = options.proof.map(|every| Proof::new(every, options.frames));
+        let frame_size = options
+            .output_size
+            .map_or((window.width, window.height), |size| {
+                (size.width, size.height)
+            });
+        let overlay = Overlay::new(&options, frame_size)?.map(Arc::new);
 
-        Self {
+        Ok(Self {
             order,
+            function: options.function,
             image_size,
             num_pixels,
-            frames: options.frames,
+            // Rendering in strips never looks at whole frames, and the curve would be as big as one
+            curve: Arc::new(match options.strip_rows {
+                Some(_) => Vec::new(),
+                None => hilbert_curve(order, window, options.supersample),
+            }),
+            frame_pool: Arc::default(),
+            frames: proof.map_or(options.frames, Proof::rendered_frames),
             framerate: options.framerate,
             loops: options.loops,
             bitrate: options.bitrate,
-            filename: options.filename,
+            crf: options.crf,
+            encode_preset: options.encode_preset,
+            hwaccel: options.hwaccel,
+            threads: options.threads,
+            ffmpeg_path: options.ffmpeg_path,
+            ffmpeg_args: options.ffmpeg_args,
+            ffmpeg_fallback: options.ffmpeg_fallback,
+            segments: options.segments,
+            keep_segments: options.keep_segments,
+            frames_dir: options.frames_dir,
+            crop: options.crop,
+            supersample: options.supersample,
+            output_size: options.output_size,
+            scale_filter: options.scale_filter,
+            overlay,
+            debug_overlay: options.debug_overlay,
+            strip_rows: options.strip_rows,
+            audio: options.audio,
+            two_pass: options.two_pass,
+            alpha: options.alpha,
+            pix_fmt: options.pix_fmt,
+            bit_depth: options.bit_depth,
+            lossless: options.lossless,
+            webp_quality: options.webp_quality,
+            webp_method: options.webp_method,
+            webp_preset: options.webp_preset,
+            webp_mixed: options.webp_mixed,
+            webp_sharp_yuv: options.webp_sharp_yuv,
+            dither: options.dither,
+            colors: options.colors,
+            palette_cycle: options.palette_cycle,
+            #[cfg(feature = "gifski")]
+            gifski: options.gifski,
+            #[cfg(feature = "gifski")]
+            gif_quality: options.gif_quality,
+            #[cfg(feature = "gifski")]
+            gif_fast: options.gif_fast,
+            frame_format: options.frame_format,
+            frame_pattern: options.frame_pattern,
+            frame_start: options.frame_start,
+            jpeg_quality: options.jpeg_quality,
+            background: options.background,
+            color_space: options.gamut,
+            preset: platform,
+            manifest: options.manifest,
+            deterministic: options.deterministic,
+            force: options.force,
+            resume: options.resume,
+            shard: options.shard,
+            proof,
+            dry_run: options.dry_run,
+            post_cmd: options.post_cmd,
+            max_size: options.max_size,
+            max_memory: options.max_memory,
+            format: options.format,
+            filename: options.outputs.into_iter().next().unwrap_or_else(|| {
+                let format = platform.map_or(OutputFormat::Webp, Platform::format);
+                format!("out.{}", format.name()).into()
+            }),
+        })
+    }
+
+    fn output_format(&self) -> Result<OutputFormat> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        match self.filename.extension() {
+            None => Ok(self.preset.map_or(OutputFormat::Frames, Platform::format)),
+            Some(ext) => ext
+                .to_str()
+                .and_then(|ext| ext.parse().ok())
+                .with_context(|| {
+                    format!(
+                        "Can't tell the output format from '{}', pass --format",
+                        self.filename.display()
+                    )
+                }),
         }
     }
 
-    fn gen_image<F>(&self, color: F, offset: u64) -> RgbaImage
+    fn is_stdout(&self) -> bool {
+        self.filename.as_os_str() == "-"
+    }
+
+    /// Opens the output file, or stdout for `-`.
+    fn create_output(&self) -> Result<Output> {
+        let output: Box<dyn Write + Send> = if self.is_stdout() {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(&self.filename).context("Failed to open file")?)
+        };
+        Ok(BufWriter::new(output))
+    }
+
+    fn frame_offset(&self, frame: usize) -> u64 {
+        let (frame, frames) = self.animation_frame(frame);
+        frame as u64 * self.num_pixels / frames as u64
+    }
+
+    /// Every color along the curve, encoded once up front. Frames only differ in where along the
+    /// curve they start, so each one is then just lookups into this.
+    fn color_table<C, F>(&self, color: F) -> Vec<[C; 4]>
     where
-        F: Fn(u64, u64) -> Srgba<u8>,
+        C: Channel,
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
     {
-        RgbaImage::from_fn(self.image_size, self.image_size, |x, y| {
-            let i = (fast_hilbert::xy2h(x, y, self.order) + offset) % self.num_pixels;
-            let (r, g, b, a) = color(i, self.num_pixels).into_components();
-            // dbg!((r, g, b));
-            image::Rgba([r, g, b, a])
-        })
+        let encoder = self.color_space.encoder();
+        let color = self.curve_colors(&color);
+        (0..self.num_pixels)
+            .into_par_iter()
+            .map(|i| C::encode(color(i), &encoder))
+            .collect()
     }
 
-    fn write_gif<I>(&self, frames: I) -> Result<()>
+    /// Every color along the curve as linear light floats, skipping sRGB encoding entirely. These
+    /// stay linear sRGB whatever the --gamut, since floats can hold colors outside it anyway.
+    fn linear_color_table<F>(&self, color: F) -> Vec<[f32; 4]>
     where
-        I: ParallelIterator<Item = RgbaImage> + IndexedParallelIterator,
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
     {
-        let mut frames_vec = Vec::with_capacity(self.frames);
-        par_tqdm!(frames.map_with(self.framerate, |framerate, frame| {
-            image::Frame::from_parts(
-                frame,
-                0,
-                0,
-                image::Delay::from_numer_denom_ms(1000, *framerate),
-            )
-        }))
-        .collect_into_vec(&mut frames_vec);
-
-        let file = BufWriter::new(File::create(&self.filename).context("Failed to open file")?);
-        let mut encoder = GifEncoder::new(file);
-        encoder
-            .encode_frames(tqdm!(frames_vec.into_iter()))
-            .context("failed to write frames")?;
+        let color = self.curve_colors(&color);
+        (0..self.num_pixels)
+            .into_par_iter()
+            .map(|i| {
+                let color: LinSrgba<f32> = color(i).into_format();
+                color.into()
+            })
+            .collect()
+    }
 
-        Ok(())
+    /// Renders frame `i` from a table of colors along the curve. Rows are filled in parallel, so
+    /// a single huge frame still uses every core.
+    fn gen_image<C>(&self, colors: &[[C; 4]], i: usize) -> ImageBuffer<Rgba<C>, Vec<C>>
+    where
+        C: Primitive + Send + Sync + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        self.gen_image_into(colors, i, None)
     }
 
-    fn write_webp<I>(&self, frames: I) -> Result<()>
+    /// Renders frame `i` over the samples in `buffer`, or into a new one, then resizes it to
+    /// --output-size.
+    fn gen_image_into<C>(
+        &self,
+        colors: &[[C; 4]],
+        i: usize,
+        buffer: Option<Vec<C>>,
+    ) -> ImageBuffer<Rgba<C>, Vec<C>>
     where
-        I: ParallelIterator<Item = RgbaImage> + IndexedParallelIterator,
+        C: Primitive + Send + Sync + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
     {
-        let mut frames_vec = Vec::with_capacity(self.frames);
-        par_tqdm!(frames).collect_into_vec(&mut frames_vec);
-
-        let mut webp_encoder = Encoder::new_with_options(
-            (self.image_size, self.image_size),
-            EncoderOptions {
-                minimize_size: true,
-                ..Default::default()
-            },
-        )
-        .context("Failed to initialize webp encoder")?;
-
-        let mut timestamp: f64 = 0.0;
-        for frame in tqdm!(frames_vec.into_iter()) {
-            webp_encoder
-                .add_frame(frame.as_flat_samples().samples, timestamp.round() as i32)
-                .context("Failed to add frame to webp")?;
-            timestamp += 1000.0 / self.framerate as f64;
+        let offset = self.frame_offset(i);
+        let window = self.window();
+        let width = window.width as usize;
+        let samples = width * window.height as usize * 4;
+        let per_pixel = (self.supersample * self.supersample) as usize;
+        let buffer = buffer
+            .filter(|buffer| buffer.len() == samples)
+            .unwrap_or_else(|| vec![C::zero(); samples]);
+        let mut image = ImageBuffer::from_raw(window.width, window.height, buffer).unwrap();
+        image
+            .par_chunks_mut(width * 4)
+            .zip(self.curve.par_chunks(width * per_pixel))
+            .for_each(|(row, curve)| {
+                for (pixel, positions) in row.chunks_exact_mut(4).zip(curve.chunks(per_pixel)) {
+                    if let [h] = positions {
+                        pixel.copy_from_slice(&colors[((h + offset) % self.num_pixels) as usize]);
+                    } else {
+                        pixel.copy_from_slice(&self.average_color(colors, positions, offset));
+                    }
+                }
+            });
+        let mut image = self.scale_frame(image);
+        if let Some(overlay) = &self.overlay {
+            overlay.draw(&mut image);
         }
-
-        let webp_data = webp_encoder
-            .finalize(timestamp.round() as i32)
-            .context("Failed to finalize webp")?;
-        let mut file = BufWriter::new(File::create(&self.filename).context("Failed to open file")?);
-        file.write_all(webp_data.as_ref())
-            .context("Failed to write webp to file")?;
-
-        Ok(())
+        if self.debug_overlay {
+            self.draw_debug_overlay(&mut image, i);
+        }
+        image
     }
 
-    fn write_frames<I>(&self, frames: I, out_dir: Option<&Path>) -> Result<()>
+    /// Renders every frame in parallel on the rayon pool and hands them to `consume` in order.
+    /// Only a window of frames is rendered ahead of the consumer, so just a few are held in memory
+    /// at a time. Workers never wait on the consumer, which would starve anything else (like
+    /// gifski) that needs the pool.
+    fn stream_frames<T, R, C>(&self, render: R, mut consume: C) -> Result<()>
     where
-        I: ParallelIterator<Item = RgbaImage> + IndexedParallelIterator,
+        T: Send,
+        R: Fn(usize) -> T + Sync,
+        C: FnMut(T) -> Result<()>,
     {
-        let out_dir = out_dir.unwrap_or(&self.filename);
+        let window = self.frame_budget(rayon::current_num_threads() * 2);
+        let (sender, receiver) = mpsc::channel();
 
-        if self.filename.is_dir() {
-            fs::remove_dir_all(out_dir).context("Failed to remove existing output dir")?;
-        }
-        fs::create_dir_all(out_dir).context("Failed to create output dir")?;
-
-        par_tqdm!(frames.enumerate()).try_for_each_with(out_dir, |out_dir, (i, frame)| {
-            frame
-                .save(out_dir.join(format!("frame_{i:05}.png")))
-                .with_context(|| format!("Failed to save frame {i}"))
-        })?;
-
-        Ok(())
-    }
-
-    fn frames_to_webm(&self, frames_dir: &Path) -> Result<()> {
-        std::process::Command::new("ffmpeg")
-            .args(
-                [
-                    "-y",
-                    "-framerate",
-                    &self.framerate.to_string(),
-                    "-stream_loop",
-                    &(self.loops.get() - 1).to_string(),
-                    "-pattern_type",
-                    "glob",
-                    "-i",
-                ]
-                .into_iter()
-                .map(OsStr::new)
-                .chain(once(frames_dir.join("*.png").as_os_str()))
-                .chain(
-                    [
-                        "-c:v",
-                        "libvpx-vp9",
-                        // "-deadline",
-                        // "best",
-                        // "-cpu-used",
-                        // "1"
-                    ]
-                    .map(OsStr::new),
-                )
-                .chain(
-                    self.bitrate
-                        .as_ref()
-                        .map(|b| [OsStr::new("-b:v"), OsStr::new(b)].into_iter())
-                        .into_iter()
-                        .flatten(),
-                )
-                .chain(once(self.filename.as_os_str())),
-            )
-            .spawn()
-            .context("Failed to run FFMpeg")?
-            .wait()
-            .context("FFMpeg failed")?;
+        rayon::in_place_scope(|scope| {
+            let render = &render;
+            let spawn_frame = |i: usize| {
+                let sender = sender.clone();
+                scope.spawn(move |_| {
+                    let _ = sender.send((i, render(i)));
+                });
+            };
+            (0..self.frames.min(window)).for_each(spawn_frame);
 
-        Ok(())
+            let progress = Progress::new("render", self.frames);
+            let mut pending = BTreeMap::new();
+            for i in 0..self.frames {
+                let frame = loop {
+                    if let Some(frame) = pending.remove(&i) {
+                        break frame;
+                    }
+                    let (rendered, frame) = receiver.recv()?;
+                    pending.insert(rendered, frame);
+                };
+                consume(frame)?;
+                if i + window < self.frames {
+                    spawn_frame(i + window);
+                }
+                progress.update(1);
+            }
+            Ok(())
+        })
     }
 }
 
-fn oklab_hue(i: u64, size: u64) -> Srgba<u8> {
+/// Each pixel's position along the curve, in row major order, for the pixels in `window`. When
+/// supersampling, the curve is `supersample` times the size, and each pixel gets all the positions
+/// it covers in a row.
+fn hilbert_curve(order: u8, window: Crop, supersample: u32) -> Vec<u64> {
+    let (width, height) = (window.width as u64, window.height as u64);
+    let factor = supersample as u64;
+    let order = order + supersample.trailing_zeros() as u8;
+    (0..width * height * factor * factor)
+        .into_par_iter()
+        .map(|i| {
+            let (pixel, sample) = (i / (factor * factor), i % (factor * factor));
+            let x = (window.x as u64 + pixel % width) * factor + sample % factor;
+            let y = (window.y as u64 + pixel / width) * factor + sample / factor;
+            fast_hilbert::xy2h(x as u32, y as u32, order)
+        })
+        .collect()
+}
+
+fn oklab_hue(i: u64, size: u64) -> LinSrgba<f64> {
     let degrees = i as f64 / size as f64 * 360.0;
     let color = Okhsva::new(OklabHue::new(degrees), 1.0, 1.0, 1.0);
-    let rgb_color: LinSrgba<f64> = color.into_color();
-    rgb_color.into_encoding()
+    color.into_color()
 }
 
-fn oklab_hue_sine_value(i: u64, size: u64) -> Srgba<u8> {
+fn oklab_hue_sine_value(i: u64, size: u64) -> LinSrgba<f64> {
     let progress = i as f64 / size as f64;
     let hue = OklabHue::new(progress * 360.0);
     let sine_cycles = 8.0;
     let value = (progress * 2.0 * std::f64::consts::PI * sine_cycles).sin() * 0.375 + 0.625;
     let color = Okhsva::new(hue, 1.0, value, 1.0);
-    let rgb_color: LinSrgba<f64> = color.into_color();
-    rgb_color.into_encoding()
+    color.into_color()
 }
 
-fn square_value(i: u64, size: u64) -> Srgba<u8> {
+fn square_value(i: u64, size: u64) -> LinSrgba<f64> {
     let progress = (i as f64 / size as f64 * 2.0).rem(1.0);
     let value = -(progress * 2.0 - 1.0).powf(2.0) + 1.0;
     let color = Okhsva::new(OklabHue::new(0.0), 0.0, value, 1.0);
-    let rgb_color: LinSrgba<f64> = color.into_color();
-    rgb_color.into_encoding()
+    color.into_color()
 }
 
 fn square_channel(progress: f64) -> f64 {
     (-(progress * 4.0 - 2.0).powf(2.0) + 1.0).max(0.0)
 }
 
-fn square_linsrgb_channels(i: u64, size: u64) -> Srgba<u8> {
+fn square_linsrgb_channels(i: u64, size: u64) -> LinSrgba<f64> {
     let progress = i as f64 / size as f64;
     let red_progress = (progress + (1.0 / 3.0)).rem_euclid(1.0);
     let green_progress = progress;
     let blue_progress = (progress - (1.0 / 3.0)).rem_euclid(1.0);
-    let color = LinSrgba::new(
+    LinSrgba::new(
         square_channel(red_progress),
         square_channel(green_progress),
         square_channel(blue_progress),
         1.0,
-    );
-    color.into_encoding()
-}
-
-fn main() {
-    let opts = options().run();
-    let function = match &*opts.function {
-        "oklab_hue" => oklab_hue,
-        "oklab_hue_sine_value" => oklab_hue_sine_value,
-        "square_value" => square_value,
-        "square_linsrgb_channels" => square_linsrgb_channels,
-        _ => panic!("unknown function {}", opts.function),
+    )
+}
+
+fn main() -> ExitCode {
+    logging::init();
+    interrupt::install();
+    let cli = match cli().run_inner(Args::current_args()) {
+        Ok(cli) => cli,
+        Err(failure) => return exit::parse_failure(failure),
+    };
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", exit::message(&err));
+            exit::code(&err)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let opts = match cli {
+        Cli::Merge(merge) => return merge.run(),
+        Cli::Preset(preset) => return preset.run(),
+        Cli::Batch(batch) => return batch.run(),
+        Cli::Preview(preview) => return preview.run(),
+        Cli::ContactSheet(sheet) => return sheet.run(),
+        Cli::Diff(diff) => return diff.run(),
+        Cli::Verify(verify) => return verify.run(),
+        Cli::SelfTest(self_test) => return self_test.run(),
+        Cli::Completions(shell) => shell.print(),
+        Cli::Manpage => {
+            completions::print_manpage();
+            return Ok(());
+        }
+        Cli::Wizard => return wizard::run(),
+        Cli::Render(options) if options.watch => return watch::run(options),
+        Cli::Render(options) => {
+            config::apply(options, env::args_os().skip(1).collect()).map_err(exit::usage)?
+        }
     };
-    let params = Params::new(opts);
+    logging::set_verbosity(opts.quiet, opts.verbose);
+    if opts.list_formats {
+        return formats::list(&opts.ffmpeg_path);
+    }
+    progress::set_style(opts.progress, opts.progress_stages.clone(), opts.quiet);
+    let threads = start_thread_pool(opts.threads, opts.nice)?;
+    render(Options { threads, ..opts })
+}
 
-    let frames = (0..params.frames)
-        .into_par_iter()
-        .map_with(params.clone(), |params, i| {
-            let offset = i as u64 * params.num_pixels / params.frames as u64;
-            params.gen_image(function, offset)
+/// Starts the thread pool renders share, returning the threads FFMpeg is also limited to.
+fn start_thread_pool(threads: Option<NonZeroUsize>, nice: bool) -> Result<Option<NonZeroUsize>> {
+    let mut threads = threads;
+    if nice {
+        // Before the thread pool and FFMpeg are started, so they all inherit it
+        nice::lower_priority()?;
+        threads = threads.or(Some(nice::background_threads()));
+    }
+    if let Some(threads) = threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .build_global()
+            .context("Failed to start the thread pool")?;
+    }
+    debug!("Rendering on {} threads", rayon::current_num_threads());
+    Ok(threads)
+}
+
+/// The built in functions, by the name --function takes. Curves longer than 2^20 positions only
+/// run a function at that many evenly spaced points and interpolate between them (see
+/// `color_samples.rs`), so functions need to change smoothly with `i / size`, with nothing finer
+/// than a millionth of the curve that would be lost.
+const FUNCTIONS: &[(&str, ColorFunction)] = &[
+    ("oklab_hue", oklab_hue),
+    ("oklab_hue_sine_value", oklab_hue_sine_value),
+    ("square_value", square_value),
+    ("square_linsrgb_channels", square_linsrgb_channels),
+];
+
+/// The built in function with this name.
+fn color_function(name: &str) -> Result<ColorFunction> {
+    match FUNCTIONS.iter().find(|(function, _)| *function == name) {
+        Some((_, function)) => Ok(*function),
+        None => {
+            let names: Vec<&str> = FUNCTIONS.iter().map(|(function, _)| *function).collect();
+            let (last, rest) = names.split_last().unwrap();
+            bail!(
+                "unknown function '{name}'{}, expected {}, or {last}",
+                suggest::did_you_mean(name, names.iter().copied()),
+                rest.join(", ")
+            )
+        }
+    }
+}
+
+/// Renders the animation the options describe to each of their outputs.
+fn render(opts: Options) -> Result<()> {
+    render_plan(plan(opts).map_err(exit::usage)?)
+}
+
+/// Renders each output of a plan, notifying when it's done for --notify. Mistakes in the options
+/// are found while planning, before there's a render to notify about.
+fn render_plan(plan: Plan) -> Result<()> {
+    let notify = plan.notify.then(|| (plan.filenames(), Instant::now()));
+    let rendered = render_outputs(plan);
+    if let Some((outputs, started)) = notify {
+        notify::render_finished(&outputs, started.elapsed(), &rendered);
+    }
+    rendered
+}
+
+fn render_outputs(plan: Plan) -> Result<()> {
+    let started = SystemTime::now();
+    let Plan {
+        function,
+        params,
+        outputs,
+        ..
+    } = plan;
+    if params.dry_run {
+        return params.print_dry_run(&outputs, function);
+    }
+    debug!("{}", params.description());
+    for (params, format) in &outputs {
+        debug!("Writing {} as {}", params.filename.display(), format.name());
+    }
+    // Several outputs share a single render of the frames
+    let stages = match &outputs[..] {
+        [(params, format)] => params.progress_stages(*format),
+        _ => 1 + usize::from(params.manifest),
+    };
+    let _progress = progress::start_render(stages);
+    if let [(params, format)] = &outputs[..] {
+        params.write_atomically(*format, |params| write_output(params, *format, function))?;
+        params.write_shard_record(*format)?;
+    } else {
+        params.write_outputs(&outputs, function)?;
+    }
+    let finished = SystemTime::now();
+    if params.manifest {
+        for (params, format) in &outputs {
+            params.write_manifest(function, *format, started, finished)?;
+        }
+    }
+    for (params, format) in &outputs {
+        params.run_post_cmd(*format)?;
+    }
+    Ok(())
+}
+
+/// What a render draws, and each output it writes.
+struct Plan {
+    function: ColorFunction,
+    params: Params,
+    outputs: Vec<(Params, OutputFormat)>,
+    /// Whether to show a notification once it's rendered, for --notify.
+    notify: bool,
+}
+
+impl Plan {
+    /// Where each output is written, with the placeholders in their names filled in.
+    fn filenames(&self) -> Vec<PathBuf> {
+        self.outputs
+            .iter()
+            .map(|(params, _)| params.filename.clone())
+            .collect()
+    }
+}
+
+/// Works out what the options render and to which outputs, checking everything that can be
+/// before any rendering starts. Everything that fails here is a mistake in the options.
+fn plan(opts: Options) -> Result<Plan> {
+    let function = color_function(&opts.function)?;
+    let filenames = opts.outputs.clone();
+    let notify = opts.notify && !opts.dry_run;
+    let params = Params::new(opts)?;
+    // Every output gets the same {date} and {time}
+    let now = SystemTime::now();
+    let filenames = filenames
+        .iter()
+        .map(|filename| params.fill_filename(filename, now))
+        .collect::<Result<Vec<_>>>()?;
+    let params = Params {
+        filename: params.fill_filename(&params.filename, now)?,
+        ..params
+    };
+    params.check_memory()?;
+    if filenames.len() > 1 {
+        let outputs = filenames
+            .into_iter()
+            .map(|filename| {
+                check_output(Params {
+                    filename,
+                    ..params.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        params.check_outputs(&outputs)?;
+        return Ok(Plan {
+            function,
+            params,
+            outputs,
+            notify,
         });
+    }
+
+    let (params, format) = check_output(params)?;
+    Ok(Plan {
+        function,
+        params: params.clone(),
+        outputs: vec![(params, format)],
+        notify,
+    })
+}
 
-    match params.filename.extension().and_then(|ext| ext.to_str()) {
-        Some("gif") => params
-            .write_gif(frames)
-            .context("Failed to write gif")
-            .unwrap(),
-        Some("webp") => params
-            .write_webp(frames)
-            .context("Failed to write webp")
-            .unwrap(),
-        Some("webm") => {
-            let temp_frames_path = Path::new("_frames_out");
+/// Works out an output's format and checks the options can be used to write it, whether it's the
+/// only output or one of several.
+fn check_output(params: Params) -> Result<(Params, OutputFormat)> {
+    let format = params.output_format()?;
+    let (params, format) = params.check_ffmpeg(format)?;
+    params.check_preset(format)?;
+    params.check_color_space(format)?;
+    params.check_overwrite(format)?;
+    params.check_strips(format)?;
+    params.check_supersample(format)?;
+    params.check_output_size(format)?;
+    params.check_overlay(format)?;
+    params.check_resume(format)?;
+    params.check_shard(format)?;
+    params.check_deterministic(format)?;
+    if params.is_stdout() && !format.supports_stdout() {
+        bail!("{} outputs can't be written to stdout", format.name());
+    }
+    if params.audio.is_some() && !matches!(format, OutputFormat::Webm | OutputFormat::Mp4) {
+        bail!("--audio only works for webm and mp4 outputs");
+    }
+    if params.manifest && params.is_stdout() {
+        bail!("--manifest can't be used when writing to stdout");
+    }
+    if params.post_cmd.is_some() && params.is_stdout() {
+        bail!("--post-cmd can't be used when writing to stdout");
+    }
+    Ok((params, format))
+}
+
+fn write_output(params: &Params, format: OutputFormat, function: ColorFunction) -> Result<()> {
+    if let Some(max_size) = params.max_size {
+        return params
+            .write_within_size(format, function, max_size)
+            .with_context(|| format!("Failed to write {}", format.name()));
+    }
+
+    match format {
+        OutputFormat::Gif => {
+            let colors = params.color_table(function);
             params
-                .write_frames(frames, Some(temp_frames_path))
-                .context("Failed to write frames")
-                .unwrap();
+                .write_gif(&colors, |i| params.gen_frame(&colors, i))
+                .context("Failed to write gif")
+        }
+        OutputFormat::Webp => {
+            let colors = params.color_table::<u8, _>(function);
+            params
+                .write_webp(|i| params.gen_frame(&colors, i))
+                .context("Failed to write webp")
+        }
+        OutputFormat::Webm | OutputFormat::Mp4 => {
+            let container = match format {
+                OutputFormat::Webm => Container::Webm,
+                _ => Container::Mp4,
+            };
+            match (params.bit_depth, params.strip_rows) {
+                (BitDepth::Eight, Some(_)) => {
+                    params.write_video_strips::<u8, _>(function, container)
+                }
+                (_, Some(_)) => params.write_video_strips::<u16, _>(function, container),
+                (BitDepth::Eight, None) => {
+                    let colors = params.color_table::<u8, _>(function);
+                    params.write_video(|i| params.gen_frame(&colors, i), container)
+                }
+                (BitDepth::Ten | BitDepth::Sixteen, None) => {
+                    let colors = params.color_table::<u16, _>(function);
+                    params.write_video(|i| params.gen_image(&colors, i), container)
+                }
+            }
+            .with_context(|| format!("Failed to write {}", format.name()))
+        }
+        OutputFormat::Zip | OutputFormat::Tar => {
+            let archive = match format {
+                OutputFormat::Zip => Archive::Zip,
+                _ => Archive::Tar,
+            };
             params
-                .frames_to_webm(temp_frames_path)
-                .context("Failed to convert frames to webm")
-                .unwrap();
+                .write_frame_archive(function, params.frame_format, archive)
+                .with_context(|| format!("Failed to write {}", format.name()))
         }
-        None => params
-            .write_frames(frames, None)
-            .context("Failed to write frames")
-            .unwrap(),
-        Some(ext) => panic!("unknown format '{}'", ext),
+        OutputFormat::Exr => params
+            .write_frames(function, FrameFormat::Exr)
+            .context("Failed to write frames"),
+        OutputFormat::Frames => params
+            .write_frames(function, params.frame_format)
+            .context("Failed to write frames"),
     }
 }
@@ -0,0 +1,158 @@
+use std::{
+    fmt::Debug,
+    fs,
+    num::NonZeroU32,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use palette::LinSrgba;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde_json::{json, Value};
+
+use crate::{progress::Progress, OutputFormat, Params};
+
+/// An option's value as it's written on the command line, like `floyd-steinberg` for
+/// `Dither::FloydSteinberg`.
+fn option_name(value: impl Debug) -> String {
+    let mut name = String::new();
+    for (i, c) in format!("{value:?}").chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            name.push('-');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+impl Params {
+    /// Writes `<output>.json` next to the output, describing how it was rendered, for cataloging
+    /// batches of renders.
+    pub fn write_manifest<F>(
+        &self,
+        color: F,
+        format: OutputFormat,
+        started: SystemTime,
+        finished: SystemTime,
+    ) -> Result<()>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        let seconds = finished
+            .duration_since(started)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut manifest = json!({
+            "program": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "command": self.description(),
+            "output": self.filename.to_string_lossy(),
+            "format": format.name(),
+            "parameters": {
+                "order": self.order,
+                "size": self.image_size,
+                "function": self.function,
+                "frames": self.frames,
+                "proof": self.proof.map(|proof| proof.every.get()),
+                "framerate": self.framerate,
+                "loops": self.loops.map(NonZeroU32::get),
+                "gamut": option_name(self.color_space),
+                "crop": self.crop.map(|crop| crop.to_string()),
+                "supersample": self.supersample,
+                "output_size": self.output_size.map(|size| size.to_string()),
+                "scale_filter": self.output_size.map(|_| self.scale_filter.name()),
+                "deterministic": self.deterministic,
+            },
+            "encoder": self.encoder_settings(format),
+            "timing": {
+                "started": started.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                "seconds": seconds,
+            },
+            "frame_crc32": self.frame_checksums(color),
+        });
+        if self.deterministic {
+            manifest["content_crc32"] = self.content_checksum(format)?.into();
+        }
+
+        let path = self.manifest_path();
+        let manifest = serde_json::to_string_pretty(&manifest)?;
+        fs::write(&path, manifest + "\n")
+            .with_context(|| format!("Failed to write manifest {}", path.display()))
+    }
+
+    /// `<output>.json`, appended rather than replacing the extension so out.gif and out.webm
+    /// don't collide.
+    pub fn manifest_path(&self) -> PathBuf {
+        let mut path = self.filename.clone().into_os_string();
+        path.push(".json");
+        PathBuf::from(path)
+    }
+
+    pub fn encoder_settings(&self, format: OutputFormat) -> Value {
+        match format {
+            OutputFormat::Gif => {
+                #[allow(unused_mut)]
+                let mut settings = json!({
+                    "colors": self.colors,
+                    "dither": option_name(self.dither),
+                    "palette_cycle": self.palette_cycle,
+                });
+                #[cfg(feature = "gifski")]
+                if self.gifski {
+                    settings["gifski"] = json!({
+                        "quality": self.gif_quality,
+                        "fast": self.gif_fast,
+                    });
+                }
+                settings
+            }
+            OutputFormat::Webp => json!({
+                "lossless": self.lossless || self.webp_quality.is_none(),
+                "quality": self.webp_quality,
+                "method": self.webp_method,
+                "preset": option_name(self.webp_preset),
+                "mixed": self.webp_mixed,
+                "sharp_yuv": self.webp_sharp_yuv,
+            }),
+            OutputFormat::Webm | OutputFormat::Mp4 => json!({
+                "bitrate": self.bitrate.map(|bitrate| bitrate.to_string()),
+                "crf": self.crf,
+                "encode_preset": self.encode_preset.map(option_name),
+                "hwaccel": self.hwaccel.map(option_name),
+                "pix_fmt": option_name(self.pix_fmt),
+                "bit_depth": self.bit_depth.bits(),
+                "two_pass": self.two_pass,
+                "segments": self.segments,
+                "ffmpeg_args": self.ffmpeg_args,
+                "audio": self.audio,
+            }),
+            OutputFormat::Frames | OutputFormat::Zip | OutputFormat::Tar => json!({
+                "frame_format": option_name(self.frame_format),
+                "jpeg_quality": self.jpeg_quality,
+                "bit_depth": self.bit_depth.bits(),
+            }),
+            OutputFormat::Exr => json!({ "frame_format": "exr" }),
+        }
+    }
+
+    /// CRC-32s of each frame's 8-bit RGBA pixels, so renders can be compared without decoding
+    /// them. The frames are rendered again for this, since not every output keeps them.
+    pub fn frame_checksums<F>(&self, color: F) -> Vec<String>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        let colors = self.color_table::<u8, _>(color);
+        let progress = Progress::new("checksums", self.frames);
+        (0..self.frames)
+            .into_par_iter()
+            .inspect(|_| progress.update(1))
+            .map(|i| {
+                let frame = self.gen_frame(&colors, i);
+                let checksum = format!("{:08x}", crc32fast::hash(frame.as_raw()));
+                self.recycle_frame(frame);
+                checksum
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,192 @@
+use std::{fs, ops::RangeInclusive};
+
+use anyhow::{bail, Context, Result};
+use image::{ImageBuffer, Pixel, Rgba};
+use palette::LinSrgba;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    gif::Dither,
+    progress::Progress,
+    units::{Bitrate, ByteSize},
+    video::{BitDepth, Container},
+    Channel, OutputFormat, Params,
+};
+
+/// Frames for encoding again and again, kept in memory if they fit in --max-memory and rendered
+/// again each time if not.
+enum FrameCache<T, R> {
+    Kept(Vec<T>),
+    Rendered(R),
+}
+
+impl<T: Clone, R: Fn(usize) -> T> FrameCache<T, R> {
+    fn get(&self, i: usize) -> T {
+        match self {
+            Self::Kept(frames) => frames[i].clone(),
+            Self::Rendered(render) => render(i),
+        }
+    }
+}
+
+impl Params {
+    /// Encodes at the highest quality that still fits in --max-size. Frames are rendered once up
+    /// front, then encoded as many times as the search needs.
+    pub fn write_within_size<F>(
+        &self,
+        format: OutputFormat,
+        color: F,
+        max_size: ByteSize,
+    ) -> Result<()>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        if self.is_stdout() {
+            bail!("--max-size can't be used when writing to stdout");
+        }
+        let colors = self.color_table(&color);
+        let render_frame = |i: usize| self.gen_frame(&colors, i);
+
+        match format {
+            OutputFormat::Gif => {
+                #[cfg(feature = "gifski")]
+                if self.gifski {
+                    let frames = self.cache_frames(render_frame);
+                    return self.search_quality(max_size, 1..=100, |quality| {
+                        let params = Params {
+                            gif_quality: quality as u8,
+                            ..self.clone()
+                        };
+                        params.write_gif(&colors, |i| frames.get(i))
+                    });
+                }
+                // Only dithering works from rendered frames, the other modes use the colors
+                let frames = if self.dither != Dither::None && !self.palette_cycle {
+                    self.cache_frames(render_frame)
+                } else {
+                    FrameCache::Kept(Vec::new())
+                };
+                self.search_quality(max_size, 2..=256, |count| {
+                    let params = Params {
+                        colors: count as usize,
+                        ..self.clone()
+                    };
+                    params.write_gif(&colors, |i| frames.get(i))
+                })
+            }
+            OutputFormat::Webp => {
+                let frames = self.cache_frames(render_frame);
+                self.search_quality(max_size, 0..=100, |quality| {
+                    let params = Params {
+                        webp_quality: Some(quality as f32),
+                        lossless: false,
+                        ..self.clone()
+                    };
+                    params.write_webp(|i| frames.get(i))
+                })
+            }
+            OutputFormat::Webm | OutputFormat::Mp4 => {
+                let container = match format {
+                    OutputFormat::Webm => Container::Webm,
+                    _ => Container::Mp4,
+                };
+                match self.bit_depth {
+                    BitDepth::Eight => {
+                        let frames = self.cache_frames(render_frame);
+                        self.search_bitrate(max_size, container, |i| frames.get(i))
+                    }
+                    BitDepth::Ten | BitDepth::Sixteen => {
+                        let colors = self.color_table::<u16, _>(&color);
+                        let frames = self.cache_frames(|i| self.gen_image(&colors, i));
+                        self.search_bitrate(max_size, container, |i| frames.get(i))
+                    }
+                }
+            }
+            _ => bail!("--max-size only works for gif, webp, webm, and mp4 outputs"),
+        }
+    }
+
+    fn cache_frames<T, R>(&self, render: R) -> FrameCache<T, R>
+    where
+        T: Send,
+        R: Fn(usize) -> T + Sync + Send,
+    {
+        if self.frame_budget(self.frames) < self.frames {
+            return FrameCache::Rendered(render);
+        }
+        let progress = Progress::new("render", self.frames);
+        FrameCache::Kept(
+            (0..self.frames)
+                .into_par_iter()
+                .map(render)
+                .inspect(|_| progress.update(1))
+                .collect(),
+        )
+    }
+
+    /// Searches video bitrates in kbit/s, up to twice what would exactly fill the budget since
+    /// encoders tend to undershoot.
+    fn search_bitrate<F, C>(
+        &self,
+        max_size: ByteSize,
+        container: Container,
+        render_frame: F,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let filling_kbps = (max_size.0 as f64 * 8.0 / 1000.0 / self.video_duration()) as u32;
+        self.search_quality(max_size, 1..=filling_kbps.max(1) * 2, |kbps| {
+            let params = Params {
+                bitrate: Some(Bitrate(u64::from(kbps) * 1000)),
+                crf: None,
+                ..self.clone()
+            };
+            params.write_video(&render_frame, container)
+        })
+    }
+
+    /// Binary searches `range` for the highest setting where `encode` writes an output that fits
+    /// in `max_size`, assuming higher settings make bigger files. The output is left encoded with
+    /// that setting.
+    fn search_quality<E>(
+        &self,
+        max_size: ByteSize,
+        range: RangeInclusive<u32>,
+        mut encode: E,
+    ) -> Result<()>
+    where
+        E: FnMut(u32) -> Result<()>,
+    {
+        let (mut low, mut high) = range.into_inner();
+        let mut best = None;
+        let mut last = None;
+        while low <= high {
+            let setting = low + (high - low) / 2;
+            encode(setting)?;
+            last = Some(setting);
+            let size = fs::metadata(&self.filename)
+                .context("Failed to read output size")?
+                .len();
+            if size <= max_size.0 {
+                best = Some(setting);
+                low = setting + 1;
+            } else if setting == 0 {
+                break;
+            } else {
+                high = setting - 1;
+            }
+        }
+
+        match best {
+            Some(best) if last == Some(best) => Ok(()),
+            Some(best) => encode(best),
+            None => bail!(
+                "Couldn't fit the output in {} bytes, even at the lowest quality",
+                max_size.0
+            ),
+        }
+    }
+}
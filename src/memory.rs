@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+
+use crate::{video::BitDepth, Params};
+
+impl Params {
+    fn channel_bytes(&self) -> u64 {
+        match self.bit_depth {
+            BitDepth::Eight => 1,
+            BitDepth::Ten | BitDepth::Sixteen => 2,
+        }
+    }
+
+    /// Roughly how many bytes a rendered frame takes.
+    fn frame_bytes(&self) -> u64 {
+        let (width, height) = self.frame_dimensions();
+        width as u64 * height as u64 * 4 * self.channel_bytes()
+    }
+
+    /// Bytes held for the whole render, for the curve and the table of colors along all of it,
+    /// even when only a window of it is rendered.
+    fn fixed_bytes(&self) -> u64 {
+        self.curve.len() as u64 * 8 + self.num_pixels * 4 * self.channel_bytes()
+    }
+
+    /// Checks that --max-memory leaves room for at least one frame.
+    pub fn check_memory(&self) -> Result<()> {
+        let Some(max_memory) = self.max_memory else {
+            return Ok(());
+        };
+        if self.strip_rows.is_some() {
+            // Only a strip of a frame is held at a time, which is up to the user
+            return Ok(());
+        }
+        let needed = self.fixed_bytes() + self.frame_bytes();
+        if needed > max_memory.0 {
+            bail!(
+                "--max-memory is too small, order {} needs at least {needed} bytes",
+                self.order
+            );
+        }
+        Ok(())
+    }
+
+    /// Roughly the most memory the render holds at once, with as many frames in flight as the
+    /// thread pool keeps.
+    pub fn peak_memory(&self) -> u64 {
+        if self.strip_rows.is_some() {
+            return self.num_pixels * 4 * self.channel_bytes();
+        }
+        let frames = self.frame_budget(rayon::current_num_threads() * 2) as u64;
+        self.fixed_bytes() + self.frame_bytes() * frames
+    }
+
+    /// How many frames to hold at once, `wanted` unless --max-memory only has room for fewer.
+    pub fn frame_budget(&self, wanted: usize) -> usize {
+        let Some(max_memory) = self.max_memory else {
+            return wanted;
+        };
+        let frames = max_memory.0.saturating_sub(self.fixed_bytes()) / self.frame_bytes();
+        (frames as usize).clamp(1, wanted.max(1))
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{color::ColorSpace, Params};
+
+impl Params {
+    /// The command that renders this animation again, stored in outputs so they document
+    /// themselves.
+    pub fn description(&self) -> String {
+        let mut description = format!(
+            "{} {} --order {} --function {} --frames {} --framerate {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            self.order,
+            self.function,
+            self.animation_frames(),
+            self.framerate
+        );
+        if let Some(loops) = self.loops {
+            description.push_str(&format!(" --loops {loops}"));
+        }
+        if let Some(proof) = self.proof {
+            description.push_str(&format!(" --proof {}", proof.every));
+        }
+        if self.supersample > 1 {
+            description.push_str(&format!(" --supersample {}", self.supersample));
+        }
+        if let Some(crop) = self.crop {
+            description.push_str(&format!(" --crop {crop}"));
+        }
+        if let Some(output_size) = self.output_size {
+            description.push_str(&format!(
+                " --output-size {output_size} --scale-filter {}",
+                self.scale_filter.name()
+            ));
+        }
+        match self.color_space {
+            ColorSpace::Srgb => {}
+            ColorSpace::DisplayP3 => description.push_str(" --gamut p3"),
+            ColorSpace::Rec2020 => description.push_str(" --gamut rec2020"),
+        }
+        if let Some(overlay) = &self.overlay {
+            for arg in &overlay.args {
+                description.push(' ');
+                description.push_str(&shell_quote(arg));
+            }
+        }
+        if self.debug_overlay {
+            description.push_str(" --debug-overlay");
+        }
+        if self.deterministic {
+            description.push_str(" --deterministic");
+        }
+        description
+    }
+
+    /// A short title for players to show, since the description is too long for one.
+    pub fn title(&self) -> String {
+        format!("Hilbert curve, order {}, {}", self.order, self.function)
+    }
+
+    /// An XMP packet with the description and this program as the creator tool.
+    pub fn xmp(&self) -> String {
+        format!(
+            concat!(
+                r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">"#,
+                r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+                r#"<rdf:Description rdf:about="" "#,
+                r#"xmlns:dc="http://purl.org/dc/elements/1.1/" "#,
+                r#"xmlns:xmp="http://ns.adobe.com/xap/1.0/">"#,
+                "<xmp:CreatorTool>{} {}</xmp:CreatorTool>",
+                r#"<dc:description><rdf:Alt><rdf:li xml:lang="x-default">{}</rdf:li></rdf:Alt></dc:description>"#,
+                "</rdf:Description></rdf:RDF></x:xmpmeta>",
+            ),
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            // --text can put anything in it
+            self.description()
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        )
+    }
+}
+
+/// Quotes an argument for a POSIX shell, if it needs it.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = arg
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+".contains(c));
+    match plain && !arg.is_empty() {
+        true => arg.to_owned(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+/// Splits a command back into its arguments, undoing `shell_quote`.
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = None::<String>;
+    let mut quoted = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            '\\' if !quoted => arg.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
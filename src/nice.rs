@@ -0,0 +1,33 @@
+use std::{io, num::NonZeroUsize, thread};
+
+use anyhow::{Context, Result};
+
+/// How far --nice lowers the priority of the render, like the `nice` command does by default.
+#[cfg(unix)]
+const NICENESS: libc::c_int = 10;
+
+/// Lowers the priority of the calling thread, and so of every thread and FFMpeg process it starts
+/// afterwards, which inherit it.
+#[cfg(unix)]
+pub fn lower_priority() -> Result<()> {
+    // getpriority can legitimately return -1, which is fine here since it's only added to
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    let lowered = (current + NICENESS).min(19);
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, lowered) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to lower the render's priority");
+    }
+    Ok(())
+}
+
+/// Priorities can't be lowered portably elsewhere, so --nice only leaves a core free there.
+#[cfg(not(unix))]
+pub fn lower_priority() -> Result<()> {
+    Ok(())
+}
+
+/// Threads to render with under --nice: one for every core but one, so the rest of the machine
+/// always has a core to itself.
+pub fn background_threads() -> NonZeroUsize {
+    let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    NonZeroUsize::new(cores - 1).unwrap_or(NonZeroUsize::MIN)
+}
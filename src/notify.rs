@@ -0,0 +1,75 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Result};
+use log::warn;
+#[cfg(unix)]
+use {anyhow::Context, log::debug, std::process::Command};
+
+use crate::dry_run::format_duration;
+
+/// Shows a desktop notification that a render finished or failed, for --notify. Not being able to
+/// show one only warns, since the render is over either way.
+pub fn render_finished(outputs: &[PathBuf], elapsed: Duration, rendered: &Result<()>) {
+    let outputs = match outputs {
+        [] => "the animation".to_owned(),
+        _ => outputs
+            .iter()
+            .map(|output| output.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let elapsed = format_duration(elapsed);
+    let (summary, body) = match rendered {
+        Ok(()) => ("Render finished", format!("Wrote {outputs} in {elapsed}")),
+        Err(err) => (
+            "Render failed",
+            format!("{outputs} failed after {elapsed}: {err}"),
+        ),
+    };
+    if let Err(err) = show(summary, &body, rendered.is_err()) {
+        warn!("Failed to show a notification: {err:#}");
+    }
+}
+
+/// Through notify-send, which talks to whichever notification daemon the desktop runs.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn show(summary: &str, body: &str, failed: bool) -> Result<()> {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name", env!("CARGO_PKG_NAME")]);
+    if failed {
+        command.args(["--urgency", "critical"]);
+    }
+    command.arg(summary).arg(body);
+    run(command)
+}
+
+/// Through AppleScript, which needs no app bundle of its own to notify from.
+#[cfg(target_os = "macos")]
+fn show(summary: &str, body: &str, _failed: bool) -> Result<()> {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(summary)
+    ));
+    run(command)
+}
+
+#[cfg(not(unix))]
+fn show(_summary: &str, _body: &str, _failed: bool) -> Result<()> {
+    bail!("notifications are only supported on Linux, the BSDs, and macOS")
+}
+
+#[cfg(unix)]
+fn run(mut command: Command) -> Result<()> {
+    debug!("Running {command:?}");
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !status.success() {
+        bail!("{program} failed with {status}");
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::{
+    sync::{mpsc, Arc},
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::RgbaImage;
+use palette::LinSrgba;
+
+use crate::{
+    frames::{Archive, FrameFormat},
+    video::{BitDepth, Container},
+    OutputFormat, Params,
+};
+
+/// Frames kept waiting for each output before rendering waits on the slowest one.
+const OUTPUT_BUFFER: usize = 4;
+
+/// An encoder that takes the frames of the animation one at a time, in order.
+pub trait FrameSink {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+impl Params {
+    /// Checks the options that can't be used when rendering to several outputs at once.
+    pub fn check_outputs(&self, outputs: &[(Params, OutputFormat)]) -> Result<()> {
+        if self.bit_depth != BitDepth::Eight {
+            bail!("Multiple outputs can only be rendered at 8-bit");
+        }
+        if self.max_size.is_some() {
+            bail!("--max-size can't be used with multiple outputs");
+        }
+        if self.strip_rows.is_some() {
+            bail!("--strip-rows can't be used with multiple outputs");
+        }
+        if self.resume {
+            bail!("--resume can't be used with multiple outputs");
+        }
+        if self.shard.is_some() {
+            bail!("--shard can't be used with multiple outputs");
+        }
+        if outputs
+            .iter()
+            .filter(|(params, _)| params.is_stdout())
+            .count()
+            > 1
+        {
+            bail!("Only one output can be written to stdout");
+        }
+        for (_, format) in outputs {
+            let frame_output = matches!(
+                format,
+                OutputFormat::Frames | OutputFormat::Zip | OutputFormat::Tar
+            );
+            if frame_output && self.frame_format == FrameFormat::Exr {
+                bail!("exr frames can't be combined with other outputs");
+            }
+            if *format == OutputFormat::Exr {
+                bail!("exr outputs can't be combined with other outputs");
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the animation once and feeds every frame to each of `outputs`, each encoding on its
+    /// own thread.
+    pub fn write_outputs<F>(&self, outputs: &[(Params, OutputFormat)], color: F) -> Result<()>
+    where
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        let partials: Vec<_> = outputs
+            .iter()
+            .map(|(params, format)| params.partial(*format))
+            .collect();
+        let colors = self.color_table::<u8, _>(color);
+        let buffer = self.frame_budget(OUTPUT_BUFFER);
+        let written = thread::scope(|scope| {
+            let (senders, writers): (Vec<_>, Vec<_>) = outputs
+                .iter()
+                .zip(&partials)
+                .map(|((_, format), params)| {
+                    let (sender, receiver) = mpsc::sync_channel::<Arc<RgbaImage>>(buffer);
+                    let writer = scope.spawn(move || {
+                        let mut sink = params.frame_sink(*format)?;
+                        for frame in receiver {
+                            sink.add_frame(&frame)?;
+                        }
+                        sink.finish()
+                    });
+                    (sender, writer)
+                })
+                .unzip();
+
+            let rendered = self.stream_frames(
+                |i| self.gen_image(&colors, i),
+                |frame| {
+                    let frame = Arc::new(frame);
+                    senders.iter().try_for_each(|sender| {
+                        sender
+                            .send(frame.clone())
+                            .map_err(|_| anyhow!("An output stopped early"))
+                    })
+                },
+            );
+            drop(senders);
+
+            // An output's own error says more than rendering having to stop because of it
+            for (writer, (params, _)) in writers.into_iter().zip(outputs) {
+                writer
+                    .join()
+                    .unwrap()
+                    .with_context(|| format!("Failed to write {}", params.filename.display()))?;
+            }
+            rendered
+        });
+
+        // Outputs only stop early when rendering does, so they're all kept or all discarded
+        for ((params, _), partial) in outputs.iter().zip(&partials) {
+            match written {
+                Ok(()) => params.commit_partial(partial)?,
+                Err(_) => params.discard_partial(partial),
+            }
+        }
+        written
+    }
+
+    /// An encoder for `format` that works from shared 8-bit frames, where it can, for outputs
+    /// [`Self::check_outputs`] allows.
+    fn frame_sink(&self, format: OutputFormat) -> Result<Box<dyn FrameSink + '_>> {
+        Ok(match format {
+            OutputFormat::Gif => Box::new(self.gif_sink()?),
+            OutputFormat::Webp => Box::new(self.webp_sink()?),
+            OutputFormat::Webm => Box::new(self.video_sink(Container::Webm)?),
+            OutputFormat::Mp4 => Box::new(self.video_sink(Container::Mp4)?),
+            OutputFormat::Frames => Box::new(self.frame_dir_sink(self.frame_format)?),
+            OutputFormat::Zip => Box::new(self.archive_sink(self.frame_format, Archive::Zip)?),
+            OutputFormat::Tar => Box::new(self.archive_sink(self.frame_format, Archive::Tar)?),
+            OutputFormat::Exr => bail!("exr outputs can't be combined with other outputs"),
+        })
+    }
+}
@@ -0,0 +1,250 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result};
+use image::{ImageBuffer, Pixel, Primitive, Rgba, RgbaImage};
+use palette::Srgb;
+
+use crate::{color, font::Font, Options, OutputFormat, Params};
+
+/// Where on the frame text or a watermark is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Position {
+    fn name(self) -> &'static str {
+        match self {
+            Self::TopLeft => "top-left",
+            Self::Top => "top",
+            Self::TopRight => "top-right",
+            Self::Left => "left",
+            Self::Center => "center",
+            Self::Right => "right",
+            Self::BottomLeft => "bottom-left",
+            Self::Bottom => "bottom",
+            Self::BottomRight => "bottom-right",
+        }
+    }
+
+    /// Where the top left of an image `size` goes in a frame `frame`, `margin` in from its edges.
+    fn place(self, frame: (u32, u32), size: (u32, u32), margin: u32) -> (i64, i64) {
+        let along = |start: bool, end: bool, frame: u32, size: u32| match (start, end) {
+            (true, _) => margin as i64,
+            (_, true) => frame as i64 - size as i64 - margin as i64,
+            _ => (frame as i64 - size as i64) / 2,
+        };
+        let left = matches!(self, Self::TopLeft | Self::Left | Self::BottomLeft);
+        let right = matches!(self, Self::TopRight | Self::Right | Self::BottomRight);
+        let top = matches!(self, Self::TopLeft | Self::Top | Self::TopRight);
+        let bottom = matches!(self, Self::BottomLeft | Self::Bottom | Self::BottomRight);
+        (
+            along(left, right, frame.0, size.0),
+            along(top, bottom, frame.1, size.1),
+        )
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Self::TopLeft),
+            "top" => Ok(Self::Top),
+            "top-right" => Ok(Self::TopRight),
+            "left" => Ok(Self::Left),
+            "center" => Ok(Self::Center),
+            "right" => Ok(Self::Right),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom" => Ok(Self::Bottom),
+            "bottom-right" => Ok(Self::BottomRight),
+            _ => Err(format!(
+                "unknown position '{s}', expected top-left, top, top-right, left, center, right, \
+                 bottom-left, bottom, or bottom-right"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextColor([u8; 4]);
+
+impl TextColor {
+    pub const WHITE: Self = Self([0xff; 4]);
+}
+
+impl FromStr for TextColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        color::parse_hex(s).map(Self)
+    }
+}
+
+impl fmt::Display for TextColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        write!(f, "{r:02x}{g:02x}{b:02x}{a:02x}")
+    }
+}
+
+/// An image drawn over the frames, in sRGB with straight alpha.
+struct Layer {
+    image: RgbaImage,
+    x: i64,
+    y: i64,
+}
+
+/// Text and a watermark drawn onto every frame once it's rendered and resized, so they're in the
+/// output without another pass through an editor or FFMpeg.
+pub struct Overlay {
+    layers: Vec<Layer>,
+    /// The options that draw it, to record with the others in outputs.
+    pub args: Vec<String>,
+}
+
+impl fmt::Debug for Overlay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Overlay").field("args", &self.args).finish()
+    }
+}
+
+impl Overlay {
+    /// Draws the text and loads the watermark that `options` ask for onto frames `frame` in size,
+    /// or nothing if they ask for neither.
+    pub fn new(options: &Options, frame: (u32, u32)) -> Result<Option<Self>> {
+        let mut layers = Vec::new();
+        let mut args = Vec::new();
+        // Clear of the edges, but not so far that it looks adrift
+        let margin = frame.0.min(frame.1) / 32;
+
+        // Options are validated to have a font whenever there's text
+        if let (Some(text), Some(font)) = (&options.text, &options.font) {
+            let size = options
+                .text_size
+                .map_or((frame.1 / 16).max(8), |size| size.get());
+            let (width, height, coverage) = Font::open(font)?
+                .render(text, size as f32)
+                .with_context(|| format!("Failed to draw '{text}'"))?;
+            let [r, g, b, a] = options.text_color.0;
+            let image = RgbaImage::from_fn(width, height, |x, y| {
+                let covered = coverage[(y * width + x) as usize];
+                Rgba([r, g, b, (a as f32 * covered).round() as u8])
+            });
+            let (x, y) = options.text_pos.place(frame, (width, height), margin);
+            layers.push(Layer { image, x, y });
+
+            args.extend(["--text".to_owned(), text.clone()]);
+            args.extend(["--font".to_owned(), font.display().to_string()]);
+            if let Some(size) = options.text_size {
+                args.extend(["--text-size".to_owned(), size.to_string()]);
+            }
+            args.extend([
+                "--text-pos".to_owned(),
+                options.text_pos.name().to_owned(),
+                "--text-color".to_owned(),
+                options.text_color.to_string(),
+            ]);
+        }
+
+        if let Some(watermark) = &options.watermark {
+            let mut image = image::open(watermark)
+                .with_context(|| format!("Failed to read watermark {}", watermark.display()))?
+                .into_rgba8();
+            for pixel in image.pixels_mut() {
+                pixel[3] = (pixel[3] as f32 * options.watermark_opacity).round() as u8;
+            }
+            let (x, y) = options
+                .watermark_pos
+                .place(frame, image.dimensions(), margin);
+            layers.push(Layer { image, x, y });
+
+            args.extend([
+                "--watermark".to_owned(),
+                watermark.display().to_string(),
+                "--watermark-pos".to_owned(),
+                options.watermark_pos.name().to_owned(),
+                "--watermark-opacity".to_owned(),
+                options.watermark_opacity.to_string(),
+            ]);
+        }
+
+        Ok((!layers.is_empty()).then_some(Self { layers, args }))
+    }
+
+    /// Blends the layers over a frame.
+    pub fn draw<C>(&self, frame: &mut ImageBuffer<Rgba<C>, Vec<C>>)
+    where
+        C: Primitive + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        for layer in &self.layers {
+            blend(frame, &layer.image, layer.x, layer.y);
+        }
+    }
+}
+
+/// Blends an sRGB image with straight alpha over a frame, with its top left at `x`, `y`. Float
+/// frames hold linear colors, so the image's colors are converted for them.
+pub fn blend<C>(frame: &mut ImageBuffer<Rgba<C>, Vec<C>>, image: &RgbaImage, x: i64, y: i64)
+where
+    C: Primitive + 'static,
+    Rgba<C>: Pixel<Subpixel = C>,
+{
+    let max = C::DEFAULT_MAX_VALUE.to_f32().unwrap_or(1.0);
+    // Only float frames go up to 1
+    let linear = max == 1.0;
+    let (width, height) = frame.dimensions();
+    for (image_x, image_y, source) in image.enumerate_pixels() {
+        let (frame_x, frame_y) = (x + image_x as i64, y + image_y as i64);
+        if source[3] == 0
+            || !(0..width as i64).contains(&frame_x)
+            || !(0..height as i64).contains(&frame_y)
+        {
+            continue;
+        }
+        let [r, g, b, a] = source.0.map(|channel| channel as f32 / 255.0);
+        let color = match linear {
+            false => [r, g, b],
+            true => {
+                let linear = Srgb::new(r, g, b).into_linear::<f32>();
+                [linear.red, linear.green, linear.blue]
+            }
+        };
+
+        let pixel = frame.get_pixel_mut(frame_x as u32, frame_y as u32);
+        let below = pixel
+            .0
+            .map(|channel| channel.to_f32().unwrap_or_default() / max);
+        // Over the frame, which can be partly transparent itself
+        let below_weight = below[3] * (1.0 - a);
+        let alpha = a + below_weight;
+        let mut blended = [0.0, 0.0, 0.0, alpha];
+        for ((blended, color), below) in blended.iter_mut().zip(color).zip(below) {
+            *blended = (color * a + below * below_weight) / alpha;
+        }
+        pixel.0 = blended.map(|value| color::to_channel((value * max) as f64));
+    }
+}
+
+impl Params {
+    /// Checks that every output draws its frames from the pixels overlays are drawn on.
+    pub fn check_overlay(&self, format: OutputFormat) -> Result<()> {
+        let drawn = match (&self.overlay, self.debug_overlay) {
+            (None, false) => return Ok(()),
+            (Some(_), false) => "--text or --watermark",
+            (None, true) => "--debug-overlay",
+            (Some(_), true) => "--text, --watermark, or --debug-overlay",
+        };
+        self.draws_from_rendered_frames(format, drawn)
+    }
+}
@@ -0,0 +1,100 @@
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{interrupt, OutputFormat, Params};
+
+impl Params {
+    /// Checks that the output, and its manifest, won't replace anything unless --force was given.
+    /// A directory where a file goes is never replaced, even with --force, since there's no
+    /// telling what's in it.
+    pub fn check_overwrite(&self, format: OutputFormat) -> Result<()> {
+        let directory_output = matches!(format, OutputFormat::Frames | OutputFormat::Exr);
+        if !directory_output && !self.is_stdout() && self.filename.is_dir() {
+            bail!(
+                "{} is a directory, so it won't be replaced with a {} file",
+                self.filename.display(),
+                format.name()
+            );
+        }
+        if self.force || self.is_stdout() {
+            return Ok(());
+        }
+        let manifest = self.manifest.then(|| self.manifest_path());
+        // Resuming writes into the frame directory that's already there
+        let resumed = self.resume && directory_output;
+        let output = (!resumed).then(|| self.output_path(format));
+        for path in [output, manifest].into_iter().flatten() {
+            if path.exists() {
+                bail!(
+                    "{} already exists, pass --force to overwrite it",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the output ends up, which for frames is a directory.
+    pub fn output_path(&self, format: OutputFormat) -> PathBuf {
+        match format {
+            OutputFormat::Frames | OutputFormat::Exr => self.frame_dir(),
+            _ => self.filename.clone(),
+        }
+    }
+
+    /// Writes the output with `write` into a partial file next to it, which only replaces the
+    /// output once it's complete. A failed or interrupted render then never leaves a truncated
+    /// output behind for viewers to choke on.
+    pub fn write_atomically<W>(&self, format: OutputFormat, write: W) -> Result<()>
+    where
+        W: FnOnce(&Params) -> Result<()>,
+    {
+        let partial = self.partial(format);
+        let _cleanup = (partial.filename != self.filename)
+            .then(|| interrupt::remove_on_interrupt(&partial.filename));
+        match write(&partial) {
+            Ok(()) => self.commit_partial(&partial),
+            Err(error) => {
+                self.discard_partial(&partial);
+                Err(error)
+            }
+        }
+    }
+
+    /// The same parameters, writing to a hidden file next to the output instead. Stdout and
+    /// directories of frames are written in place.
+    pub fn partial(&self, format: OutputFormat) -> Params {
+        if self.is_stdout() || matches!(format, OutputFormat::Frames | OutputFormat::Exr) {
+            return self.clone();
+        }
+        // The extension stays last, since FFMpeg picks the container from it
+        let mut name = OsString::from(".");
+        name.push(self.filename.file_stem().unwrap_or_default());
+        name.push(".partial");
+        if let Some(extension) = self.filename.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        Params {
+            filename: self.filename.with_file_name(name),
+            ..self.clone()
+        }
+    }
+
+    /// Moves a finished partial output over the output.
+    pub fn commit_partial(&self, partial: &Params) -> Result<()> {
+        if partial.filename == self.filename {
+            return Ok(());
+        }
+        fs::rename(&partial.filename, &self.filename)
+            .with_context(|| format!("Failed to move output to {}", self.filename.display()))
+    }
+
+    /// Removes what was written of a failed partial output.
+    pub fn discard_partial(&self, partial: &Params) {
+        if partial.filename != self.filename {
+            let _ = fs::remove_file(&partial.filename);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use crate::{units::ByteSize, Options, OutputFormat, Params};
+
+/// The name --preset takes for each platform.
+pub const PLATFORMS: [&str; 4] = ["discord", "discord-emoji", "telegram-sticker", "twitter"];
+
+/// A platform with known limits on the animations it takes, for --preset.
+#[derive(Debug, Clone, Copy)]
+pub enum Platform {
+    Discord,
+    DiscordEmoji,
+    TelegramSticker,
+    Twitter,
+}
+
+impl Platform {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Discord => "discord",
+            Self::DiscordEmoji => "discord-emoji",
+            Self::TelegramSticker => "telegram-sticker",
+            Self::Twitter => "twitter",
+        }
+    }
+
+    pub fn format(self) -> OutputFormat {
+        match self {
+            // Embeds play inline, where gifs of the same length are far bigger
+            Self::Discord => OutputFormat::Mp4,
+            Self::DiscordEmoji | Self::Twitter => OutputFormat::Gif,
+            Self::TelegramSticker => OutputFormat::Webm,
+        }
+    }
+
+    /// The order to render at, given the one asked for.
+    fn order(self, order: u8) -> u8 {
+        match self {
+            Self::Discord | Self::Twitter => order.min(10),
+            // Shown at 128x128 at most
+            Self::DiscordEmoji => order.min(7),
+            // Video stickers need one side to be exactly 512
+            Self::TelegramSticker => 9,
+        }
+    }
+
+    /// The framerate it plays `framerate` at, which is no more than it allows.
+    pub fn framerate(self, framerate: u32) -> u32 {
+        framerate.min(self.max_framerate())
+    }
+
+    fn max_framerate(self) -> u32 {
+        match self {
+            Self::Discord => 60,
+            // Browsers slow down gif frames shorter than 2 hundredths of a second
+            Self::DiscordEmoji | Self::Twitter => 50,
+            Self::TelegramSticker => 30,
+        }
+    }
+
+    fn max_size(self) -> ByteSize {
+        match self {
+            Self::Discord => ByteSize(10_000_000),
+            Self::DiscordEmoji => ByteSize(256 << 10),
+            Self::TelegramSticker => ByteSize(256 << 10),
+            Self::Twitter => ByteSize(15_000_000),
+        }
+    }
+
+    fn max_seconds(self) -> Option<f64> {
+        match self {
+            Self::TelegramSticker => Some(3.0),
+            _ => None,
+        }
+    }
+
+    /// Brings the options within the platform's limits, keeping any that are already within them.
+    pub fn apply(self, options: &mut Options) {
+        options.order = self.order(options.order);
+        options.framerate = self.framerate(options.framerate);
+        let max_size = self.max_size();
+        options.max_size = Some(options.max_size.map_or(max_size, |size| size.min(max_size)));
+        if let Self::TelegramSticker = self {
+            // Stickers loop by themselves, so repeating the frames would only break the limit
+            options.loops = None;
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "discord" => Ok(Self::Discord),
+            "discord-emoji" => Ok(Self::DiscordEmoji),
+            "telegram-sticker" => Ok(Self::TelegramSticker),
+            "twitter" => Ok(Self::Twitter),
+            _ => Err(format!(
+                "unknown preset '{s}', expected discord, discord-emoji, telegram-sticker, or twitter"
+            )),
+        }
+    }
+}
+
+impl Params {
+    /// Checks what --preset can't fix by itself.
+    pub fn check_preset(&self, format: OutputFormat) -> Result<()> {
+        let Some(preset) = self.preset else {
+            return Ok(());
+        };
+        if format != preset.format() {
+            bail!(
+                "The {} preset writes {} outputs, not {}",
+                preset.name(),
+                preset.format().name(),
+                format.name()
+            );
+        }
+        if let Some(max_seconds) = preset.max_seconds() {
+            let seconds = self.frames as f64 / self.framerate as f64;
+            if seconds > max_seconds {
+                bail!(
+                    "The {} preset allows at most {max_seconds} seconds, but {} frames at {} fps \
+                     take {seconds:.2}s",
+                    preset.name(),
+                    self.frames,
+                    self.framerate
+                );
+            }
+        }
+        Ok(())
+    }
+}
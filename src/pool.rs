@@ -0,0 +1,33 @@
+use std::sync::Mutex;
+
+use image::RgbaImage;
+
+use crate::Params;
+
+/// Buffers of 8-bit frames that encoders are done with, for rendering later frames into instead
+/// of allocating new ones.
+#[derive(Debug, Default)]
+pub struct FramePool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Params {
+    /// Renders 8-bit frame `i` into a recycled buffer, if there is one.
+    pub fn gen_frame(&self, colors: &[[u8; 4]], i: usize) -> RgbaImage {
+        let buffer = self.frame_pool.buffers.lock().unwrap().pop();
+        self.gen_image_into(colors, i, buffer)
+    }
+
+    /// Hands a frame back once it's encoded.
+    pub fn recycle_frame(&self, frame: RgbaImage) {
+        self.recycle_samples(frame.into_raw());
+    }
+
+    /// Hands a frame's samples back. No more are kept than can be in flight at once.
+    pub fn recycle_samples(&self, samples: Vec<u8>) {
+        let mut buffers = self.frame_pool.buffers.lock().unwrap();
+        if buffers.len() < self.frame_budget(rayon::current_num_threads() * 2) {
+            buffers.push(samples);
+        }
+    }
+}
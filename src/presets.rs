@@ -0,0 +1,166 @@
+use std::{env, ffi::OsString, fs, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use bpaf::*;
+use log::info;
+
+use crate::{config, exit, platform::PLATFORMS, suggest, Options, Platform};
+
+/// A preset for --preset: one of the platforms, or options saved with `preset save`.
+#[derive(Debug, Clone)]
+pub enum Preset {
+    Platform(Platform),
+    Saved(String),
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(platform) => Ok(Self::Platform(platform)),
+            Err(_) if valid_name(s) => Ok(Self::Saved(s.to_owned())),
+            Err(err) => Err(format!("{err}, or the name of a saved preset")),
+        }
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Options {
+    /// The platform given with --preset, if it named one rather than a saved preset.
+    pub fn platform(&self) -> Option<Platform> {
+        match self.preset {
+            Some(Preset::Platform(platform)) => Some(platform),
+            _ => None,
+        }
+    }
+}
+
+/// Where saved presets are kept, in the user's config directory.
+fn preset_dir() -> Result<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .context("Can't find the config directory to keep presets in")?;
+    Ok(config_dir.join(env!("CARGO_PKG_NAME")).join("presets"))
+}
+
+/// The names of every saved preset, in order.
+pub fn saved_names() -> Vec<String> {
+    let mut saved: Vec<_> = preset_dir()
+        .and_then(|dir| Ok(fs::read_dir(dir)?))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_stem()?.to_str()?.to_owned();
+            (path.extension()? == "toml").then_some(name)
+        })
+        .collect();
+    saved.sort();
+    saved
+}
+
+/// The file a saved preset is kept in, which must already exist.
+pub fn saved_preset_path(name: &str) -> Result<PathBuf> {
+    let dir = preset_dir()?;
+    let path = dir.join(format!("{name}.toml"));
+    if !path.exists() {
+        let saved = saved_names();
+        let saved = if saved.is_empty() {
+            "none are saved yet".to_owned()
+        } else {
+            format!("saved presets are {}", saved.join(", "))
+        };
+        let suggestion = suggest::did_you_mean(
+            name,
+            PLATFORMS
+                .into_iter()
+                .chain(saved_names().iter().map(String::as_str)),
+        );
+        bail!("unknown preset '{name}'{suggestion}, expected discord, discord-emoji, telegram-sticker, twitter, or one saved with `preset save` ({saved})");
+    }
+    Ok(path)
+}
+
+/// Manages saved presets
+#[derive(Debug, Clone, Bpaf)]
+pub enum PresetCommand {
+    /// Saves options under a name, to render with again by passing --preset NAME
+    #[bpaf(command)]
+    Save {
+        /// Replace the preset if one with this name is already saved
+        force: bool,
+        /// Name to save the options under
+        #[bpaf(positional("NAME"))]
+        name: String,
+        /// Options to save, like --order 11 --gamut p3
+        #[bpaf(any("OPTION", not_help), many)]
+        options: Vec<OsString>,
+    },
+}
+
+/// Leaves --help for the command itself, rather than taking it as an option to save.
+fn not_help(arg: OsString) -> Option<OsString> {
+    (arg != "--help" && arg != "-h").then_some(arg)
+}
+
+impl PresetCommand {
+    pub fn run(self) -> Result<()> {
+        match self {
+            Self::Save {
+                force,
+                name,
+                options,
+            } => save(&name, &options, force),
+        }
+    }
+}
+
+fn save(name: &str, args: &[OsString], force: bool) -> Result<()> {
+    let settings = check_save(name, args, force).map_err(exit::usage)?;
+    let dir = preset_dir()?;
+    let path = dir.join(format!("{name}.toml"));
+    fs::create_dir_all(&dir).context("Failed to create preset directory")?;
+    let contents = format!(
+        "# Saved with `{} preset save {name}`\n{}",
+        env!("CARGO_PKG_NAME"),
+        config::to_toml(&settings)
+    );
+    fs::write(&path, contents)
+        .with_context(|| format!("Failed to write preset {}", path.display()))?;
+    info!("Saved preset {name} to {}", path.display());
+    Ok(())
+}
+
+/// Checks the name and options to save, returning the settings to write.
+fn check_save(name: &str, args: &[OsString], force: bool) -> Result<Vec<(String, config::Value)>> {
+    if !valid_name(name) || name.parse::<Platform>().is_ok() {
+        bail!(
+            "invalid preset name '{name}', expected letters, numbers, - and _, and not a platform"
+        );
+    }
+    let options = config::parse_options(args)?;
+    if !options.outputs.is_empty() {
+        bail!("Presets can't include outputs, give them when rendering");
+    }
+    if options.preset.is_some() || options.config.is_some() {
+        bail!("Presets can't include --preset or --config");
+    }
+    let settings = config::settings_from_args(args)?;
+    if settings.is_empty() {
+        bail!("No options to save");
+    }
+    if preset_dir()?.join(format!("{name}.toml")).exists() && !force {
+        bail!("A preset named {name} is already saved, pass --force to replace it");
+    }
+    Ok(settings)
+}
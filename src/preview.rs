@@ -0,0 +1,113 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bpaf::*;
+
+use crate::{
+    color_function, config, exit, frames::FrameFormat, logging, options, progress,
+    resume::write_frame, start_thread_pool, ColorFunction, Options, Params,
+};
+
+/// A point in the animation for --at.
+#[derive(Debug, Clone, Copy)]
+enum Position {
+    /// How far through the animation, from 0 to 1.
+    Fraction(f64),
+    Frame(usize),
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid position '{s}', expected a fraction like 0.5 or a frame number like 12"
+            )
+        };
+        if s.contains('.') {
+            let fraction: f64 = s.parse().map_err(|_| invalid())?;
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(format!(
+                    "invalid position '{s}', fractions must be between 0 and 1"
+                ));
+            }
+            Ok(Self::Fraction(fraction))
+        } else {
+            s.parse().map(Self::Frame).map_err(|_| invalid())
+        }
+    }
+}
+
+/// Renders a single frame, to check the colors before rendering them all
+#[derive(Debug, Clone, Bpaf)]
+pub struct PreviewOptions {
+    /// Frame to render, as a fraction of the way through the animation like 0.5, or a frame
+    /// number like 12
+    #[bpaf(long, argument("POSITION"), fallback(Position::Fraction(0.0)))]
+    at: Position,
+    #[bpaf(external(options))]
+    options: Options,
+}
+
+impl PreviewOptions {
+    pub fn run(self) -> Result<()> {
+        let mut options = config::apply(self.options, config::subcommand_render_args(&["--at"]))
+            .map_err(exit::usage)?;
+        logging::set_verbosity(options.quiet, options.verbose);
+        progress::set_style(
+            options.progress,
+            options.progress_stages.clone(),
+            options.quiet,
+        );
+        match options.outputs.len() {
+            0 => options.outputs.push(PathBuf::from("preview.png")),
+            1 => {}
+            _ => return Err(exit::usage(anyhow!("preview writes a single image"))),
+        }
+        let threads = start_thread_pool(options.threads, options.nice)?;
+        let options = Options { threads, ..options };
+        let (function, params, format, frame) = plan(options, self.at).map_err(exit::usage)?;
+        let image = params.encode_single_frame(function, frame, format)?;
+        write_frame(params.filename.clone(), &image)
+            .with_context(|| format!("Failed to write {}", params.filename.display()))
+    }
+}
+
+/// Works out which frame to write and how, checking the options before it's rendered.
+fn plan(options: Options, at: Position) -> Result<(ColorFunction, Params, FrameFormat, usize)> {
+    let function = color_function(&options.function)?;
+    let params = Params::new(options)?;
+    if params.frames == 0 {
+        bail!("There are no frames to preview");
+    }
+    let frame = match at {
+        Position::Fraction(fraction) => {
+            (fraction * params.frames as f64).round() as usize % params.frames
+        }
+        Position::Frame(frame) if frame < params.frames => frame,
+        Position::Frame(frame) => bail!(
+            "Frame {frame} is past the end of the {} frames",
+            params.frames
+        ),
+    };
+    // A single image, in the format of its extension
+    let format: FrameFormat = params
+        .filename
+        .extension()
+        .and_then(|ext| ext.to_str()?.parse().ok())
+        .with_context(|| {
+            format!(
+                "Can't tell the image format from '{}', expected a png, jpeg, tiff, bmp, webp, or \
+                 exr file",
+                params.filename.display()
+            )
+        })?;
+    if params.filename.exists() && !params.force {
+        bail!(
+            "{} already exists, pass --force to overwrite it",
+            params.filename.display()
+        );
+    }
+    Ok((function, params, format, frame))
+}
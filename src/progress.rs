@@ -0,0 +1,598 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use kdam::{Bar, BarExt};
+use log::debug;
+use serde_json::{json, Map, Value};
+
+use crate::{interrupt, OutputFormat, Params};
+
+/// Every stage that reports progress, for --progress-stages.
+const STAGES: &[&str] = &[
+    "render",
+    "write",
+    "palettes",
+    "encode",
+    "first-pass",
+    "second-pass",
+    "checksums",
+];
+
+/// How often the overall bar is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the progress of a render that can be resumed is saved.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How progress is shown, for --progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressStyle {
+    /// A bar for each stage.
+    Bar,
+    /// A single bar over every stage of the render.
+    Overall,
+    /// A JSON object on a line of stderr for every step, for programs wrapping this one.
+    Json,
+    None,
+}
+
+impl FromStr for ProgressStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(Self::Bar),
+            "overall" => Ok(Self::Overall),
+            "json" => Ok(Self::Json),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown progress style '{s}', expected bar, overall, json, or none"
+            )),
+        }
+    }
+}
+
+/// The stages to show progress for, for --progress-stages.
+#[derive(Debug, Clone)]
+pub struct Stages(Vec<&'static str>);
+
+impl FromStr for Stages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|stage| {
+                let stage = stage.trim();
+                STAGES
+                    .iter()
+                    .find(|known| **known == stage)
+                    .copied()
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown stage '{stage}', expected render, write, palettes, encode, \
+                             first-pass, second-pass, or checksums"
+                        )
+                    })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// How progress is shown, set once the options are read.
+struct Settings {
+    style: ProgressStyle,
+    stages: Option<Stages>,
+}
+
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+    style: ProgressStyle::None,
+    stages: None,
+});
+
+/// The bar drawn with --progress overall, for the render in progress.
+static OVERALL: Mutex<Option<Overall>> = Mutex::new(None);
+
+/// How far the render in progress has got, when it's to a directory that can be resumed.
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Sets how progress is shown from --progress and --progress-stages. Without --progress, bars are
+/// drawn when stderr is a terminal and --quiet wasn't given, and left out of logs.
+pub fn set_style(style: Option<ProgressStyle>, stages: Option<Stages>, quiet: bool) {
+    let style = style.unwrap_or(match !quiet && io::stderr().is_terminal() {
+        true => ProgressStyle::Bar,
+        false => ProgressStyle::None,
+    });
+    *SETTINGS.lock().unwrap() = Settings { style, stages };
+}
+
+/// How progress is shown for `stage`, which is none if it wasn't picked with --progress-stages.
+fn style(stage: &str) -> ProgressStyle {
+    let settings = SETTINGS.lock().unwrap();
+    match &settings.stages {
+        Some(Stages(stages)) if !stages.contains(&stage) => ProgressStyle::None,
+        _ => settings.style,
+    }
+}
+
+impl Params {
+    /// How many stages rendering to `format` goes through, for the overall bar to divide between
+    /// them. Renders that go through more, like the encodes --max-size tries, make room for them
+    /// as they start.
+    pub fn progress_stages(&self, format: OutputFormat) -> usize {
+        #[cfg(feature = "gifski")]
+        let palette_cycle = self.palette_cycle && !self.gifski;
+        #[cfg(not(feature = "gifski"))]
+        let palette_cycle = self.palette_cycle;
+        let stages = match format {
+            OutputFormat::Gif if palette_cycle => 2,
+            OutputFormat::Webm | OutputFormat::Mp4 if self.two_pass => 2,
+            _ => 1,
+        };
+        stages + usize::from(self.manifest)
+    }
+}
+
+/// Starts the overall bar for a render going through `stages` stages, with --progress overall.
+/// It's finished when the returned guard is dropped.
+pub fn start_render(stages: usize) -> RenderProgress {
+    if SETTINGS.lock().unwrap().style == ProgressStyle::Overall {
+        *OVERALL.lock().unwrap() = Some(Overall {
+            expected: stages,
+            stages: Vec::new(),
+            started: Instant::now(),
+            drawn: None,
+        });
+    }
+    RenderProgress
+}
+
+/// Ends the overall bar for a render once it's finished or failed, and saves how far a render
+/// that can be resumed got.
+pub struct RenderProgress;
+
+impl Drop for RenderProgress {
+    fn drop(&mut self) {
+        if let Some(mut overall) = OVERALL.lock().unwrap().take() {
+            overall.draw();
+            eprintln!();
+        }
+        if let Some(mut state) = STATE.lock().unwrap().take() {
+            state.save();
+        }
+    }
+}
+
+/// Starts recording the progress of a render to a directory that can be resumed in `path`,
+/// carrying on from what earlier runs recorded there if it's being `resumed`.
+pub fn record_state(path: PathBuf, resumed: bool) {
+    let earlier = match resumed {
+        true => read_state(&path),
+        false => BTreeMap::new(),
+    };
+    *STATE.lock().unwrap() = Some(State {
+        path,
+        earlier,
+        stages: Vec::new(),
+        saved: Instant::now(),
+    });
+}
+
+/// Stops recording once everything in the directory is written, removing the record.
+pub fn finish_state() {
+    if let Some(state) = STATE.lock().unwrap().take() {
+        // Only there for showing progress, so one that's left behind does no harm
+        let _ = fs::remove_file(state.path);
+    }
+}
+
+/// What earlier runs recorded in `path`. A record that's missing or can't be read only means the
+/// resumed render's rate isn't known until it's done some of its own work.
+fn read_state(path: &Path) -> BTreeMap<String, Record> {
+    let Some(Value::Object(stages)) = fs::read(path)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+    else {
+        return BTreeMap::new();
+    };
+    stages
+        .into_iter()
+        .filter_map(|(stage, record)| {
+            let record = Record {
+                done: record["done"].as_u64()? as usize,
+                seconds: record["seconds"].as_f64()?,
+            };
+            Some((stage, record))
+        })
+        .collect()
+}
+
+/// Steps of a stage done, and the seconds spent on them.
+#[derive(Debug, Clone, Copy, Default)]
+struct Record {
+    done: usize,
+    seconds: f64,
+}
+
+impl Record {
+    /// Steps a second over these and `more` done in the `elapsed` seconds since.
+    fn rate(self, more: usize, elapsed: f64) -> f64 {
+        (self.done + more) as f64 / (self.seconds + elapsed).max(1e-3)
+    }
+}
+
+/// The progress of each stage of a render to a directory that can be resumed, kept in it so a run
+/// resuming the render knows how fast it went.
+struct State {
+    path: PathBuf,
+    /// What earlier runs of the render recorded.
+    earlier: BTreeMap<String, Record>,
+    /// Each stage this run has started, with how many steps it's done and when it started.
+    stages: Vec<(&'static str, usize, Instant)>,
+    saved: Instant,
+}
+
+impl State {
+    /// Starts counting the steps of `stage` if it hasn't started already, returning what earlier
+    /// runs recorded for it.
+    fn start(&mut self, stage: &'static str) -> Record {
+        if !self.stages.iter().any(|(name, ..)| *name == stage) {
+            self.stages.push((stage, 0, Instant::now()));
+        }
+        self.earlier.get(stage).copied().unwrap_or_default()
+    }
+
+    fn update(&mut self, stage: &'static str, steps: usize) {
+        if let Some((_, done, _)) = self.stages.iter_mut().find(|(name, ..)| *name == stage) {
+            *done += steps;
+        }
+        if self.saved.elapsed() >= SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        self.saved = Instant::now();
+        let mut stages = self.earlier.clone();
+        for &(stage, done, started) in &self.stages {
+            let record = stages.entry(stage.to_owned()).or_default();
+            record.done += done;
+            record.seconds += started.elapsed().as_secs_f64();
+        }
+        let stages: Map<String, Value> = stages
+            .into_iter()
+            .map(|(stage, record)| {
+                let seconds = (record.seconds * 10.0).round() / 10.0;
+                (stage, json!({ "done": record.done, "seconds": seconds }))
+            })
+            .collect();
+        let json = Value::Object(stages).to_string() + "\n";
+        // Only there for showing progress, so failing to save it isn't worth stopping the render
+        if let Err(err) = fs::write(&self.path, json) {
+            debug!("Failed to save progress to {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Counts `steps` of a stage an earlier run of a resumed render already did, like the frames of a
+/// segment that's already encoded, towards the overall bar.
+pub fn already_done(stage: &'static str, steps: usize) {
+    if let Some(overall) = &mut *OVERALL.lock().unwrap() {
+        let stage = overall.stage(stage);
+        stage.total += steps;
+        stage.done += steps;
+        stage.initial += steps;
+    }
+}
+
+/// Draws a bar over the current line of stderr, for those not drawn by kdam.
+fn draw_bar(label: &str, fraction: f64, details: &str) {
+    let filled = ((fraction * 20.0) as usize).min(20);
+    eprint!(
+        "\r\x1b[K{label}: {:3.0}%|{}{}| {details}",
+        fraction * 100.0,
+        "█".repeat(filled),
+        " ".repeat(20 - filled),
+    );
+    let _ = io::stderr().flush();
+}
+
+/// How long until the rest of the work is done at `rate` steps a second, or ? if nothing's been
+/// done to tell.
+fn format_eta(remaining: f64, rate: f64) -> String {
+    match rate > 0.0 {
+        true => format!("{:.0}s", remaining / rate),
+        false => "?".to_owned(),
+    }
+}
+
+/// One bar over every stage of a render, each taking an even share of it.
+struct Overall {
+    expected: usize,
+    /// Each stage started so far, with how far along it is, summed over its segments.
+    stages: Vec<StageProgress>,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+struct StageProgress {
+    name: &'static str,
+    done: usize,
+    total: usize,
+    /// Steps done by earlier runs of a resumed render, counted in `done` from the start.
+    initial: usize,
+    /// What earlier runs recorded doing, for how fast it's going.
+    earlier: Record,
+    bytes: u64,
+    started: Instant,
+}
+
+impl Overall {
+    fn stage(&mut self, name: &'static str) -> &mut StageProgress {
+        let index = match self.stages.iter().position(|stage| stage.name == name) {
+            Some(index) => index,
+            None => {
+                self.stages.push(StageProgress {
+                    name,
+                    done: 0,
+                    total: 0,
+                    initial: 0,
+                    earlier: Record::default(),
+                    bytes: 0,
+                    started: Instant::now(),
+                });
+                self.stages.len() - 1
+            }
+        };
+        &mut self.stages[index]
+    }
+
+    fn draw(&mut self) {
+        self.drawn = Some(Instant::now());
+        let stages = self.expected.max(self.stages.len());
+        let fraction = self
+            .stages
+            .iter()
+            .map(|stage| stage.done as f64 / stage.total.max(1) as f64)
+            .sum::<f64>()
+            / stages as f64;
+        let Some(current) = self.stages.last() else {
+            return;
+        };
+        // Only this run's work, and what earlier runs recorded doing in the time they took, say
+        // how fast it's going
+        let (worked, seconds) = self
+            .stages
+            .iter()
+            .fold((0.0, 0.0), |(worked, seconds), stage| {
+                let done = stage.done - stage.initial + stage.earlier.done;
+                (
+                    worked + done as f64 / stage.total.max(1) as f64,
+                    seconds + stage.earlier.seconds,
+                )
+            });
+        let rate = worked / stages as f64 / (self.started.elapsed().as_secs_f64() + seconds);
+        draw_bar(
+            &format!("{} ({}/{stages})", current.name, self.stages.len()),
+            fraction,
+            &format!(
+                "{}, ETA {}",
+                current.throughput(),
+                format_eta(1.0 - fraction, rate)
+            ),
+        );
+    }
+}
+
+impl StageProgress {
+    /// Frames a second, and megabytes a second where what's written is counted.
+    fn throughput(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64().max(1e-3);
+        let fps = self.earlier.rate(self.done - self.initial, elapsed);
+        let fps = format!("{fps:.1} frames/s");
+        match self.bytes {
+            0 => fps,
+            bytes => format!("{fps}, {:.1} MB/s", bytes as f64 / 1e6 / elapsed),
+        }
+    }
+}
+
+/// Progress through one stage of a render, like rendering the frames or encoding them. It can be
+/// updated from any thread.
+pub struct Progress {
+    stage: &'static str,
+    style: ProgressStyle,
+    segment: Option<u16>,
+    total: usize,
+    /// Steps done by earlier runs of a resumed render, counted in `done` from the start.
+    initial: usize,
+    /// What earlier runs recorded doing, for how fast it's going.
+    earlier: Record,
+    done: AtomicUsize,
+    bytes: AtomicU64,
+    started: Instant,
+    bar: Option<Mutex<Bar>>,
+    /// When the bar of a resumed stage was last drawn. It's drawn here instead of by kdam, which
+    /// would count the steps earlier runs did as done in no time at all.
+    resumed_bar: Option<Mutex<Instant>>,
+}
+
+impl Progress {
+    pub fn new(stage: &'static str, total: usize) -> Self {
+        Self::start(stage, None, total, 0)
+    }
+
+    /// Progress through one of several segments being encoded at once, each drawn on its own
+    /// line.
+    pub fn segment(stage: &'static str, segment: u16, total: usize) -> Self {
+        Self::start(stage, Some(segment), total, 0)
+    }
+
+    /// Progress through a stage an earlier run of a resumed render already did `done` steps of,
+    /// which are counted from the start.
+    pub fn resumed(stage: &'static str, total: usize, done: usize) -> Self {
+        Self::start(stage, None, total, done)
+    }
+
+    fn start(stage: &'static str, segment: Option<u16>, total: usize, initial: usize) -> Self {
+        let style = style(stage);
+        let earlier = STATE
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map_or_else(Record::default, |state| state.start(stage));
+        let bar = (style == ProgressStyle::Bar && initial == 0).then(|| {
+            Mutex::new(kdam::tqdm!(
+                total = total,
+                desc = stage,
+                unit = " frames",
+                position = segment.unwrap_or(0)
+            ))
+        });
+        let resumed_bar =
+            (style == ProgressStyle::Bar && initial > 0).then(|| Mutex::new(Instant::now()));
+        if style == ProgressStyle::Overall {
+            if let Some(overall) = &mut *OVERALL.lock().unwrap() {
+                let overall = overall.stage(stage);
+                overall.total += total;
+                overall.done += initial;
+                overall.initial += initial;
+                overall.earlier = earlier;
+            }
+        }
+        let progress = Self {
+            stage,
+            style,
+            segment,
+            total,
+            initial,
+            // A segment starts again from nothing, and what's recorded is for every segment at once
+            earlier: segment.map_or(earlier, |_| Record::default()),
+            done: AtomicUsize::new(initial),
+            bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            bar,
+            resumed_bar,
+        };
+        if progress.resumed_bar.is_some() {
+            progress.draw_resumed(initial, 0);
+        }
+        progress
+    }
+
+    /// Draws the bar of a resumed stage, with how fast it's going over every run of it.
+    fn draw_resumed(&self, done: usize, bytes: u64) {
+        let elapsed = self.started.elapsed().as_secs_f64().max(1e-3);
+        let rate = self.earlier.rate(done - self.initial, elapsed);
+        let mut details = format!("{done}/{} frames, {rate:.1} frames/s", self.total);
+        if bytes > 0 {
+            details += &format!(", {:.1} MB/s", bytes as f64 / 1e6 / elapsed);
+        }
+        let eta = format_eta(self.total.saturating_sub(done) as f64, rate);
+        draw_bar(
+            self.stage,
+            done as f64 / self.total.max(1) as f64,
+            &format!("{details}, ETA {eta}"),
+        );
+        if done >= self.total {
+            eprintln!();
+        }
+    }
+
+    /// Records that `steps` more of the stage are done.
+    pub fn update(&self, steps: usize) {
+        self.update_written(steps, 0);
+    }
+
+    /// Records that `steps` more of the stage are done, having written `bytes` for them.
+    pub fn update_written(&self, steps: usize, bytes: usize) {
+        interrupt::wait_while_paused();
+        let done = self.done.fetch_add(steps, Ordering::Relaxed) + steps;
+        let bytes = bytes as u64;
+        let written = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(state) = &mut *STATE.lock().unwrap() {
+            state.update(self.stage, steps);
+        }
+        let elapsed = self.started.elapsed().as_secs_f64().max(1e-3);
+        match self.style {
+            ProgressStyle::Bar if self.resumed_bar.is_some() => {
+                let mut drawn = self.resumed_bar.as_ref().unwrap().lock().unwrap();
+                if drawn.elapsed() >= REDRAW_INTERVAL || done >= self.total {
+                    *drawn = Instant::now();
+                    self.draw_resumed(done, written);
+                }
+            }
+            ProgressStyle::Bar => {
+                let mut bar = self.bar.as_ref().unwrap().lock().unwrap();
+                if written > 0 {
+                    bar.set_postfix(format!("{:.1} MB/s", written as f64 / 1e6 / elapsed));
+                }
+                // A bar that can't be drawn isn't worth stopping the render over
+                let _ = bar.update(steps);
+            }
+            ProgressStyle::Overall => {
+                if let Some(overall) = &mut *OVERALL.lock().unwrap() {
+                    let stage = overall.stage(self.stage);
+                    stage.done += steps;
+                    stage.bytes += bytes;
+                    if overall
+                        .drawn
+                        .is_none_or(|drawn| drawn.elapsed() >= REDRAW_INTERVAL)
+                    {
+                        overall.draw();
+                    }
+                }
+            }
+            ProgressStyle::Json => {
+                let rate = self.earlier.rate(done - self.initial, elapsed);
+                let eta = self.total.saturating_sub(done) as f64 / rate;
+                let mut line = json!({
+                    "stage": self.stage,
+                    "frame": done,
+                    "total": self.total,
+                    "percent": (done as f64 / self.total.max(1) as f64 * 1000.0).round() / 10.0,
+                    "eta": (eta * 10.0).round() / 10.0,
+                    "fps": (rate * 10.0).round() / 10.0,
+                });
+                if written > 0 {
+                    line["mb_per_second"] =
+                        ((written as f64 / 1e6 / elapsed * 10.0).round() / 10.0).into();
+                }
+                if let Some(segment) = self.segment {
+                    line["segment"] = segment.into();
+                }
+                eprintln!("{line}");
+            }
+            ProgressStyle::None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overall_stage_counts_each_write_once() {
+        set_style(Some(ProgressStyle::Overall), None, true);
+        let _render = start_render(1);
+        let progress = Progress::new("write", 2);
+        progress.update_written(1, 100);
+        progress.update_written(1, 50);
+
+        let overall = OVERALL.lock().unwrap();
+        let stage = &overall.as_ref().unwrap().stages[0];
+        assert_eq!(stage.done, 2);
+        assert_eq!(stage.bytes, 150);
+    }
+}
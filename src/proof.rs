@@ -0,0 +1,38 @@
+use std::num::NonZeroUsize;
+
+use crate::Params;
+
+/// Rendering only every Nth frame of the animation for --proof.
+#[derive(Debug, Clone, Copy)]
+pub struct Proof {
+    pub every: NonZeroUsize,
+    /// Frames in the whole animation, of which only some are rendered.
+    pub frames: usize,
+}
+
+impl Proof {
+    pub fn new(every: NonZeroUsize, frames: usize) -> Self {
+        Self { every, frames }
+    }
+
+    /// How many frames are rendered, the first and every Nth after it.
+    pub fn rendered_frames(self) -> usize {
+        self.frames.div_ceil(self.every.get())
+    }
+}
+
+impl Params {
+    /// Which frame of the whole animation rendered frame `frame` is, and how many frames the
+    /// animation has.
+    pub fn animation_frame(&self, frame: usize) -> (usize, usize) {
+        match self.proof {
+            Some(proof) => (frame * proof.every.get(), proof.frames),
+            None => (frame, self.frames),
+        }
+    }
+
+    /// The number of frames in the whole animation, which is more than are rendered with --proof.
+    pub fn animation_frames(&self) -> usize {
+        self.proof.map_or(self.frames, |proof| proof.frames)
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{trace, warn};
+use serde_json::{json, Value};
+
+use crate::{progress, OutputFormat, Params};
+
+/// Kept in a frame or segment directory while it's being rendered, recording what's in it.
+const RESUME_FILE: &str = ".hilbert_animation.json";
+/// Kept next to it, recording how far each stage got and how long it took, for the progress of a
+/// run that resumes the render.
+const PROGRESS_FILE: &str = ".hilbert_animation.progress.json";
+
+impl Params {
+    /// Checks the options --resume can be used with, and that what's already in the directory it
+    /// resumes can be picked up from, before anything is rendered.
+    pub fn check_resume(&self, format: OutputFormat) -> Result<()> {
+        if !self.resume {
+            return Ok(());
+        }
+        let out_dir = match format {
+            OutputFormat::Frames | OutputFormat::Exr => self.frame_dir(),
+            OutputFormat::Webm | OutputFormat::Mp4 if self.segments.get() > 1 => self.segment_dir(),
+            OutputFormat::Webm | OutputFormat::Mp4 => {
+                bail!("--resume needs --segments for videos, which are resumed a segment at a time")
+            }
+            _ => bail!("--resume only works for frame directories and segmented videos"),
+        };
+
+        let path = out_dir.join(RESUME_FILE);
+        if path.exists() {
+            let previous: Value = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice(&json).ok())
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            if previous != self.resume_settings(format) {
+                bail!(
+                    "What's in {} was rendered with different parameters, so it can't be resumed",
+                    out_dir.display()
+                );
+            }
+            return Ok(());
+        }
+        // Finished renders don't keep the record, so what they wrote can't be told apart from
+        // something rendered differently
+        let has_frames = fs::read_dir(&out_dir).is_ok_and(|mut entries| entries.next().is_some());
+        if has_frames {
+            bail!(
+                "{} has no record of an interrupted render to resume, pass --force to render it \
+                 again",
+                out_dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Everything that decides what the frames or segments in a directory look like.
+    pub fn resume_settings(&self, format: OutputFormat) -> Value {
+        json!({
+            "command": self.description(),
+            "encoder": self.encoder_settings(format),
+        })
+    }
+
+    /// Records how the frames or segments in `out_dir` are rendered, before any are written. With
+    /// --resume, the record of the interrupted render is kept, since `check_resume` already
+    /// checked it matches.
+    pub fn start_resumable_dir(&self, out_dir: &Path, format: OutputFormat) -> Result<()> {
+        let path = out_dir.join(RESUME_FILE);
+        if self.resume && path.exists() {
+            progress::record_state(out_dir.join(PROGRESS_FILE), true);
+            return Ok(());
+        }
+        let settings = serde_json::to_string_pretty(&self.resume_settings(format))?;
+        fs::write(&path, settings + "\n")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        progress::record_state(out_dir.join(PROGRESS_FILE), false);
+        Ok(())
+    }
+
+    /// Removes the record of a directory once everything in it is written.
+    pub fn finish_resumable_dir(&self, out_dir: &Path) -> Result<()> {
+        progress::finish_state();
+        let path = out_dir.join(RESUME_FILE);
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))
+    }
+
+    /// Whether a frame or segment was already written by an earlier, interrupted run. They're only
+    /// ever renamed into place once complete, so one that exists is whole.
+    pub fn already_written(&self, path: &Path) -> bool {
+        self.resume && path.exists()
+    }
+}
+
+/// Writes a frame next to where it goes and then moves it there, so an interrupted run never
+/// leaves a truncated frame behind for --resume to skip.
+pub fn write_frame(path: PathBuf, data: &[u8]) -> Result<()> {
+    let mut partial = path.clone().into_os_string();
+    partial.push(".partial");
+    fs::write(&partial, data)?;
+    fs::rename(&partial, &path)?;
+    trace!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Removes a directory the render made once it's done with it, along with the files in it that
+/// `written` says are the render's. Anything else was put there by someone else, so the directory
+/// is left in place with it rather than deleted.
+pub fn remove_own_dir(dir: &Path, written: impl Fn(&str) -> bool) -> Result<()> {
+    let mut others = remove_own_files(dir, written)?;
+    if !others.is_empty() {
+        others.sort();
+        warn!(
+            "Left {} in place, since the render didn't write {}",
+            dir.display(),
+            others.join(", ")
+        );
+        return Ok(());
+    }
+    fs::remove_dir(dir).with_context(|| format!("Failed to remove {}", dir.display()))
+}
+
+/// Removes the files in `dir` that `written` says are a render's, along with its records, and
+/// returns the names of everything else.
+pub fn remove_own_files(dir: &Path, written: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let mut others = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let records = [RESUME_FILE, PROGRESS_FILE].contains(&name.as_str());
+        if entry.file_type()?.is_file() && (records || written(&name)) {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+        } else {
+            others.push(name);
+        }
+    }
+    Ok(others)
+}
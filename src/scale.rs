@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Result};
+use image::{imageops::FilterType, ImageBuffer, Pixel, Primitive, Rgba};
+
+use crate::{gif::Dither, OutputFormat, Params};
+
+/// A frame size for --output-size, like `1080x1080`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Dimensions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid size '{s}', expected WIDTHxHEIGHT like 1080x1080");
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(format!(
+                "invalid size '{s}', sides must be between 1 and 65535"
+            ));
+        }
+        Ok(Self { width, height })
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// How frames are resized to --output-size.
+#[derive(Debug, Clone, Copy)]
+pub enum ScaleFilter {
+    Nearest,
+    Lanczos,
+}
+
+impl ScaleFilter {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Nearest => "nearest",
+            Self::Lanczos => "lanczos",
+        }
+    }
+
+    fn filter_type(self) -> FilterType {
+        match self {
+            Self::Nearest => FilterType::Nearest,
+            Self::Lanczos => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Self::Nearest),
+            "lanczos" => Ok(Self::Lanczos),
+            _ => Err(format!(
+                "unknown scale filter '{s}', expected nearest or lanczos"
+            )),
+        }
+    }
+}
+
+impl Params {
+    /// The width and height of the frames that get encoded.
+    pub fn frame_dimensions(&self) -> (u32, u32) {
+        match self.output_size {
+            Some(size) => (size.width, size.height),
+            None => (self.window().width, self.window().height),
+        }
+    }
+
+    /// Resizes a rendered frame to --output-size, if it was given.
+    pub fn scale_frame<C>(
+        &self,
+        frame: ImageBuffer<Rgba<C>, Vec<C>>,
+    ) -> ImageBuffer<Rgba<C>, Vec<C>>
+    where
+        C: Primitive + 'static,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        match self.output_size {
+            Some(size) if frame.dimensions() != (size.width, size.height) => {
+                image::imageops::resize(
+                    &frame,
+                    size.width,
+                    size.height,
+                    self.scale_filter.filter_type(),
+                )
+            }
+            _ => frame,
+        }
+    }
+
+    /// Checks that every output draws its frames from the resized pixels.
+    pub fn check_output_size(&self, format: OutputFormat) -> Result<()> {
+        if self.output_size.is_none() {
+            return Ok(());
+        }
+        self.draws_from_rendered_frames(format, "--output-size")
+    }
+
+    /// Checks that `format` is encoded from the frames as they're rendered, which `option` changes,
+    /// rather than straight from positions along the curve like strips and gifs with one palette.
+    pub fn draws_from_rendered_frames(&self, format: OutputFormat, option: &str) -> Result<()> {
+        if self.strip_rows.is_some() {
+            bail!("{option} can't be used with --strip-rows");
+        }
+        #[cfg(feature = "gifski")]
+        if self.gifski {
+            return Ok(());
+        }
+        if format == OutputFormat::Gif && (self.palette_cycle || self.dither == Dither::None) {
+            bail!(
+                "gif outputs are drawn straight from the curve's palette, so they need --dither or \
+                 --gifski with {option}"
+            );
+        }
+        Ok(())
+    }
+}
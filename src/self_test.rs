@@ -0,0 +1,211 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use bpaf::*;
+
+use crate::{
+    color_function, config, decode, formats, logging,
+    progress::{self, ProgressStyle},
+    render,
+    video::{self, EncoderSupport},
+    Params, FUNCTIONS,
+};
+
+/// Order of the test animation, the size hardware encoders are probed at.
+const ORDER: &str = "8";
+/// Frames in the test animation.
+const FRAMES: usize = 8;
+
+/// Renders a tiny animation with every function and through every encoder this build and FFMpeg
+/// can use, and checks that each output decodes
+#[derive(Debug, Clone, Bpaf)]
+pub struct SelfTestOptions {
+    /// FFMpeg binary to test webm and mp4 outputs with
+    #[bpaf(long, argument("PATH"), fallback("ffmpeg".into()))]
+    ffmpeg_path: PathBuf,
+    /// Keep the test outputs, and print where they are
+    keep: bool,
+    /// Print more about what's being done, or everything with -vv
+    #[bpaf(short('v'), long("verbose"), req_flag(()), count)]
+    verbose: usize,
+}
+
+/// One render to check: what it's called, the options that pick it, and its output.
+struct Check {
+    name: String,
+    args: Vec<String>,
+    output: String,
+    /// Whether the output decodes back to exactly the frames rendered.
+    lossless: bool,
+    /// Why it can't be checked on this machine, if it can't.
+    skipped: Option<String>,
+}
+
+impl Check {
+    fn new(name: &str, args: &[&str], output: &str, lossless: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            output: output.to_owned(),
+            lossless,
+            skipped: None,
+        }
+    }
+
+    fn skipped(self, why: String) -> Self {
+        Self {
+            skipped: Some(why),
+            ..self
+        }
+    }
+}
+
+impl SelfTestOptions {
+    pub fn run(self) -> Result<()> {
+        // Each check prints its own result, so renders only print what goes wrong
+        logging::set_verbosity(self.verbose == 0, self.verbose);
+        progress::set_style(Some(ProgressStyle::None), None, true);
+        let dir = tempfile::Builder::new()
+            .prefix("hilbert_animation-self-test-")
+            .tempdir()
+            .context("Failed to create test dir")?;
+
+        let checks = self.checks()?;
+        let width = checks
+            .iter()
+            .map(|check| check.name.len() + 2)
+            .max()
+            .unwrap_or(0);
+        let (mut passed, mut failed) = (0, 0);
+        for check in &checks {
+            if let Some(why) = &check.skipped {
+                println!("  {:<width$}skipped, {why}", check.name);
+                continue;
+            }
+            match self.run_check(check, dir.path()) {
+                Ok(()) => {
+                    println!("  {:<width$}ok", check.name);
+                    passed += 1;
+                }
+                Err(err) => {
+                    println!("  {:<width$}FAILED: {err:#}", check.name);
+                    failed += 1;
+                }
+            }
+        }
+
+        if self.keep {
+            println!("Kept the test outputs in {}", dir.keep().display());
+        }
+        if failed > 0 {
+            bail!("{failed} of {} checks failed", passed + failed);
+        }
+        println!("All {passed} checks passed");
+        Ok(())
+    }
+
+    /// Every function through a lossless webp, then every output and encoder with the default
+    /// function.
+    fn checks(&self) -> Result<Vec<Check>> {
+        let mut checks: Vec<Check> = FUNCTIONS
+            .iter()
+            .map(|(function, _)| {
+                let name = format!("function {function}");
+                Check::new(
+                    &name,
+                    &["--function", function],
+                    &format!("{function}.webp"),
+                    true,
+                )
+            })
+            .collect();
+        checks.extend([
+            Check::new("gif", &[], "out.gif", false),
+            #[cfg(feature = "gifski")]
+            Check::new("gif with gifski", &["--gifski"], "gifski.gif", false),
+            Check::new("frames", &[], "frames", true),
+            Check::new("frames in a zip", &[], "frames.zip", true),
+            Check::new("frames in a tar", &[], "frames.tar", true),
+            Check::new("exr frames", &[], "exr.exr", false),
+        ]);
+
+        if formats::ffmpeg_version(&self.ffmpeg_path).is_none() {
+            let why = format!("FFMpeg can't be run from '{}'", self.ffmpeg_path.display());
+            checks.push(Check::new("webm and mp4", &[], "", false).skipped(why));
+            return Ok(checks);
+        }
+        for (encoder, picked_by, support) in video::probe_encoders(&self.ffmpeg_path)? {
+            let mut args = picked_by.split(' ');
+            let container = args.next().unwrap_or_default();
+            let args: Vec<&str> = args.collect();
+            let check = Check::new(
+                &format!("{picked_by} ({encoder})"),
+                &args,
+                &format!("{encoder}.{container}"),
+                false,
+            );
+            match support {
+                EncoderSupport::Available => checks.push(check),
+                // Hardware encoders are in most FFMpeg builds, so there's no point listing them
+                // all on machines without the hardware
+                EncoderSupport::NoHardware => {}
+                EncoderSupport::NotBuilt => checks.push(check.skipped("not in this FFMpeg".into())),
+            }
+        }
+        Ok(checks)
+    }
+
+    /// Renders the check's output and decodes it again, checking it has every frame at the right
+    /// size, and that they're the frames rendered if it's lossless.
+    fn run_check(&self, check: &Check, dir: &Path) -> Result<()> {
+        let output = dir.join(&check.output);
+        let mut args: Vec<OsString> = ["--order", ORDER, "--frames", &FRAMES.to_string()]
+            .iter()
+            .map(Into::into)
+            .collect();
+        args.extend(check.args.iter().map(Into::into));
+        args.extend([
+            "--ffmpeg-path".into(),
+            self.ffmpeg_path.clone().into(),
+            output.clone().into(),
+        ]);
+        let options = config::parse_options(&args)?;
+        let params = Params::new(options.clone())?;
+        render(options).context("Failed to render")?;
+
+        // Frames are written to a directory named after the output
+        let decoded = match output.extension() {
+            Some(ext) if ext == "exr" => output.with_extension(""),
+            _ => output,
+        };
+        let frames: Vec<_> = decode::frames(&decoded, &self.ffmpeg_path)?
+            .collect::<Result<_>>()
+            .context("Failed to decode")?;
+        if frames.len() != FRAMES {
+            bail!("{} frames decoded, expected {FRAMES}", frames.len());
+        }
+        let size = params.image_size;
+        if let Some(frame) = frames
+            .iter()
+            .find(|frame| frame.dimensions() != (size, size))
+        {
+            bail!(
+                "decoded a {}x{} frame, expected {size}x{size}",
+                frame.width(),
+                frame.height()
+            );
+        }
+        if check.lossless {
+            let expected = params.frame_checksums(color_function(&params.function)?);
+            for (i, (frame, expected)) in frames.iter().zip(&expected).enumerate() {
+                if format!("{:08x}", crc32fast::hash(frame.as_raw())) != *expected {
+                    bail!("frame {i} doesn't decode to the frame rendered");
+                }
+            }
+        }
+        Ok(())
+    }
+}
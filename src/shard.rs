@@ -0,0 +1,281 @@
+use std::{collections::BTreeMap, fs, ops::Range, path::PathBuf, process::Command, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use bpaf::*;
+use serde_json::{json, Value};
+
+use crate::{exit, ffmpeg_log, frames::FrameFormat, interrupt, logging, OutputFormat, Params};
+
+/// One of the parts a render is split into for --shard, like `2/8` for the second of eight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Shard {
+    /// How many shards the render is split into.
+    pub fn count(self) -> usize {
+        self.count
+    }
+
+    /// The positions out of `total` that this shard renders. Each gets an even run of them, with
+    /// some taking one more than others when they don't divide evenly.
+    fn range(self, total: usize) -> Range<usize> {
+        (self.index - 1) * total / self.count..self.index * total / self.count
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid shard '{s}', expected INDEX/COUNT like 2/8");
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: usize = index.trim().parse().map_err(|_| invalid())?;
+        let count: usize = count.trim().parse().map_err(|_| invalid())?;
+        if index == 0 || index > count {
+            return Err(format!(
+                "invalid shard '{s}', the index must be between 1 and {count}"
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl Params {
+    /// The positions out of `total` that this machine renders, which is all of them unless
+    /// --shard was given.
+    pub fn shard_range(&self, total: usize) -> Range<usize> {
+        self.shard.map_or(0..total, |shard| shard.range(total))
+    }
+
+    /// Checks that `format` can be split into shards and merged again.
+    pub fn check_shard(&self, format: OutputFormat) -> Result<()> {
+        if self.shard.is_none() {
+            return Ok(());
+        }
+        match format {
+            OutputFormat::Frames | OutputFormat::Exr | OutputFormat::Webm | OutputFormat::Mp4 => {}
+            _ => bail!("--shard only works for frame directories and videos"),
+        }
+        if self.segments.get() > 1 {
+            bail!("--shard can't be used with --segments");
+        }
+        if self.audio.is_some() {
+            bail!("--shard can't be used with --audio, which can't be cut between shards");
+        }
+        if self.max_size.is_some() {
+            bail!("--shard can't be used with --max-size");
+        }
+        Ok(())
+    }
+
+    /// `<output>.shard.json`, next to the shard's frame directory or video.
+    fn shard_record_path(&self, format: OutputFormat) -> PathBuf {
+        let mut path = self.output_path(format).into_os_string();
+        path.push(".shard.json");
+        PathBuf::from(path)
+    }
+
+    /// Records which shard was rendered and how, for `merge` to check shards against each other
+    /// and put them back in order.
+    pub fn write_shard_record(&self, format: OutputFormat) -> Result<()> {
+        let Some(shard) = self.shard else {
+            return Ok(());
+        };
+        let (positions, files, metadata) = match format {
+            OutputFormat::Frames | OutputFormat::Exr => {
+                let frame_format = match format {
+                    OutputFormat::Exr => FrameFormat::Exr,
+                    _ => self.frame_format,
+                };
+                let positions = self.shard_range(self.frames);
+                let files: Vec<_> = positions
+                    .clone()
+                    .map(|i| self.frame_name(i, frame_format))
+                    .collect();
+                (positions, files, None)
+            }
+            _ => {
+                let positions = self.shard_range(self.total_video_frames());
+                let file = self.filename.file_name().unwrap_or_default();
+                let files = vec![file.to_string_lossy().into_owned()];
+                (positions, files, Some(self.ffmetadata(&[])))
+            }
+        };
+        let record = json!({
+            "shard": shard.index,
+            "shards": shard.count,
+            "format": format.name(),
+            "settings": self.resume_settings(format),
+            "positions": [positions.start, positions.end],
+            "files": files,
+            "ffmetadata": metadata,
+        });
+
+        let path = self.shard_record_path(format);
+        let record = serde_json::to_string_pretty(&record)?;
+        fs::write(&path, record + "\n")
+            .with_context(|| format!("Failed to write shard record {}", path.display()))
+    }
+}
+
+/// Joins the shards of a render made with --shard into the whole output
+#[derive(Debug, Clone, Bpaf)]
+pub struct MergeOptions {
+    /// FFMpeg binary to use for joining videos
+    #[bpaf(long, argument("PATH"), fallback("ffmpeg".into()))]
+    ffmpeg_path: PathBuf,
+    /// Overwrite the output if it already exists
+    force: bool,
+    /// Frame directory or video to write
+    #[bpaf(short, long, argument("OUTPUT"))]
+    output: PathBuf,
+    /// Every shard's frame directory or video, in any order
+    #[bpaf(positional("SHARD"), some("at least one shard is needed"))]
+    shards: Vec<PathBuf>,
+}
+
+/// A shard's record, and where its files are.
+struct ShardRecord {
+    dir: PathBuf,
+    record: Value,
+}
+
+impl MergeOptions {
+    pub fn run(self) -> Result<()> {
+        let shards = self.read_records().map_err(exit::usage)?;
+        let first = &shards[0].record;
+        match first["format"].as_str() {
+            Some("webm" | "mp4") => self.join_videos(&shards),
+            _ => self.copy_frames(&shards),
+        }
+    }
+
+    /// Reads every shard's record, checking they're all from the same render and that none are
+    /// missing, and puts them in order. The output is checked first, so nothing is read for a
+    /// merge that couldn't be written.
+    fn read_records(&self) -> Result<Vec<ShardRecord>> {
+        if self.output.exists() && !self.force {
+            bail!(
+                "{} already exists, pass --force to overwrite it",
+                self.output.display()
+            );
+        }
+        let mut shards = BTreeMap::new();
+        for shard in &self.shards {
+            let mut path = shard.clone().into_os_string();
+            path.push(".shard.json");
+            let path = PathBuf::from(path);
+            let record: Value = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice(&json).ok())
+                .with_context(|| format!("Failed to read shard record {}", path.display()))?;
+            let index = record["shard"]
+                .as_u64()
+                .context("Shard record has no index")?;
+            // Frame names are relative to the shard's directory, and videos to the one they're in
+            let dir = match record["format"].as_str() {
+                Some("webm" | "mp4") => shard.parent().unwrap_or(shard).to_path_buf(),
+                _ => shard.clone(),
+            };
+            if shards.insert(index, ShardRecord { dir, record }).is_some() {
+                bail!("Shard {index} was given more than once");
+            }
+        }
+
+        let first = &shards.values().next().unwrap().record;
+        let count = first["shards"]
+            .as_u64()
+            .context("Shard record has no count")?;
+        for (index, shard) in &shards {
+            for key in ["shards", "format", "settings"] {
+                if shard.record[key] != first[key] {
+                    bail!("Shard {index} is from a different render than the others");
+                }
+            }
+        }
+        if let Some(missing) = (1..=count).find(|index| !shards.contains_key(index)) {
+            bail!("Shard {missing} of {count} is missing");
+        }
+        Ok(shards.into_values().collect())
+    }
+
+    fn files(shard: &ShardRecord) -> impl Iterator<Item = PathBuf> + '_ {
+        shard.record["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(|file| shard.dir.join(file))
+    }
+
+    /// Copies every shard's frames into one directory. Their names already count from the start
+    /// of the whole render.
+    fn copy_frames(&self, shards: &[ShardRecord]) -> Result<()> {
+        fs::create_dir_all(&self.output).context("Failed to create output dir")?;
+        for shard in shards {
+            for file in Self::files(shard) {
+                let name = file.file_name().unwrap_or_default();
+                fs::copy(&file, self.output.join(name))
+                    .with_context(|| format!("Failed to copy {}", file.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Joins the shards' videos without re-encoding them, with the tags they were rendered with.
+    fn join_videos(&self, shards: &[ShardRecord]) -> Result<()> {
+        let join_dir = tempfile::Builder::new()
+            .prefix("hilbert_animation-")
+            .tempdir()
+            .context("Failed to create join dir")?;
+        let _cleanup = interrupt::remove_on_interrupt(join_dir.path());
+        let list = join_dir.path().join("shards.txt");
+        let mut list_contents = String::new();
+        for file in shards.iter().flat_map(Self::files) {
+            let file = file
+                .canonicalize()
+                .with_context(|| format!("Failed to find {}", file.display()))?;
+            list_contents.push_str(&format!("file '{}'\n", file.to_string_lossy()));
+        }
+        fs::write(&list, list_contents).context("Failed to write shard list")?;
+        let metadata = join_dir.path().join("metadata.txt");
+        let ffmetadata = shards[0].record["ffmetadata"].as_str().unwrap_or_default();
+        fs::write(&metadata, ffmetadata).context("Failed to write metadata")?;
+
+        let mut join = Command::new(&self.ffmpeg_path);
+        join.arg("-y")
+            .args(logging::ffmpeg_args())
+            .args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(&list)
+            .args(["-f", "ffmetadata", "-i"])
+            .arg(&metadata)
+            .args(["-map_metadata", "1", "-c:v", "copy"])
+            .arg(&self.output);
+        ffmpeg_log::run(join, "FFMpeg failed to join shards")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(total: usize, count: usize) -> Vec<Range<usize>> {
+        (1..=count)
+            .map(|index| Shard { index, count }.range(total))
+            .collect()
+    }
+
+    #[test]
+    fn splits_evenly() {
+        assert_eq!(ranges(8, 4), [0..2, 2..4, 4..6, 6..8]);
+    }
+
+    #[test]
+    fn leaves_no_shard_empty() {
+        assert_eq!(ranges(5, 4), [0..1, 1..2, 2..3, 3..5]);
+        assert_eq!(ranges(3, 3), [0..1, 1..2, 2..3]);
+    }
+}
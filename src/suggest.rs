@@ -0,0 +1,53 @@
+/// Most names suggested for one that wasn't recognized.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Names close enough to `name` to be what was meant, closest first: those it's the start or
+/// part of, like oklab for oklab_hue, then those a few typos away.
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(bool, usize, &str)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let part = name.len() >= 3 && candidate.contains(&name);
+            let distance = edit_distance(&name, candidate);
+            (part || distance <= max_distance).then_some((!part, distance, candidate))
+        })
+        .collect();
+    close.sort();
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, candidate)| candidate)
+        .collect()
+}
+
+/// ` (did you mean 'a' or 'b'?)` for names close to `name`, to go in an error after it, or
+/// nothing if none are.
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    let close: Vec<String> = closest(name, candidates)
+        .into_iter()
+        .map(|candidate| format!("'{candidate}'"))
+        .collect();
+    match &close[..] {
+        [] => String::new(),
+        [only] => format!(" (did you mean {only}?)"),
+        [rest @ .., last] => format!(" (did you mean {} or {last}?)", rest.join(", ")),
+    }
+}
+
+/// Levenshtein distance: the fewest characters inserted, removed, or replaced to turn `a` into
+/// `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a != b);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
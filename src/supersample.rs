@@ -0,0 +1,32 @@
+use anyhow::Result;
+use image::Primitive;
+
+use crate::{color, OutputFormat, Params};
+
+impl Params {
+    /// Checks that every output draws its frames from the supersampled pixels.
+    pub fn check_supersample(&self, format: OutputFormat) -> Result<()> {
+        if self.supersample == 1 {
+            return Ok(());
+        }
+        self.draws_from_rendered_frames(format, "--supersample")
+    }
+
+    /// The mean of the colors at `positions` along the curve, which are all the samples of one
+    /// output pixel.
+    pub fn average_color<C: Primitive>(
+        &self,
+        colors: &[[C; 4]],
+        positions: &[u64],
+        offset: u64,
+    ) -> [C; 4] {
+        let mut sum = [0.0; 4];
+        for h in positions {
+            let color = colors[((h + offset) % self.num_pixels) as usize];
+            for (sum, channel) in sum.iter_mut().zip(color) {
+                *sum += channel.to_f64().unwrap_or_default();
+            }
+        }
+        sum.map(|sum| color::to_channel(sum / positions.len() as f64))
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+
+use crate::Params;
+
+impl Params {
+    /// The placeholders filled in from the parameters, in output names and --post-cmd.
+    pub fn placeholders(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("order", self.order.to_string()),
+            ("size", self.image_size.to_string()),
+            ("function", self.function.clone()),
+            ("frames", self.frames.to_string()),
+            ("framerate", self.framerate.to_string()),
+        ]
+    }
+
+    /// Fills in the placeholders in an output's name, like `render_{function}_{order}.webp`, along
+    /// with `{date}` and `{time}` from `now`. Names without any are left as they are.
+    pub fn fill_filename(&self, filename: &Path, now: SystemTime) -> Result<PathBuf> {
+        let Some(template) = filename.to_str().filter(|name| name.contains('{')) else {
+            return Ok(filename.to_owned());
+        };
+        let (date, time) = date_and_time(now);
+        let mut placeholders = self.placeholders();
+        placeholders.extend([("date", date), ("time", time)]);
+
+        let mut filled = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            // A brace that's never closed is just part of the name
+            let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+                filled.push_str(&rest[start..]);
+                rest = "";
+                break;
+            };
+            let placeholder = &rest[start + 1..end];
+            match placeholders.iter().find(|(name, _)| *name == placeholder) {
+                Some((_, value)) => filled.push_str(value),
+                None => {
+                    let names: Vec<String> = placeholders
+                        .iter()
+                        .map(|(name, _)| format!("{{{name}}}"))
+                        .collect();
+                    bail!(
+                        "unknown placeholder '{{{placeholder}}}' in output '{template}', \
+                         expected {}",
+                        names.join(", ")
+                    );
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        filled.push_str(rest);
+        Ok(filled.into())
+    }
+}
+
+/// The date as YYYY-MM-DD and the time as HH-MM-SS, in UTC. Colons are left out of the time, since
+/// they can't be in file names everywhere.
+fn date_and_time(now: SystemTime) -> (String, String) {
+    let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    let time = format!(
+        "{:02}-{:02}-{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+
+    // Days since the epoch to a date, counting in 400 year eras that start on the 1st of March,
+    // so leap days fall at the end of each year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = match month_from_march {
+        0..=9 => month_from_march + 3,
+        _ => month_from_march - 9,
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (format!("{year:04}-{month:02}-{day:02}"), time)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// The date and time `seconds` after the epoch.
+    fn at(seconds: u64) -> (String, String) {
+        date_and_time(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    fn pair(date: &str, time: &str) -> (String, String) {
+        (date.to_owned(), time.to_owned())
+    }
+
+    #[test]
+    fn starts_at_the_epoch() {
+        assert_eq!(at(0), pair("1970-01-01", "00-00-00"));
+        assert_eq!(at(86_399), pair("1970-01-01", "23-59-59"));
+        assert_eq!(at(86_400), pair("1970-01-02", "00-00-00"));
+    }
+
+    #[test]
+    fn counts_leap_days() {
+        // Every fourth year, including 2000 as a multiple of 400
+        assert_eq!(at(951_782_400), pair("2000-02-29", "00-00-00"));
+        assert_eq!(at(951_868_800), pair("2000-03-01", "00-00-00"));
+        assert_eq!(at(1_709_164_800), pair("2024-02-29", "00-00-00"));
+        assert_eq!(at(1_709_251_199), pair("2024-02-29", "23-59-59"));
+        // But not 2100, a multiple of 100
+        assert_eq!(at(4_107_456_000), pair("2100-02-28", "00-00-00"));
+        assert_eq!(at(4_107_542_400), pair("2100-03-01", "00-00-00"));
+    }
+
+    #[test]
+    fn turns_over_years() {
+        assert_eq!(at(1_704_067_199), pair("2023-12-31", "23-59-59"));
+        assert_eq!(at(1_704_067_200), pair("2024-01-01", "00-00-00"));
+        assert_eq!(at(1_735_603_200), pair("2024-12-31", "00-00-00"));
+    }
+
+    #[test]
+    fn times_before_the_epoch_are_the_epoch() {
+        assert_eq!(
+            date_and_time(UNIX_EPOCH - Duration::from_secs(1)),
+            pair("1970-01-01", "00-00-00")
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::{fmt, str::FromStr};
+
+/// Splits a value like `2.5M` or `1m30s` into its leading number and the unit after it, which is
+/// trimmed and lowercased. None if it doesn't start with a number.
+fn number_and_unit(s: &str) -> Option<(f64, String)> {
+    let unit_start = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(unit_start);
+    let number: f64 = number.parse().ok()?;
+    (number.is_finite() && number >= 0.0).then(|| (number, unit.trim().to_ascii_lowercase()))
+}
+
+/// A file size, in bytes, like `8MB`, `500KiB`, or `1000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| {
+            format!("invalid size '{s}', {why}, expected something like 8MB, 500KiB, or 1000")
+        };
+        let (number, unit) =
+            number_and_unit(s.trim()).ok_or_else(|| invalid("it doesn't start with a number"))?;
+        let multiplier: u64 = match &*unit {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "m" | "mb" => 1_000_000,
+            "g" | "gb" => 1_000_000_000,
+            "t" | "tb" => 1_000_000_000_000,
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            "tib" => 1 << 40,
+            _ => return Err(invalid(&format!("unknown unit '{unit}'"))),
+        };
+        let bytes = number * multiplier as f64;
+        if bytes >= u64::MAX as f64 {
+            return Err(invalid("it's too big"));
+        }
+        Ok(Self(bytes as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            bytes if bytes >= 1_000_000_000 => write!(f, "{:.1}GB", bytes as f64 / 1e9),
+            bytes if bytes >= 1_000_000 => write!(f, "{:.1}MB", bytes as f64 / 1e6),
+            bytes if bytes >= 1_000 => write!(f, "{:.1}KB", bytes as f64 / 1e3),
+            bytes => write!(f, "{bytes}B"),
+        }
+    }
+}
+
+/// A video bitrate, in bits a second, like `2.5M`, `800k`, or `2.5Mbps`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bitrate(pub u64);
+
+impl FromStr for Bitrate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| {
+            format!("invalid bitrate '{s}', {why}, expected something like 2.5M, 800k, or 800000")
+        };
+        let (number, unit) =
+            number_and_unit(s.trim()).ok_or_else(|| invalid("it doesn't start with a number"))?;
+        // Bits a second however it's spelled, so 2M, 2Mb, 2Mbps, and 2Mbit/s are all the same
+        let prefix = ["bit/s", "bps", "b/s", "bit", "b"]
+            .iter()
+            .find_map(|suffix| unit.strip_suffix(suffix))
+            .unwrap_or(&unit);
+        let multiplier = match prefix {
+            "" => 1.0,
+            "k" => 1e3,
+            "m" => 1e6,
+            "g" => 1e9,
+            _ => return Err(invalid(&format!("unknown unit '{unit}'"))),
+        };
+        match number * multiplier {
+            bits if bits >= u64::MAX as f64 => Err(invalid("it's too big")),
+            bits if bits < 1.0 => Err(invalid("it has to be more than 0")),
+            bits => Ok(Self(bits as u64)),
+        }
+    }
+}
+
+impl fmt::Display for Bitrate {
+    /// The way FFMpeg takes it, as short as it can be written exactly.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            bits if bits % 1_000_000 == 0 => write!(f, "{}M", bits / 1_000_000),
+            bits if bits % 1000 == 0 => write!(f, "{}k", bits / 1000),
+            bits => write!(f, "{bits}"),
+        }
+    }
+}
+
+/// A length of time, in seconds, like `1m30s`, `90s`, `1.5m`, `500ms`, or `90`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Duration(pub f64);
+
+impl Duration {
+    /// How many frames play for this long at `framerate`, at least one.
+    pub fn frames(self, framerate: u32) -> usize {
+        ((self.0 * framerate as f64).round() as usize).max(1)
+    }
+}
+
+impl FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| {
+            format!("invalid duration '{s}', {why}, expected something like 1m30s, 90s, or 1.5m")
+        };
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(invalid("it's empty"));
+        }
+        // Each part is a number and its unit, like the 1m and the 30s of 1m30s
+        let mut seconds = 0.0;
+        while !rest.is_empty() {
+            let unit_start = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let unit_end = rest[unit_start..]
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .map_or(rest.len(), |end| unit_start + end);
+            let (number, unit) = number_and_unit(&rest[..unit_end])
+                .ok_or_else(|| invalid("it doesn't start with a number"))?;
+            seconds += number
+                * match &*unit {
+                    // A bare number is seconds, so 1m30 is 1m30s
+                    "" | "s" | "sec" | "secs" => 1.0,
+                    "ms" => 1e-3,
+                    "m" | "min" | "mins" => 60.0,
+                    "h" | "hr" | "hrs" => 3600.0,
+                    _ => return Err(invalid(&format!("unknown unit '{unit}'"))),
+                };
+            rest = rest[unit_end..].trim_start();
+        }
+        match seconds {
+            _ if !seconds.is_finite() => Err(invalid("it's too long")),
+            _ if seconds > 0.0 => Ok(Self(seconds)),
+            _ => Err(invalid("it has to be longer than 0")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_sizes() {
+        for (s, bytes) in [
+            ("1000", 1000),
+            ("8MB", 8_000_000),
+            ("8 mb", 8_000_000),
+            ("500KiB", 512_000),
+            ("1.5k", 1500),
+            ("2GiB", 2 << 30),
+            ("0.5tb", 500_000_000_000),
+            (" 12b ", 12),
+        ] {
+            assert_eq!(s.parse(), Ok(ByteSize(bytes)), "{s}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_byte_sizes() {
+        for (s, why) in [
+            ("", "it doesn't start with a number"),
+            ("MB", "it doesn't start with a number"),
+            ("-5MB", "it doesn't start with a number"),
+            ("1.2.3MB", "it doesn't start with a number"),
+            ("5XB", "unknown unit 'xb'"),
+            ("20000000TB", "it's too big"),
+        ] {
+            let err = s.parse::<ByteSize>().unwrap_err();
+            assert!(err.contains(why), "{s} gave {err}");
+        }
+    }
+
+    #[test]
+    fn shows_byte_sizes() {
+        assert_eq!(ByteSize(999).to_string(), "999B");
+        assert_eq!(ByteSize(1500).to_string(), "1.5KB");
+        assert_eq!(ByteSize(8_000_000).to_string(), "8.0MB");
+        assert_eq!(ByteSize(2_500_000_000).to_string(), "2.5GB");
+    }
+
+    #[test]
+    fn parses_bitrates() {
+        for (s, bits) in [
+            ("800000", 800_000),
+            ("800k", 800_000),
+            ("2.5M", 2_500_000),
+            ("2.5Mbps", 2_500_000),
+            ("2Mbit/s", 2_000_000),
+            ("2mb", 2_000_000),
+            ("1G", 1_000_000_000),
+        ] {
+            assert_eq!(s.parse(), Ok(Bitrate(bits)), "{s}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bitrates() {
+        for (s, why) in [
+            ("fast", "it doesn't start with a number"),
+            ("0", "it has to be more than 0"),
+            ("0.0001k", "it has to be more than 0"),
+            ("2Xbps", "unknown unit 'xbps'"),
+            ("99999999999G", "it's too big"),
+        ] {
+            let err = s.parse::<Bitrate>().unwrap_err();
+            assert!(err.contains(why), "{s} gave {err}");
+        }
+    }
+
+    #[test]
+    fn shows_bitrates_as_ffmpeg_takes_them() {
+        assert_eq!(Bitrate(2_000_000).to_string(), "2M");
+        assert_eq!(Bitrate(2_500_000).to_string(), "2500k");
+        assert_eq!(Bitrate(800_500).to_string(), "800500");
+    }
+
+    #[test]
+    fn parses_durations() {
+        for (s, seconds) in [
+            ("90", 90.0),
+            ("90s", 90.0),
+            ("1m30s", 90.0),
+            ("1m30", 90.0),
+            ("1m 30s", 90.0),
+            ("1.5m", 90.0),
+            ("500ms", 0.5),
+            ("1h2m3s", 3723.0),
+            ("2 mins", 120.0),
+        ] {
+            assert_eq!(s.parse(), Ok(Duration(seconds)), "{s}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        let huge = format!("{}h", "9".repeat(306));
+        for (s, why) in [
+            ("", "it's empty"),
+            ("s", "it doesn't start with a number"),
+            ("0s", "it has to be longer than 0"),
+            ("5 days", "unknown unit 'days'"),
+            ("1m..5s", "it doesn't start with a number"),
+            (&huge, "it's too long"),
+        ] {
+            let err = s.parse::<Duration>().unwrap_err();
+            assert!(err.contains(why), "{s} gave {err}");
+        }
+    }
+
+    #[test]
+    fn counts_frames_in_a_duration() {
+        assert_eq!(Duration(2.0).frames(30), 60);
+        assert_eq!(Duration(0.01).frames(30), 1);
+        assert_eq!(Duration(1.0 / 60.0).frames(30), 1);
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{bail, Result};
+
+use crate::Options;
+
+/// The biggest order with the curve's positions still counted in a u64, and its side in a u32.
+const MAX_ORDER: u8 = 31;
+
+impl Options {
+    /// Checks the numbers the whole render depends on before anything is worked out from them,
+    /// reporting every problem at once rather than just the first.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        // Supersampling draws a curve of a higher order and averages it down
+        let supersample_orders = self.supersample.trailing_zeros() as u8;
+        let max_order = MAX_ORDER - supersample_orders;
+        let valid_order = (1..=max_order).contains(&self.order);
+        if self.order == 0 {
+            problems.push("--order must be at least 1".to_owned());
+        } else if !valid_order && self.supersample > 1 {
+            problems.push(format!(
+                "--order {} is too big, the most is {max_order} with --supersample {}",
+                self.order, self.supersample
+            ));
+        } else if !valid_order {
+            problems.push(format!(
+                "--order {} is too big, the most is {max_order}",
+                self.order
+            ));
+        }
+
+        if self.frames == 0 {
+            problems.push("--frames must be at least 1".to_owned());
+        } else if valid_order {
+            let positions = 1u64 << (2 * (self.order + supersample_orders));
+            if self.frames as u64 > positions {
+                let frames = match self.duration {
+                    Some(_) => format!("--duration's {} frames are", self.frames),
+                    None => format!("--frames {} is", self.frames),
+                };
+                problems.push(format!(
+                    "{frames} more than the {positions} positions along an order {} curve, so \
+                     frames would repeat",
+                    self.order
+                ));
+            }
+        }
+        if let Some(shard) = self.shard {
+            if shard.count() > self.frames {
+                problems.push(format!(
+                    "--shard can't split {} frames into {} shards, some would have none",
+                    self.frames,
+                    shard.count()
+                ));
+            }
+        }
+        if self.framerate == 0 {
+            problems.push("--framerate must be at least 1".to_owned());
+        }
+        if self.text.is_some() && self.font.is_none() {
+            problems.push("--text needs --font to draw it with".to_owned());
+        }
+
+        match &problems[..] {
+            [] => Ok(()),
+            [problem] => bail!("{problem}"),
+            _ => bail!(
+                "There are {} problems with the options:\n  {}",
+                problems.len(),
+                problems.join("\n  ")
+            ),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use bpaf::*;
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::{color_function, config, decode, exit, metadata, progress, Params};
+
+/// Checks that a render's manifest can be reproduced
+#[derive(Debug, Clone, Bpaf)]
+pub struct VerifyOptions {
+    /// Decode the output next to the manifest and check its frames, rather than rendering them
+    /// again. Only lossless outputs at the curve's own size can match.
+    decode: bool,
+    /// FFMpeg binary to use for decoding videos
+    #[bpaf(long, argument("PATH"), fallback("ffmpeg".into()))]
+    ffmpeg_path: PathBuf,
+    /// Manifest written with --manifest, like out.webp.json
+    #[bpaf(positional("MANIFEST"))]
+    manifest: PathBuf,
+}
+
+impl VerifyOptions {
+    pub fn run(self) -> Result<()> {
+        let manifest: Value = fs::read(&self.manifest)
+            .with_context(|| format!("Failed to read {}", self.manifest.display()))
+            .and_then(|json| Ok(serde_json::from_slice(&json)?))
+            .with_context(|| format!("Invalid manifest {}", self.manifest.display()))
+            .map_err(exit::usage)?;
+        let expected: Vec<&str> = manifest["frame_crc32"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        if expected.is_empty() {
+            return Err(exit::usage(anyhow::anyhow!(
+                "{} has no frame checksums to verify against",
+                self.manifest.display()
+            )));
+        }
+        let version = manifest["version"].as_str().unwrap_or("an unknown version");
+        if version != env!("CARGO_PKG_VERSION") {
+            info!(
+                "Rendered with version {version}, verifying with {}",
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+
+        progress::set_style(None, None, false);
+        let actual = match self.decode {
+            true => self.decoded_checksums()?,
+            false => rendered_checksums(&manifest).map_err(exit::usage)?,
+        };
+
+        let mut mismatched = 0;
+        for (i, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+            if expected != actual {
+                println!("Frame {i} doesn't match: expected {expected}, got {actual}");
+                mismatched += 1;
+            }
+        }
+        if expected.len() != actual.len() {
+            bail!(
+                "The manifest has {} frames, but {} were {}",
+                expected.len(),
+                actual.len(),
+                if self.decode { "decoded" } else { "rendered" }
+            );
+        }
+        if mismatched > 0 {
+            bail!("{mismatched} of {} frames don't match", expected.len());
+        }
+        println!("All {} frames match", expected.len());
+        Ok(())
+    }
+
+    /// Checksums of the frames in the output the manifest was written for.
+    fn decoded_checksums(&self) -> Result<Vec<String>> {
+        // The manifest is named after its output, with .json on the end
+        let output = self.manifest.with_extension("");
+        if !output.exists() {
+            bail!(
+                "{} doesn't exist to decode, it's expected next to its manifest",
+                output.display()
+            );
+        }
+        warn!("Only lossless outputs at the curve's own size decode to the frames rendered");
+        decode::frames(&output, &self.ffmpeg_path)?
+            .map(|frame| Ok(format!("{:08x}", crc32fast::hash(frame?.as_raw()))))
+            .collect()
+    }
+}
+
+/// Checksums of the frames rendered again from the command the manifest records.
+fn rendered_checksums(manifest: &Value) -> Result<Vec<String>> {
+    let command = manifest["command"]
+        .as_str()
+        .context("The manifest doesn't record the command it was rendered with")?;
+    // It starts with the program's name and version
+    let args: Vec<OsString> = metadata::split_command(command)
+        .into_iter()
+        .skip(2)
+        .map(Into::into)
+        .collect();
+    let options =
+        config::parse_options(&args).with_context(|| format!("Can't render '{command}' again"))?;
+    let params = Params::new(options)?;
+    let function = color_function(&params.function)?;
+    Ok(params.frame_checksums(function))
+}
@@ -0,0 +1,941 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    mem,
+    num::NonZeroU32,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    str::FromStr,
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use image::{ImageBuffer, Pixel, Rgba, RgbaImage};
+use log::{debug, info, trace};
+use palette::LinSrgba;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    exit::FfmpegError,
+    ffmpeg_log::{self, FfmpegLog},
+    interrupt::{self, Cleanup},
+    logging,
+    outputs::FrameSink,
+    progress::{self, Progress},
+    resume, Channel, OutputFormat, Params,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Container {
+    Webm,
+    Mp4,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum EncodePreset {
+    Fast,
+    Balanced,
+    Best,
+}
+
+impl FromStr for EncodePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "balanced" => Ok(Self::Balanced),
+            "best" => Ok(Self::Best),
+            _ => Err(format!(
+                "unknown encode preset '{s}', expected fast, balanced, or best"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Yuv420p,
+    Yuv444p,
+}
+
+impl PixelFormat {
+    fn name(self, alpha: bool, bit_depth: BitDepth) -> &'static str {
+        use BitDepth::*;
+        match (self, alpha, bit_depth) {
+            (Self::Yuv420p, false, Eight) => "yuv420p",
+            (Self::Yuv420p, true, Eight) => "yuva420p",
+            (Self::Yuv444p, false, Eight) => "yuv444p",
+            (Self::Yuv444p, true, Eight) => "yuva444p",
+            (Self::Yuv420p, false, Ten) => "yuv420p10le",
+            (Self::Yuv420p, true, Ten) => "yuva420p10le",
+            (Self::Yuv444p, false, Ten) => "yuv444p10le",
+            (Self::Yuv444p, true, Ten) => "yuva444p10le",
+            (Self::Yuv420p, false, Sixteen) => "yuv420p16le",
+            (Self::Yuv420p, true, Sixteen) => "yuva420p16le",
+            (Self::Yuv444p, false, Sixteen) => "yuv444p16le",
+            (Self::Yuv444p, true, Sixteen) => "yuva444p16le",
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yuv420p" => Ok(Self::Yuv420p),
+            "yuv444p" => Ok(Self::Yuv444p),
+            _ => Err(format!(
+                "unknown pixel format '{s}', expected yuv420p or yuv444p"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Ten,
+    Sixteen,
+}
+
+impl BitDepth {
+    pub fn bits(self) -> u8 {
+        match self {
+            Self::Eight => 8,
+            Self::Ten => 10,
+            Self::Sixteen => 16,
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(Self::Eight),
+            "10" => Ok(Self::Ten),
+            "16" => Ok(Self::Sixteen),
+            _ => Err(format!(
+                "unsupported bit depth '{s}', expected 8, 10, or 16"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HwAccel {
+    Nvenc,
+    Vaapi,
+    Qsv,
+    VideoToolbox,
+}
+
+impl FromStr for HwAccel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nvenc" => Ok(Self::Nvenc),
+            "vaapi" => Ok(Self::Vaapi),
+            "qsv" => Ok(Self::Qsv),
+            "videotoolbox" => Ok(Self::VideoToolbox),
+            _ => Err(format!(
+                "unknown hardware encoder '{s}', expected nvenc, vaapi, qsv, or videotoolbox"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    LibvpxVp9,
+    Libx264,
+    Av1Nvenc,
+    H264Nvenc,
+    Vp9Vaapi,
+    H264Vaapi,
+    Vp9Qsv,
+    H264Qsv,
+    H264VideoToolbox,
+}
+
+impl Codec {
+    fn select(container: Container, hwaccel: Option<HwAccel>) -> Result<Self> {
+        Ok(match (container, hwaccel) {
+            (Container::Webm, None) => Self::LibvpxVp9,
+            (Container::Mp4, None) => Self::Libx264,
+            // NVENC can't encode VP9, but AV1 is also valid in webm
+            (Container::Webm, Some(HwAccel::Nvenc)) => Self::Av1Nvenc,
+            (Container::Mp4, Some(HwAccel::Nvenc)) => Self::H264Nvenc,
+            (Container::Webm, Some(HwAccel::Vaapi)) => Self::Vp9Vaapi,
+            (Container::Mp4, Some(HwAccel::Vaapi)) => Self::H264Vaapi,
+            (Container::Webm, Some(HwAccel::Qsv)) => Self::Vp9Qsv,
+            (Container::Mp4, Some(HwAccel::Qsv)) => Self::H264Qsv,
+            (Container::Webm, Some(HwAccel::VideoToolbox)) => {
+                bail!("VideoToolbox can't encode webm, use an mp4 output instead")
+            }
+            (Container::Mp4, Some(HwAccel::VideoToolbox)) => Self::H264VideoToolbox,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::LibvpxVp9 => "libvpx-vp9",
+            Self::Libx264 => "libx264",
+            Self::Av1Nvenc => "av1_nvenc",
+            Self::H264Nvenc => "h264_nvenc",
+            Self::Vp9Vaapi => "vp9_vaapi",
+            Self::H264Vaapi => "h264_vaapi",
+            Self::Vp9Qsv => "vp9_qsv",
+            Self::H264Qsv => "h264_qsv",
+            Self::H264VideoToolbox => "h264_videotoolbox",
+        }
+    }
+
+    fn is_software(self) -> bool {
+        matches!(self, Self::LibvpxVp9 | Self::Libx264)
+    }
+
+    /// Arguments that have to come before the input, such as hardware device setup.
+    fn input_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Vp9Vaapi | Self::H264Vaapi => &["-vaapi_device", "/dev/dri/renderD128"],
+            _ => &[],
+        }
+    }
+
+    fn supports_pix_fmt(self, pix_fmt: PixelFormat, alpha: bool, bit_depth: BitDepth) -> bool {
+        // None of these go past 10-bit
+        if bit_depth == BitDepth::Sixteen {
+            return false;
+        }
+        let eight_bit = bit_depth == BitDepth::Eight;
+        let yuv420p = pix_fmt == PixelFormat::Yuv420p;
+        match self {
+            // libvpx only supports alpha with 8-bit 4:2:0
+            Self::LibvpxVp9 => !alpha || (yuv420p && eight_bit),
+            Self::Libx264 => !alpha,
+            Self::H264Nvenc => !alpha && eight_bit,
+            Self::Av1Nvenc | Self::Vp9Vaapi | Self::Vp9Qsv => !alpha && yuv420p,
+            Self::H264Vaapi | Self::H264Qsv | Self::H264VideoToolbox => {
+                !alpha && yuv420p && eight_bit
+            }
+        }
+    }
+
+    /// Pixel format conversion needed to get frames into the encoder, converting RGB to YUV with
+    /// `matrix`.
+    fn format_args(self, pix_fmt: &'static str, bit_depth: BitDepth, matrix: &str) -> [String; 2] {
+        let ten_bit = bit_depth == BitDepth::Ten;
+        let format = match self {
+            // VAAPI only takes frames that have been uploaded to the GPU
+            Self::Vp9Vaapi | Self::H264Vaapi if ten_bit => "p010,hwupload",
+            Self::Vp9Vaapi | Self::H264Vaapi => "nv12,hwupload",
+            Self::Vp9Qsv | Self::H264Qsv if ten_bit => "p010le",
+            Self::Vp9Qsv | Self::H264Qsv => "nv12",
+            _ => pix_fmt,
+        };
+        // FFMpeg converts with BT.601 unless told otherwise, which shifts the colors
+        [
+            "-vf".into(),
+            format!("scale=out_color_matrix={matrix},format={format}"),
+        ]
+    }
+
+    /// Profile needed for high bit depth output, where the encoder doesn't always pick one.
+    fn profile_args(self, pix_fmt: PixelFormat, bit_depth: BitDepth) -> &'static [&'static str] {
+        match (self, pix_fmt, bit_depth) {
+            (Self::LibvpxVp9, PixelFormat::Yuv420p, BitDepth::Ten) => &["-profile:v", "2"],
+            (Self::LibvpxVp9, PixelFormat::Yuv444p, BitDepth::Ten) => &["-profile:v", "3"],
+            (Self::Libx264, PixelFormat::Yuv420p, BitDepth::Ten) => &["-profile:v", "high10"],
+            (Self::Libx264, PixelFormat::Yuv444p, BitDepth::Ten) => &["-profile:v", "high444"],
+            (Self::Vp9Vaapi | Self::Vp9Qsv, _, BitDepth::Ten) => &["-profile:v", "2"],
+            _ => &[],
+        }
+    }
+
+    fn preset_args(self, preset: EncodePreset) -> &'static [&'static str] {
+        use EncodePreset::*;
+        match (self, preset) {
+            (Self::LibvpxVp9, Fast) => &["-deadline", "realtime", "-cpu-used", "8"],
+            (Self::LibvpxVp9, Balanced) => &["-deadline", "good", "-cpu-used", "2"],
+            (Self::LibvpxVp9, Best) => &["-deadline", "best", "-cpu-used", "1"],
+            (Self::Libx264 | Self::Vp9Qsv | Self::H264Qsv, Fast) => &["-preset", "veryfast"],
+            (Self::Libx264 | Self::Vp9Qsv | Self::H264Qsv, Balanced) => &["-preset", "medium"],
+            (Self::Libx264 | Self::Vp9Qsv | Self::H264Qsv, Best) => &["-preset", "veryslow"],
+            (Self::Av1Nvenc | Self::H264Nvenc, Fast) => &["-preset", "p1"],
+            (Self::Av1Nvenc | Self::H264Nvenc, Balanced) => &["-preset", "p4"],
+            (Self::Av1Nvenc | Self::H264Nvenc, Best) => &["-preset", "p7"],
+            (Self::H264VideoToolbox, Fast) => &["-realtime", "1"],
+            (Self::H264VideoToolbox, Balanced | Best) => &[],
+            // VAAPI drivers don't expose a consistent speed setting
+            (Self::Vp9Vaapi | Self::H264Vaapi, _) => &[],
+        }
+    }
+
+    fn quality_args(self, crf: u8) -> Vec<String> {
+        let crf = crf.to_string();
+        let crf = crf.as_str();
+        let args: &[&str] = match self {
+            Self::LibvpxVp9 | Self::Libx264 => &["-crf", crf],
+            Self::Av1Nvenc | Self::H264Nvenc => &["-rc", "vbr", "-cq", crf],
+            Self::Vp9Vaapi | Self::H264Vaapi => &["-rc_mode", "CQP", "-qp", crf],
+            Self::Vp9Qsv | Self::H264Qsv => &["-global_quality", crf],
+            Self::H264VideoToolbox => &["-q:v", crf],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Whether a constant quality setting needs `-b:v 0` to not be capped by the default bitrate.
+    fn quality_needs_zero_bitrate(self) -> bool {
+        matches!(self, Self::LibvpxVp9 | Self::Av1Nvenc | Self::H264Nvenc)
+    }
+
+    /// Whether the encoder can encode a single generated frame, set up the way renders set it up.
+    fn encodes_test_frame(self, ffmpeg_path: &Path) -> bool {
+        let mut command = Command::new(ffmpeg_path);
+        command
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(self.input_args())
+            .args(["-f", "lavfi", "-i", "color=size=256x256:duration=0.1"])
+            .args(["-frames:v", "1"])
+            .args(self.format_args("yuv420p", BitDepth::Eight, "bt709"))
+            .args(["-c:v", self.name(), "-f", "null", "-"]);
+        debug!("Running {command:?}");
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+/// Every encoder video outputs can use, with the output and --hwaccel that pick it.
+const CODECS: [(Codec, &str); 9] = [
+    (Codec::LibvpxVp9, "webm"),
+    (Codec::Libx264, "mp4"),
+    (Codec::Av1Nvenc, "webm --hwaccel nvenc"),
+    (Codec::H264Nvenc, "mp4 --hwaccel nvenc"),
+    (Codec::Vp9Vaapi, "webm --hwaccel vaapi"),
+    (Codec::H264Vaapi, "mp4 --hwaccel vaapi"),
+    (Codec::Vp9Qsv, "webm --hwaccel qsv"),
+    (Codec::H264Qsv, "mp4 --hwaccel qsv"),
+    (Codec::H264VideoToolbox, "mp4 --hwaccel videotoolbox"),
+];
+
+/// Whether FFMpeg can encode with one of the video encoders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderSupport {
+    Available,
+    /// Built into FFMpeg, but failing to encode a test frame, usually for want of the hardware.
+    NoHardware,
+    NotBuilt,
+}
+
+/// Each video encoder, what picks it, and whether the FFMpeg at `ffmpeg_path` can encode with
+/// it. Hardware encoders are only listed as available once they've encoded a test frame, since
+/// FFMpeg builds include them whether or not there's hardware to run them on.
+pub fn probe_encoders(
+    ffmpeg_path: &Path,
+) -> Result<Vec<(&'static str, &'static str, EncoderSupport)>> {
+    let output = Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-encoders"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .context(FfmpegError("Failed to run FFMpeg"))?;
+    // Each encoder is a line of flags followed by its name
+    let built: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| Some(line.split_whitespace().nth(1)?.to_owned()))
+        .collect();
+    Ok(CODECS
+        .into_par_iter()
+        .map(|(codec, picked_by)| {
+            let support = if !built.iter().any(|name| name == codec.name()) {
+                EncoderSupport::NotBuilt
+            } else if codec.is_software() || codec.encodes_test_frame(ffmpeg_path) {
+                EncoderSupport::Available
+            } else {
+                EncoderSupport::NoHardware
+            };
+            (codec.name(), picked_by, support)
+        })
+        .collect())
+}
+
+/// FFMpeg being fed frames through a pipe, with what it prints captured. It's stopped if the
+/// render is interrupted before it's finished.
+pub struct FfmpegPipe {
+    ffmpeg: Child,
+    stdin: BufWriter<ChildStdin>,
+    log: FfmpegLog,
+    _running: Cleanup,
+}
+
+impl FfmpegPipe {
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        debug!("Running {command:?}");
+        let mut ffmpeg = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(FfmpegError("Failed to run FFMpeg"))?;
+        let running = interrupt::kill_on_interrupt(&ffmpeg);
+        let stdin = BufWriter::new(ffmpeg.stdin.take().context("Failed to open FFMpeg stdin")?);
+        let log = FfmpegLog::capture(&mut ffmpeg);
+        Ok(Self {
+            ffmpeg,
+            stdin,
+            log,
+            _running: running,
+        })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        match self.stdin.write_all(bytes) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(self.write_failed(err)),
+        }
+    }
+
+    /// Waits for FFMpeg to finish once every frame is written.
+    pub fn finish(mut self) -> Result<()> {
+        if let Err(err) = self.stdin.flush() {
+            return Err(self.write_failed(err));
+        }
+        let Self {
+            mut ffmpeg,
+            stdin,
+            log,
+            ..
+        } = self;
+        drop(stdin);
+        let status = ffmpeg.wait().context(FfmpegError("FFMpeg failed"))?;
+        log.check(status, "FFMpeg failed")
+    }
+
+    /// Why a frame couldn't be written. FFMpeg stops reading them when it fails, so its own error
+    /// is the one that says why, if it printed one.
+    fn write_failed(&mut self, err: io::Error) -> anyhow::Error {
+        let err = anyhow::Error::new(err).context(FfmpegError("Failed to write frame to FFMpeg"));
+        // Already exited if it failed, and stopped here if it's somehow still running
+        let _ = self.ffmpeg.kill();
+        let Ok(status) = self.ffmpeg.wait() else {
+            return err;
+        };
+        match mem::take(&mut self.log).check(status, "FFMpeg failed") {
+            Ok(()) => err,
+            Err(failed) => failed.context(FfmpegError("Failed to write frame to FFMpeg")),
+        }
+    }
+}
+
+/// Escapes the characters that mean something in an FFMpeg metadata file.
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub struct VideoSink(FfmpegPipe);
+
+impl FrameSink for VideoSink {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()> {
+        self.0.write(frame.as_raw())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.0.finish()
+    }
+}
+
+impl Params {
+    pub fn write_video<F, C>(&self, render_frame: F, container: Container) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let codec = self.video_codec(container)?;
+        let total_frames = self.total_video_frames();
+        if self.segments.get() == 1 {
+            let output = (&*self.filename, self.audio.as_deref());
+            let positions = self.shard_range(total_frames);
+            return self.encode(codec, &render_frame, positions, output, 0);
+        }
+        self.encode_segments(codec, &render_frame, total_frames)
+    }
+
+    /// Encodes frames rendered elsewhere, like when they're shared with other outputs. They only
+    /// come through once, so anything that needs them again can't be used.
+    pub fn video_sink(&self, container: Container) -> Result<VideoSink> {
+        let codec = self.video_codec(container)?;
+        if self.two_pass {
+            bail!("--two-pass can't be combined with other outputs");
+        }
+        if self.segments.get() > 1 {
+            bail!("--segments can't be combined with other outputs");
+        }
+        if self.loops.is_some_and(|loops| loops.get() > 1) {
+            bail!("--loops can't be used for videos combined with other outputs");
+        }
+
+        let mut command = self.ffmpeg_command(codec, u8::FFMPEG_PIX_FMT, self.audio.as_deref());
+        command.arg(&self.filename);
+        Ok(VideoSink(FfmpegPipe::spawn(command)?))
+    }
+
+    /// Picks the codec for `container` and checks it can do what was asked.
+    fn video_codec(&self, container: Container) -> Result<Codec> {
+        let codec = Codec::select(container, self.hwaccel)?;
+        if !codec.supports_pix_fmt(self.pix_fmt, self.alpha, self.bit_depth) {
+            bail!(
+                "{} can't encode {}",
+                codec.name(),
+                self.pix_fmt.name(self.alpha, self.bit_depth)
+            );
+        }
+
+        if self.two_pass && !codec.is_software() {
+            bail!("Two-pass encoding isn't supported with {}", codec.name());
+        }
+        Ok(codec)
+    }
+
+    /// Encodes the frames at `positions` in the looped animation into `output`, along with its
+    /// audio track if it has one.
+    fn encode<F, C>(
+        &self,
+        codec: Codec,
+        render_frame: &F,
+        positions: Range<usize>,
+        (output, audio): (&Path, Option<&Path>),
+        bar_position: u16,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        if !self.two_pass {
+            let mut command = self.ffmpeg_command(codec, C::FFMPEG_PIX_FMT, audio);
+            command.arg(output);
+            return self.pipe_to_ffmpeg(command, render_frame, positions, ("encode", bar_position));
+        }
+
+        // ffmpeg names the actual log file `<prefix>-0.log`
+        let passlog = output.with_extension("passlog");
+        let mut passlog_file = passlog.clone().into_os_string();
+        passlog_file.push("-0.log");
+        let _cleanup = interrupt::remove_on_interrupt(Path::new(&passlog_file));
+
+        let mut first_pass = self.ffmpeg_command(codec, C::FFMPEG_PIX_FMT, None);
+        first_pass
+            .args(["-pass", "1", "-passlogfile"])
+            .arg(&passlog)
+            .args(["-f", "null", "-"]);
+        self.pipe_to_ffmpeg(
+            first_pass,
+            render_frame,
+            positions.clone(),
+            ("first-pass", bar_position),
+        )
+        .context("First pass failed")?;
+
+        let mut second_pass = self.ffmpeg_command(codec, C::FFMPEG_PIX_FMT, audio);
+        second_pass
+            .args(["-pass", "2", "-passlogfile"])
+            .arg(&passlog)
+            .arg(output);
+        self.pipe_to_ffmpeg(
+            second_pass,
+            render_frame,
+            positions,
+            ("second-pass", bar_position),
+        )
+        .context("Second pass failed")?;
+
+        // A leftover log file is harmless, so it isn't worth failing a finished render over
+        let _ = fs::remove_file(passlog_file);
+
+        Ok(())
+    }
+
+    /// The stages encoding a video goes through, each a pass over its frames.
+    fn encode_stages(&self) -> &'static [&'static str] {
+        match self.two_pass {
+            true => &["first-pass", "second-pass"],
+            false => &["encode"],
+        }
+    }
+
+    /// Splits the animation into segments encoded by separate FFMpeg instances at the same time,
+    /// then joins them without re-encoding.
+    fn encode_segments<F, C>(
+        &self,
+        codec: Codec,
+        render_frame: &F,
+        total_frames: usize,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        if self.resume {
+            return self.encode_resumable_segments(codec, render_frame, total_frames);
+        }
+
+        // A fresh one for each run, so renders at the same time never share segments. Failed
+        // renders can't be picked up again from their segments, so it's removed either way.
+        let mut segment_dir = tempfile::Builder::new();
+        segment_dir.prefix("hilbert_animation-");
+        let segment_dir = match &self.frames_dir {
+            Some(frames_dir) => {
+                fs::create_dir_all(frames_dir).and_then(|()| segment_dir.tempdir_in(frames_dir))
+            }
+            None => segment_dir.tempdir(),
+        }
+        .context("Failed to create segment dir")?;
+        let _cleanup = interrupt::remove_on_interrupt(segment_dir.path());
+
+        let encoded =
+            self.encode_in_segments(codec, render_frame, total_frames, segment_dir.path());
+        if self.keep_segments {
+            info!("Kept segments in {}", segment_dir.keep().display());
+        }
+        encoded
+    }
+
+    /// The directory segments are kept in until they're joined, named after the output.
+    pub fn segment_dir(&self) -> PathBuf {
+        self.in_frames_dir(self.filename.with_extension("segments"))
+    }
+
+    /// Encodes segments into a directory next to the output that's kept until they're joined, so
+    /// a render that's interrupted can be run again with --resume to encode only the rest.
+    fn encode_resumable_segments<F, C>(
+        &self,
+        codec: Codec,
+        render_frame: &F,
+        total_frames: usize,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let segment_dir = self.segment_dir();
+        fs::create_dir_all(&segment_dir).context("Failed to create segment dir")?;
+        self.start_resumable_dir(&segment_dir, self.output_format()?)?;
+        self.encode_in_segments(codec, render_frame, total_frames, &segment_dir)?;
+        self.finish_resumable_dir(&segment_dir)?;
+        if self.keep_segments {
+            info!("Kept segments in {}", segment_dir.display());
+        } else {
+            // Two pass logs are named after their segment too
+            resume::remove_own_dir(&segment_dir, |name| {
+                name.starts_with("segment_") || matches!(name, "segments.txt" | "chapters.txt")
+            })?;
+        }
+        Ok(())
+    }
+
+    fn encode_in_segments<F, C>(
+        &self,
+        codec: Codec,
+        render_frame: &F,
+        total_frames: usize,
+        segment_dir: &Path,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let extension = self
+            .filename
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let segment_len = total_frames.div_ceil(self.segments.get());
+        let segments: Vec<_> = (0..total_frames)
+            .step_by(segment_len)
+            .enumerate()
+            .map(|(i, start)| {
+                let path = segment_dir.join(format!("segment_{i:03}.{extension}"));
+                (path, start..(start + segment_len).min(total_frames))
+            })
+            .collect();
+
+        // Segments an earlier run already encoded count towards the overall bar from the start
+        let encoded = segments
+            .iter()
+            .filter(|(path, _)| self.already_written(path));
+        for (_, positions) in encoded {
+            for stage in self.encode_stages() {
+                progress::already_done(stage, positions.len());
+            }
+        }
+
+        thread::scope(|scope| {
+            let encoders: Vec<_> = segments
+                .iter()
+                .enumerate()
+                .filter(|(_, (path, _))| !self.already_written(path))
+                .map(|(i, (path, positions))| {
+                    // Only moved into place once it's complete, for --resume to trust
+                    let partial = segment_dir.join(format!("segment_{i:03}.partial.{extension}"));
+                    scope.spawn(move || {
+                        let output = (&*partial, None);
+                        self.encode(codec, render_frame, positions.clone(), output, i as u16)
+                            .and_then(|()| Ok(fs::rename(&partial, path)?))
+                            .with_context(|| format!("Failed to encode segment {i}"))
+                    })
+                })
+                .collect();
+            encoders
+                .into_iter()
+                .try_for_each(|encoder| encoder.join().unwrap())
+        })?;
+
+        // Paths in the list are relative to the list itself
+        let list = segment_dir.join("segments.txt");
+        let list_contents: String = segments
+            .iter()
+            .map(|(path, _)| format!("file '{}'\n", path.file_name().unwrap().to_string_lossy()))
+            .collect();
+        fs::write(&list, list_contents).context("Failed to write segment list")?;
+
+        // The segments' own tags are lost in the join, so they're given again along with a chapter
+        // for each segment
+        let chapters = segment_dir.join("chapters.txt");
+        fs::write(&chapters, self.ffmetadata(&segments)).context("Failed to write chapters")?;
+
+        // The audio track goes in here, so it doesn't get cut up along with the video
+        let mut join = Command::new(&self.ffmpeg_path);
+        join.arg("-y")
+            .args(logging::ffmpeg_args())
+            .args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(&list)
+            .args(["-f", "ffmetadata", "-i"])
+            .arg(&chapters);
+        if let Some(audio) = &self.audio {
+            self.add_audio(&mut join, audio, 2);
+        }
+        join.args(["-map_metadata", "1", "-map_chapters", "1"])
+            .args(["-c:v", "copy"])
+            .args(self.bitexact_args())
+            .arg(&self.filename);
+        ffmpeg_log::run(join, "FFMpeg failed to join segments")
+    }
+
+    /// Builds an FFMpeg command reading raw frames from stdin, with everything but the output.
+    fn ffmpeg_command(&self, codec: Codec, input_pix_fmt: &str, audio: Option<&Path>) -> Command {
+        let mut command = Command::new(&self.ffmpeg_path);
+        command
+            .arg("-y")
+            .args(logging::ffmpeg_args())
+            .args(codec.input_args())
+            .args(["-f", "rawvideo", "-pix_fmt", input_pix_fmt, "-s"])
+            .arg({
+                let (width, height) = self.frame_dimensions();
+                format!("{width}x{height}")
+            })
+            .arg("-framerate")
+            .arg(self.framerate.to_string())
+            .args(["-i", "-"]);
+        if let Some(audio) = audio {
+            self.add_audio(&mut command, audio, 1);
+        }
+        command
+            .args(codec.format_args(
+                self.pix_fmt.name(self.alpha, self.bit_depth),
+                self.bit_depth,
+                self.color_space.ffmpeg_matrix(),
+            ))
+            .args(self.color_space.ffmpeg_args())
+            .args(["-c:v", codec.name()])
+            .args(codec.profile_args(self.pix_fmt, self.bit_depth));
+        if let Some(preset) = self.encode_preset {
+            command.args(codec.preset_args(preset));
+        }
+        if let Some(crf) = self.crf {
+            command.args(codec.quality_args(crf));
+        }
+        match (&self.bitrate, self.crf) {
+            (Some(bitrate), _) => command.args(["-b:v", &bitrate.to_string()]),
+            (None, Some(_)) if codec.quality_needs_zero_bitrate() => command.args(["-b:v", "0"]),
+            (None, _) => &mut command,
+        };
+        if let Some(threads) = self.threads {
+            command.arg("-threads").arg(threads.to_string());
+        }
+        // Matroska tags for webm, and title and comment atoms for mp4
+        command
+            .arg("-metadata")
+            .arg(format!("title={}", self.title()))
+            .arg("-metadata")
+            .arg(format!("comment={}", self.description()));
+        command.args(self.bitexact_args()).args(&self.ffmpeg_args);
+        command
+    }
+
+    /// Adds `audio` as input number `input`, after the frames, looped and then cut to the length
+    /// of the video. Its codec is left to FFMpeg's default for the container.
+    fn add_audio(&self, command: &mut Command, audio: &Path, input: usize) {
+        command
+            .args(["-stream_loop", "-1", "-i"])
+            .arg(audio)
+            .args(["-map", "0:v", "-map", &format!("{input}:a"), "-t"])
+            .arg(self.video_duration().to_string());
+    }
+
+    /// An FFMpeg metadata file with the title and description, and a chapter for each segment.
+    pub fn ffmetadata(&self, segments: &[(PathBuf, Range<usize>)]) -> String {
+        let mut metadata = format!(
+            ";FFMETADATA1\ntitle={}\ncomment={}\n",
+            escape_ffmetadata(&self.title()),
+            escape_ffmetadata(&self.description())
+        );
+        for (i, (_, positions)) in segments.iter().enumerate() {
+            metadata.push_str(&format!(
+                "\n[CHAPTER]\nTIMEBASE=1/{}\nSTART={}\nEND={}\ntitle=Segment {}\n",
+                self.framerate,
+                positions.start,
+                positions.end,
+                i + 1
+            ));
+        }
+        metadata
+    }
+
+    /// Frames in the video, after repeating them for --loops.
+    pub fn total_video_frames(&self) -> usize {
+        self.frames * self.loops.map_or(1, NonZeroU32::get) as usize
+    }
+
+    /// Length of the video in seconds, after repeating the frames for --loops.
+    pub fn video_duration(&self) -> f64 {
+        self.total_video_frames() as f64 / self.framerate as f64
+    }
+
+    /// Streams the frames at `positions` in the looped animation to FFMpeg.
+    fn pipe_to_ffmpeg<F, C>(
+        &self,
+        command: Command,
+        render_frame: &F,
+        positions: Range<usize>,
+        (stage, bar_position): (&'static str, u16),
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> ImageBuffer<Rgba<C>, Vec<C>> + Sync + Send,
+        C: Channel,
+        Rgba<C>: Pixel<Subpixel = C>,
+    {
+        let mut ffmpeg = FfmpegPipe::spawn(command)?;
+
+        // ffmpeg can't seek back in a pipe, so loops are rendered by streaming the frames again.
+        // Frames are generated in parallel one batch at a time to keep them in order without
+        // holding the whole animation in memory.
+        let batch_size = self.frame_budget(rayon::current_num_threads());
+        let mut batch = Vec::with_capacity(batch_size);
+        let progress = match self.segments.get() {
+            1 => Progress::new(stage, positions.len()),
+            _ => Progress::segment(stage, bar_position, positions.len()),
+        };
+        for start in positions.clone().step_by(batch_size) {
+            let end = (start + batch_size).min(positions.end);
+            (start..end)
+                .into_par_iter()
+                .map(|position| render_frame(position % self.frames))
+                .collect_into_vec(&mut batch);
+            trace!("Sending frames {start}..{end} to FFMpeg");
+            let mut sent = 0;
+            for frame in batch.drain(..) {
+                let bytes = C::to_le_bytes(frame.as_raw());
+                ffmpeg.write(&bytes)?;
+                sent += bytes.len();
+                C::recycle(self, frame.into_raw());
+            }
+            progress.update_written(end - start, sent);
+        }
+        ffmpeg.finish()
+    }
+
+    /// Streams every frame to FFMpeg --strip-rows rows at a time, working out each pixel's place
+    /// on the curve and its color as it goes. Neither whole frames nor the curve are ever held in
+    /// memory, which is far slower but lets orders too big for them be rendered.
+    pub fn write_video_strips<C, F>(&self, color: F, container: Container) -> Result<()>
+    where
+        C: Channel,
+        F: Fn(u64, u64) -> LinSrgba<f64> + Sync,
+    {
+        let Some(strip_rows) = self.strip_rows else {
+            bail!("--strip-rows isn't set");
+        };
+        let codec = self.video_codec(container)?;
+        let mut command = self.ffmpeg_command(codec, C::FFMPEG_PIX_FMT, self.audio.as_deref());
+        command.arg(&self.filename);
+        let mut ffmpeg = FfmpegPipe::spawn(command)?;
+
+        let encoder = self.color_space.encoder();
+        let color = self.curve_colors(&color);
+        let window = self.window();
+        let (width, height) = (window.width as usize, window.height as usize);
+        let strip_len = strip_rows.get().min(height) * width;
+        let mut strip = Vec::with_capacity(strip_len);
+        let positions = self.shard_range(self.total_video_frames());
+        let progress = Progress::new("encode", positions.len());
+        for position in positions {
+            let mut sent = 0;
+            let offset = self.frame_offset(position % self.frames);
+            for start in (0..width * height).step_by(strip_len) {
+                (start..(start + strip_len).min(width * height))
+                    .into_par_iter()
+                    .map(|p| {
+                        let x = window.x + (p % width) as u32;
+                        let y = window.y + (p / width) as u32;
+                        let h = fast_hilbert::xy2h(x, y, self.order);
+                        let i = (h + offset) % self.num_pixels;
+                        C::encode(color(i), &encoder)
+                    })
+                    .collect_into_vec(&mut strip);
+                let bytes = C::to_le_bytes(strip.as_flattened());
+                ffmpeg.write(&bytes)?;
+                sent += bytes.len();
+            }
+            progress.update_written(1, sent);
+        }
+        ffmpeg.finish()
+    }
+
+    /// Checks that nothing else needs whole frames when they're rendered in strips.
+    pub fn check_strips(&self, format: OutputFormat) -> Result<()> {
+        if self.strip_rows.is_none() {
+            return Ok(());
+        }
+        if !matches!(format, OutputFormat::Webm | OutputFormat::Mp4) {
+            bail!("--strip-rows only works for webm and mp4 outputs");
+        }
+        if self.two_pass || self.segments.get() > 1 {
+            bail!("--strip-rows can't be used with --two-pass or --segments");
+        }
+        if self.max_size.is_some() || self.manifest {
+            bail!("--strip-rows can't be used with --max-size or --manifest");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use log::{error, info};
+
+use crate::{config, exit, logging, progress, render, start_thread_pool, Options};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Renders a draft of the options, then renders it again each time the files they're read from
+/// change. A render that fails is reported and waits for the next change, rather than stopping.
+pub fn run(options: Options) -> Result<()> {
+    if options.stdin_json {
+        return Err(exit::usage(anyhow!(
+            "--watch can't read options from stdin, since they're read again on every change"
+        )));
+    }
+    let command_line: Vec<_> = env::args_os().skip(1).collect();
+    let paths = config::paths(&options).map_err(exit::usage)?;
+    if paths.is_empty() {
+        return Err(exit::usage(anyhow!(
+            "--watch needs options to watch, give them with --config or in hilbert_animation.toml"
+        )));
+    }
+
+    // These can only be set once, so changes to them take a restart
+    let first = config::apply(options.clone(), command_line.clone()).map_err(exit::usage)?;
+    logging::set_verbosity(first.quiet, first.verbose);
+    progress::set_style(first.progress, first.progress_stages.clone(), first.quiet);
+    let threads = start_thread_pool(first.threads, first.nice)?;
+
+    let mut modified = modified_times(&paths);
+    let mut applied = Ok(first);
+    loop {
+        let rendered = applied.and_then(|opts| {
+            // Each draft replaces the last
+            render(Options {
+                threads,
+                draft: true,
+                force: true,
+                ..opts
+            })
+        });
+        match rendered {
+            Ok(()) => info!("Rendered, waiting for changes"),
+            Err(err) => error!("{}\nWaiting for changes", exit::message(&err)),
+        }
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let now = modified_times(&paths);
+            if now != modified {
+                modified = now;
+                break;
+            }
+        }
+        info!("Options changed, rendering again");
+        applied = config::apply(options.clone(), command_line.clone());
+    }
+}
+
+/// When each file was last changed, or none if it can't be read, like while it's being saved.
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
@@ -0,0 +1,328 @@
+use std::{
+    ffi::{c_int, CStr},
+    io::Write,
+    mem,
+    ptr::{self, NonNull},
+    slice,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::RgbaImage;
+use libwebp_sys as webp;
+
+use crate::{color, outputs::FrameSink, Params};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BackgroundColor([u8; 4]);
+
+impl BackgroundColor {
+    /// The color packed as libwebp expects it, with alpha in the most significant byte.
+    fn argb(self) -> u32 {
+        let [r, g, b, a] = self.0;
+        u32::from_be_bytes([a, r, g, b])
+    }
+}
+
+impl FromStr for BackgroundColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        color::parse_hex(s).map(Self)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum WebpPreset {
+    Default,
+    Picture,
+    Photo,
+    Drawing,
+    Icon,
+    Text,
+}
+
+impl WebpPreset {
+    fn to_webp(self) -> webp::WebPPreset {
+        match self {
+            Self::Default => webp::WEBP_PRESET_DEFAULT,
+            Self::Picture => webp::WEBP_PRESET_PICTURE,
+            Self::Photo => webp::WEBP_PRESET_PHOTO,
+            Self::Drawing => webp::WEBP_PRESET_DRAWING,
+            Self::Icon => webp::WEBP_PRESET_ICON,
+            Self::Text => webp::WEBP_PRESET_TEXT,
+        }
+    }
+}
+
+impl FromStr for WebpPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "picture" => Ok(Self::Picture),
+            "photo" => Ok(Self::Photo),
+            "drawing" => Ok(Self::Drawing),
+            "icon" => Ok(Self::Icon),
+            "text" => Ok(Self::Text),
+            _ => Err(format!(
+                "unknown webp preset '{s}', expected default, picture, photo, drawing, icon, or text"
+            )),
+        }
+    }
+}
+
+/// An owned libwebp animation encoder.
+struct AnimEncoder(NonNull<webp::WebPAnimEncoder>);
+
+impl AnimEncoder {
+    fn new((width, height): (u32, u32), options: &webp::WebPAnimEncoderOptions) -> Result<Self> {
+        let encoder = unsafe { webp::WebPAnimEncoderNew(width as i32, height as i32, options) };
+        NonNull::new(encoder)
+            .map(Self)
+            .context("Failed to initialize webp encoder")
+    }
+
+    fn add(&mut self, frame: &RgbaImage, timestamp: i32, config: &webp::WebPConfig) -> Result<()> {
+        let mut picture = Picture::new(frame)?;
+        if unsafe { webp::WebPAnimEncoderAdd(self.0.as_ptr(), &mut picture.0, timestamp, config) }
+            == 0
+        {
+            return Err(self.error()).context("Failed to add frame to webp");
+        }
+        Ok(())
+    }
+
+    /// Ends the animation at `timestamp` and returns the muxed file.
+    fn finish(self, timestamp: i32) -> Result<Vec<u8>> {
+        if unsafe {
+            webp::WebPAnimEncoderAdd(self.0.as_ptr(), ptr::null_mut(), timestamp, ptr::null())
+        } == 0
+        {
+            return Err(self.error()).context("Failed to finalize webp");
+        }
+
+        let mut data = unsafe { mem::zeroed::<webp::WebPData>() };
+        if unsafe { webp::WebPAnimEncoderAssemble(self.0.as_ptr(), &mut data) } == 0 {
+            return Err(self.error()).context("Failed to finalize webp");
+        }
+        let bytes = unsafe { slice::from_raw_parts(data.bytes, data.size) }.to_vec();
+        unsafe { webp::WebPDataClear(&mut data) };
+
+        Ok(bytes)
+    }
+
+    fn error(&self) -> anyhow::Error {
+        let message = unsafe { CStr::from_ptr(webp::WebPAnimEncoderGetError(self.0.as_ptr())) };
+        anyhow!("{}", message.to_string_lossy())
+    }
+}
+
+impl Drop for AnimEncoder {
+    fn drop(&mut self) {
+        unsafe { webp::WebPAnimEncoderDelete(self.0.as_ptr()) };
+    }
+}
+
+/// An owned libwebp muxer, for adding metadata chunks to an encoded file.
+struct Mux(NonNull<webp::WebPMux>);
+
+impl Mux {
+    fn new(data: &[u8]) -> Result<Self> {
+        let data = webp::WebPData {
+            bytes: data.as_ptr(),
+            size: data.len(),
+        };
+        // Copies the data, so the mux doesn't borrow it
+        let mux = unsafe { webp::WebPMuxCreate(&data, 1) };
+        NonNull::new(mux)
+            .map(Self)
+            .context("Failed to read webp for muxing")
+    }
+
+    fn set_chunk(&mut self, fourcc: &CStr, data: &[u8]) -> Result<()> {
+        let data = webp::WebPData {
+            bytes: data.as_ptr(),
+            size: data.len(),
+        };
+        let error = unsafe { webp::WebPMuxSetChunk(self.0.as_ptr(), fourcc.as_ptr(), &data, 1) };
+        if error != webp::WEBP_MUX_OK {
+            bail!(
+                "Failed to add {} chunk (error {error})",
+                fourcc.to_string_lossy()
+            );
+        }
+        Ok(())
+    }
+
+    fn assemble(&self) -> Result<Vec<u8>> {
+        let mut data = unsafe { mem::zeroed::<webp::WebPData>() };
+        let error = unsafe { webp::WebPMuxAssemble(self.0.as_ptr(), &mut data) };
+        if error != webp::WEBP_MUX_OK {
+            bail!("Failed to assemble webp (error {error})");
+        }
+        let bytes = unsafe { slice::from_raw_parts(data.bytes, data.size) }.to_vec();
+        unsafe { webp::WebPDataClear(&mut data) };
+        Ok(bytes)
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        unsafe { webp::WebPMuxDelete(self.0.as_ptr()) };
+    }
+}
+
+/// An owned libwebp picture holding a copy of one frame.
+struct Picture(webp::WebPPicture);
+
+impl Picture {
+    fn new(frame: &RgbaImage) -> Result<Self> {
+        let mut picture = unsafe { mem::zeroed::<webp::WebPPicture>() };
+        if unsafe { webp::WebPPictureInit(&mut picture) } == 0 {
+            bail!("Failed to initialize webp picture");
+        }
+        picture.use_argb = 1;
+        picture.width = frame.width() as i32;
+        picture.height = frame.height() as i32;
+
+        // From here on the picture may own memory, so it needs to be dropped
+        let mut picture = Self(picture);
+        let stride = frame.width() as i32 * 4;
+        if unsafe { webp::WebPPictureImportRGBA(&mut picture.0, frame.as_ptr(), stride) } == 0 {
+            bail!("Failed to import frame into webp picture");
+        }
+        Ok(picture)
+    }
+}
+
+impl Drop for Picture {
+    fn drop(&mut self) {
+        unsafe { webp::WebPPictureFree(&mut self.0) };
+    }
+}
+
+pub struct WebpSink<'a> {
+    params: &'a Params,
+    encoder: AnimEncoder,
+    config: webp::WebPConfig,
+    timestamp: f64,
+}
+
+impl FrameSink for WebpSink<'_> {
+    fn add_frame(&mut self, frame: &RgbaImage) -> Result<()> {
+        self.encoder
+            .add(frame, self.timestamp.round() as i32, &self.config)?;
+        self.timestamp += 1000.0 / self.params.framerate as f64;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let webp_data = self.encoder.finish(self.timestamp.round() as i32)?;
+        let mut mux = Mux::new(&webp_data)?;
+        mux.set_chunk(c"ICCP", &self.params.color_space.icc_profile())
+            .context("Failed to add color profile to webp")?;
+        mux.set_chunk(c"XMP ", self.params.xmp().as_bytes())
+            .context("Failed to add metadata to webp")?;
+        let webp_data = mux.assemble()?;
+        let mut file = self.params.create_output()?;
+        file.write_all(&webp_data)
+            .context("Failed to write webp to file")?;
+        Ok(())
+    }
+}
+
+/// Lets libwebp append encoded data to the `WebPMemoryWriter` in the picture's `custom_ptr`.
+extern "C" fn write_to_memory(
+    data: *const u8,
+    data_size: usize,
+    picture: *const webp::WebPPicture,
+) -> c_int {
+    unsafe { webp::WebPMemoryWrite(data, data_size, picture) }
+}
+
+impl Params {
+    pub fn write_webp<R>(&self, render_frame: R) -> Result<()>
+    where
+        R: Fn(usize) -> RgbaImage + Sync,
+    {
+        // Encoding happens here while the next frames are rendered on the rayon pool
+        let mut sink = self.webp_sink()?;
+        self.stream_frames(render_frame, |frame| {
+            sink.add_frame(&frame)?;
+            self.recycle_frame(frame);
+            Ok(())
+        })?;
+        Box::new(sink).finish()
+    }
+
+    pub fn webp_sink(&self) -> Result<WebpSink<'_>> {
+        Ok(WebpSink {
+            params: self,
+            encoder: AnimEncoder::new(self.frame_dimensions(), &self.webp_anim_options()?)?,
+            config: self.webp_config()?,
+            timestamp: 0.0,
+        })
+    }
+
+    /// Encodes a single still image with the same settings as animations.
+    pub fn encode_webp_image(&self, frame: &RgbaImage) -> Result<Vec<u8>> {
+        let config = self.webp_config()?;
+        let mut picture = Picture::new(frame)?;
+        let mut writer = unsafe { mem::zeroed::<webp::WebPMemoryWriter>() };
+        unsafe { webp::WebPMemoryWriterInit(&mut writer) };
+        picture.0.writer = Some(write_to_memory);
+        picture.0.custom_ptr = (&mut writer as *mut webp::WebPMemoryWriter).cast();
+
+        let encoded = unsafe { webp::WebPEncode(&config, &mut picture.0) } != 0;
+        let bytes = unsafe { slice::from_raw_parts(writer.mem, writer.size) }.to_vec();
+        unsafe { webp::WebPMemoryWriterClear(&mut writer) };
+        if !encoded {
+            bail!(
+                "Failed to encode webp image (error {})",
+                picture.0.error_code
+            );
+        }
+
+        let mut mux = Mux::new(&bytes)?;
+        mux.set_chunk(c"ICCP", &self.color_space.icc_profile())
+            .context("Failed to add color profile to webp")?;
+        mux.assemble()
+    }
+
+    fn webp_anim_options(&self) -> Result<webp::WebPAnimEncoderOptions> {
+        let mut options = unsafe { mem::zeroed::<webp::WebPAnimEncoderOptions>() };
+        if unsafe { webp::WebPAnimEncoderOptionsInit(&mut options) } == 0 {
+            bail!("Failed to initialize webp encoder options");
+        }
+        // 0 loops forever
+        options.anim_params.loop_count = self.loops.map_or(0, |loops| loops.get() as i32);
+        if let Some(background) = self.background {
+            options.anim_params.bgcolor = background.argb();
+        }
+        // libwebp already crops each frame to what changed and picks how to blend and dispose it
+        options.minimize_size = 1;
+        options.allow_mixed = self.webp_mixed as i32;
+        Ok(options)
+    }
+
+    fn webp_config(&self) -> Result<webp::WebPConfig> {
+        let quality = self.webp_quality.unwrap_or(75.0);
+        let mut config = unsafe { mem::zeroed::<webp::WebPConfig>() };
+        if unsafe { webp::WebPConfigPreset(&mut config, self.webp_preset.to_webp(), quality) } == 0
+        {
+            bail!("Failed to initialize webp config");
+        }
+        // Without a quality to aim for, stay lossless like libwebp does with no config
+        config.lossless = (self.lossless || self.webp_quality.is_none()) as i32;
+        if let Some(method) = self.webp_method {
+            config.method = method as i32;
+        }
+        config.use_sharp_yuv = self.webp_sharp_yuv as i32;
+        if unsafe { webp::WebPValidateConfig(&config) } == 0 {
+            bail!("Invalid webp encoder settings");
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    ffi::OsString,
+    io::{self, IsTerminal, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    color::ColorSpace, color_function, config, exit, fallback, logging, metadata::shell_quote,
+    progress, render, start_thread_pool, units::Duration, ColorFunction, Options, OutputFormat,
+    FUNCTIONS,
+};
+
+/// Largest order the wizard offers. Bigger ones take long enough that they're best asked for
+/// knowingly, with --order.
+const MAX_ORDER: u8 = 14;
+/// Framerate the wizard renders at, the same as a render's default.
+const FRAMERATE: u32 = 30;
+/// Characters in each function's swatch.
+const SWATCH_WIDTH: u64 = 48;
+
+/// The outputs the wizard offers, with what each is good for.
+const FORMATS: [(OutputFormat, &str); 5] = [
+    (OutputFormat::Webp, "small and lossless, plays in browsers"),
+    (OutputFormat::Gif, "plays everywhere, in 256 colors a frame"),
+    (OutputFormat::Webm, "VP9 video, needs FFMpeg"),
+    (OutputFormat::Mp4, "H.264 video, needs FFMpeg"),
+    (OutputFormat::Frames, "a directory of png images"),
+];
+
+/// Asks for the order, function, length, and output of an animation a question at a time, then
+/// prints the command that renders it and offers to run it.
+pub fn run() -> Result<()> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(exit::usage(anyhow!(
+            "The wizard asks questions, so it needs a terminal to answer them at"
+        )));
+    }
+    eprintln!("Press enter to take the answer in brackets.\n");
+
+    let order = ask(
+        &format!("Order of the curve, 1 to {MAX_ORDER} (8 is 256x256 pixels, 10 is 1024x1024)"),
+        "8",
+        |answer| match answer.parse() {
+            Ok(order @ 1..=MAX_ORDER) => Ok(order),
+            _ => Err(format!(
+                "expected an order from 1 to {MAX_ORDER}, pass --order for bigger ones"
+            )),
+        },
+    )?;
+    let size = 1u64 << (2 * order);
+
+    eprintln!("\nFunctions, which color each point along the curve:");
+    for (n, (name, function)) in FUNCTIONS.iter().enumerate() {
+        eprintln!("  {}. {name:<26}{}", n + 1, swatch(*function, size));
+    }
+    let function = ask(
+        "Function, by name or number",
+        FUNCTIONS[0].0,
+        |answer| match answer.parse::<usize>() {
+            Ok(n @ 1..) if n <= FUNCTIONS.len() => Ok(FUNCTIONS[n - 1].0),
+            _ => color_function(answer)
+                .map(|_| {
+                    FUNCTIONS
+                        .iter()
+                        .find(|(name, _)| *name == answer)
+                        .unwrap()
+                        .0
+                })
+                .map_err(|err| err.to_string()),
+        },
+    )?;
+
+    // Every frame starts at a different point along the curve, so there are only so many
+    let longest = size as f64 / FRAMERATE as f64;
+    let default = format!("{}s", (longest.min(8.0) * 10.0).floor() / 10.0);
+    eprintln!();
+    let duration = ask(
+        &format!("How long it plays, like 8s or 1m30s, at {FRAMERATE} frames a second"),
+        &default,
+        |answer| {
+            let duration: Duration = answer.parse()?;
+            match duration.frames(FRAMERATE) as u64 <= size {
+                true => Ok(answer.to_owned()),
+                false => Err(format!(
+                    "an order {order} curve only has {size} points for frames to start at, so it \
+                     plays for {longest:.1}s at most"
+                )),
+            }
+        },
+    )?;
+
+    eprintln!("\nOutputs:");
+    for (n, (format, description)) in FORMATS.iter().enumerate() {
+        eprintln!("  {}. {:<8}{description}", n + 1, format.name());
+    }
+    let format = ask("Output, by name or number", "webp", |answer| {
+        FORMATS
+            .iter()
+            .enumerate()
+            .find(|(n, (format, _))| answer == (n + 1).to_string() || answer == format.name())
+            .map(|(_, (format, _))| *format)
+            .ok_or_else(|| {
+                format!("unknown output '{answer}', expected webp, gif, webm, mp4, or frames")
+            })
+    })?;
+    let default = match format {
+        OutputFormat::Frames => "hilbert".to_owned(),
+        _ => format!("hilbert.{}", format.name()),
+    };
+    let output = ask("File to write it to", &default, |answer| {
+        Ok(answer.to_owned())
+    })?;
+
+    let mut args: Vec<String> = [
+        "--order",
+        &order.to_string(),
+        "--function",
+        function,
+        "--duration",
+        &duration,
+    ]
+    .map(str::to_owned)
+    .into();
+    // Only needed when the output's extension wouldn't pick the format on its own
+    let picked = match Path::new(&output).extension() {
+        None => Some(OutputFormat::Frames),
+        Some(ext) => ext.to_str().and_then(|ext| ext.parse().ok()),
+    };
+    if picked != Some(format) {
+        args.extend(["--format".to_owned(), format.name().to_owned()]);
+    }
+    args.push(output);
+
+    let command: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+    eprintln!("\nThe command that renders it is:");
+    println!("{} {}", env!("CARGO_PKG_NAME"), command.join(" "));
+    eprintln!();
+    if !fallback::ask("Render it now?")? {
+        return Ok(());
+    }
+
+    let args: Vec<OsString> = args.into_iter().map(OsString::from).collect();
+    let options = config::parse_options(&args).map_err(exit::usage)?;
+    let options = config::apply(options, args).map_err(exit::usage)?;
+    logging::set_verbosity(options.quiet, options.verbose);
+    progress::set_style(
+        options.progress,
+        options.progress_stages.clone(),
+        options.quiet,
+    );
+    let threads = start_thread_pool(options.threads, options.nice)?;
+    render(Options { threads, ..options })
+}
+
+/// Asks `question` until `parse` takes the answer, which is `default` if none is given.
+fn ask<T>(question: &str, default: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T> {
+    loop {
+        eprint!("{question} [{default}]: ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin()
+            .read_line(&mut answer)
+            .context("Failed to read answer")?
+            == 0
+        {
+            bail!("The wizard was ended before it was answered");
+        }
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(err) => eprintln!("  {err}"),
+        }
+    }
+}
+
+/// The colors a function gives along the curve, left to right, in 24-bit terminal colors.
+fn swatch(function: ColorFunction, size: u64) -> String {
+    let encoder = ColorSpace::Srgb.encoder();
+    let mut swatch: String = (0..SWATCH_WIDTH)
+        .map(|n| {
+            let color = encoder.encode_u8(function(n * size / SWATCH_WIDTH, size));
+            format!("\x1b[38;2;{};{};{}m█", color.red, color.green, color.blue)
+        })
+        .collect();
+    swatch.push_str("\x1b[0m");
+    swatch
+}